@@ -0,0 +1,30 @@
+#![feature(test)]
+extern crate drpc;
+extern crate test;
+
+use drpc::frame::{Frame, FrameVersion};
+use futures::executor::block_on;
+
+fn frame(version: FrameVersion) -> Frame {
+    let mut f = Frame::new();
+    f.ok = 1;
+    f.version = version;
+    f.data = bincode::serialize(&1i32).unwrap().into();
+    f
+}
+
+#[bench]
+fn bench_frame_v1(b: &mut test::Bencher) {
+    b.iter(|| {
+        let buf = frame(FrameVersion::V1).finish(1);
+        let _f = block_on(Frame::decode_from(&mut buf.as_slice())).unwrap();
+    });
+}
+
+#[bench]
+fn bench_frame_v2(b: &mut test::Bencher) {
+    b.iter(|| {
+        let buf = frame(FrameVersion::V2).finish(1);
+        let _f = block_on(Frame::decode_from(&mut buf.as_slice())).unwrap();
+    });
+}
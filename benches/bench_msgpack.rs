@@ -0,0 +1,35 @@
+#![feature(test)]
+extern crate serde;
+extern crate test;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct A {
+    pub id: i32,
+    pub name: String,
+}
+
+#[bench]
+fn bench_msgpack_map(b: &mut test::Bencher) {
+    b.iter(|| {
+        let a = A {
+            id: 0,
+            name: "".to_string(),
+        };
+        let a = rmp_serde::to_vec_named(&a).unwrap();
+        let _b: A = rmp_serde::from_slice(&a).unwrap();
+    });
+}
+
+#[bench]
+fn bench_msgpack_array(b: &mut test::Bencher) {
+    b.iter(|| {
+        let a = A {
+            id: 0,
+            name: "".to_string(),
+        };
+        let a = rmp_serde::to_vec(&a).unwrap();
+        let _b: A = rmp_serde::from_slice(&a).unwrap();
+    });
+}
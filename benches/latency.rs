@@ -22,7 +22,7 @@ fn latency(bencher: &mut Bencher) {
             Ok(req + 1)
         }
         let mut s = Server::default();
-        s.register_fn("handle", handle);
+        s.register_fn("handle", handle).unwrap();
         s.serve(("127.0.0.1", 10000)).await;
         println!("rpc served");
     });
@@ -0,0 +1,118 @@
+use std::fmt::Write;
+
+use crate::idl::{Field, Idl, Message, Method, Service, Type};
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "static", "struct", "trait", "true", "try", "type", "unsafe", "use", "where",
+    "while", "yield", "abstract", "become", "do", "final", "gen", "macro", "override", "priv",
+    "typeof", "unsized", "virtual",
+];
+
+/// The Rust code of an IDL: its messages as serde structs and its services
+/// as `#[drpc::service]` traits.
+pub fn generate(idl: &Idl) -> String {
+    let mut out = String::new();
+    out.push_str("// Generated by drpc-build, do not edit.\n");
+    for m in &idl.messages {
+        message(&mut out, m);
+    }
+    for s in &idl.services {
+        service(&mut out, s);
+    }
+    out
+}
+
+/// `name` as a Rust identifier, raw when it is a keyword. `idl::check` refuses the names
+/// that can not be raw(`self`, `crate`..).
+fn ident(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn rust_type(ty: &Type) -> String {
+    match ty {
+        Type::Bool => "bool".to_string(),
+        Type::I8 => "i8".to_string(),
+        Type::I16 => "i16".to_string(),
+        Type::I32 => "i32".to_string(),
+        Type::I64 => "i64".to_string(),
+        Type::U8 => "u8".to_string(),
+        Type::U16 => "u16".to_string(),
+        Type::U32 => "u32".to_string(),
+        Type::U64 => "u64".to_string(),
+        Type::F32 => "f32".to_string(),
+        Type::F64 => "f64".to_string(),
+        Type::String => "::std::string::String".to_string(),
+        Type::Bytes => "::std::vec::Vec<u8>".to_string(),
+        Type::List(v) => format!("::std::vec::Vec<{}>", rust_type(v)),
+        Type::Option(v) => format!("::std::option::Option<{}>", rust_type(v)),
+        Type::Map(k, v) => format!(
+            "::std::collections::HashMap<{}, {}>",
+            rust_type(k),
+            rust_type(v)
+        ),
+        Type::Message(name, _) => ident(name),
+    }
+}
+
+fn doc(out: &mut String, indent: &str, doc: &[String]) {
+    for line in doc {
+        if line.is_empty() {
+            let _ = writeln!(out, "{}///", indent);
+        } else {
+            let _ = writeln!(out, "{}/// {}", indent, line);
+        }
+    }
+}
+
+fn message(out: &mut String, m: &Message) {
+    out.push('\n');
+    doc(out, "", &m.doc);
+    out.push_str(
+        "#[derive(Debug, Clone, Default, PartialEq, ::serde::Serialize, ::serde::Deserialize)]\n",
+    );
+    let _ = writeln!(out, "pub struct {} {{", ident(&m.name));
+    for f in &m.fields {
+        doc(out, "    ", &f.doc);
+        let _ = writeln!(out, "    pub {}: {},", ident(&f.name), rust_type(&f.ty));
+    }
+    out.push_str("}\n");
+}
+
+fn resp_type(m: &Method) -> String {
+    m.resp
+        .as_ref()
+        .map(rust_type)
+        .unwrap_or_else(|| "()".to_string())
+}
+
+fn params(fields: &[Field]) -> String {
+    fields
+        .iter()
+        .map(|f| format!(", {}: {}", ident(&f.name), rust_type(&f.ty)))
+        .collect()
+}
+
+/// The service as a `#[drpc::service]` trait, the macro generates its client and `register_*`.
+fn service(out: &mut String, s: &Service) {
+    out.push('\n');
+    doc(out, "", &s.doc);
+    out.push_str("#[::drpc::service]\n");
+    let _ = writeln!(out, "pub trait {} {{", ident(&s.name));
+    for m in &s.methods {
+        doc(out, "    ", &m.doc);
+        let _ = writeln!(
+            out,
+            "    async fn {}(&self{}) -> ::drpc::Result<{}>;",
+            ident(&m.name),
+            params(&m.params),
+            resp_type(m)
+        );
+    }
+    out.push_str("}\n");
+}
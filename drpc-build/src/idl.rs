@@ -0,0 +1,557 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::Error;
+
+// IDL grammar, `//` comments are skipped, `///` comments document the next item
+//
+// file    = (message | service)*
+// message = "message" Name "{" (field ";")* "}"
+// service = "service" Name "{" method* "}"
+// field   = name ":" type
+// method  = name "(" [field ("," field)*] ")" ["->" type] ";"
+// type    = bool | i8 | i16 | i32 | i64 | u8 | u16 | u32 | u64 | f32 | f64 | string | bytes
+//         | list<type> | option<type> | map<type, type> | Name(a message)
+
+/// A line and column in the IDL source, both from 1.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Idl {
+    pub messages: Vec<Message>,
+    pub services: Vec<Service>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Message {
+    pub name: String,
+    pub doc: Vec<String>,
+    pub fields: Vec<Field>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub doc: Vec<String>,
+    pub ty: Type,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Service {
+    pub name: String,
+    pub doc: Vec<String>,
+    pub methods: Vec<Method>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Method {
+    pub name: String,
+    pub doc: Vec<String>,
+    pub params: Vec<Field>,
+    /// `None` answers `()`
+    pub resp: Option<Type>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Type {
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    String,
+    Bytes,
+    List(Box<Type>),
+    Option(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    /// a message of the IDL
+    Message(String, Pos),
+}
+
+impl Type {
+    fn scalar(name: &str) -> Option<Type> {
+        Some(match name {
+            "bool" => Type::Bool,
+            "i8" => Type::I8,
+            "i16" => Type::I16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "u8" => Type::U8,
+            "u16" => Type::U16,
+            "u32" => Type::U32,
+            "u64" => Type::U64,
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "string" => Type::String,
+            "bytes" => Type::Bytes,
+            _ => return None,
+        })
+    }
+
+    fn is_builtin(name: &str) -> bool {
+        Self::scalar(name).is_some() || matches!(name, "list" | "option" | "map")
+    }
+
+    /// Can be a map key: hashable and comparable.
+    fn is_key(&self) -> bool {
+        !matches!(
+            self,
+            Type::F32
+                | Type::F64
+                | Type::List(_)
+                | Type::Option(_)
+                | Type::Map(_, _)
+                | Type::Message(_, _)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Token {
+    Ident(String),
+    Doc(String),
+    Arrow,
+    Sym(char),
+    Eof,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(v) => format!("`{}`", v),
+            Token::Doc(_) => "a doc comment".to_string(),
+            Token::Arrow => "`->`".to_string(),
+            Token::Sym(c) => format!("`{}`", c),
+            Token::Eof => "end of file".to_string(),
+        }
+    }
+}
+
+fn lex(src: &str) -> Result<Vec<(Token, Pos)>, Error> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = src.chars().collect();
+    let (mut i, mut line, mut col) = (0, 1, 1);
+    while i < chars.len() {
+        let pos = Pos { line, col };
+        let c = chars[i];
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            col = 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            col += 1;
+            continue;
+        }
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            let start = i;
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            let comment: String = chars[start..i].iter().collect();
+            col += i - start;
+            if let Some(doc) = comment.strip_prefix("///") {
+                if !doc.starts_with('/') {
+                    tokens.push((Token::Doc(doc.trim().to_string()), pos));
+                }
+            }
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            col += i - start;
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), pos));
+            continue;
+        }
+        if c == '-' && chars.get(i + 1) == Some(&'>') {
+            i += 2;
+            col += 2;
+            tokens.push((Token::Arrow, pos));
+            continue;
+        }
+        if "{}()<>,;:".contains(c) {
+            i += 1;
+            col += 1;
+            tokens.push((Token::Sym(c), pos));
+            continue;
+        }
+        return Err(Error::at(pos, format!("unexpected character `{}`", c)));
+    }
+    tokens.push((Token::Eof, Pos { line, col }));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    idx: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.idx].0
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens[self.idx].1
+    }
+
+    fn next(&mut self) -> (Token, Pos) {
+        let v = self.tokens[self.idx].clone();
+        if self.idx + 1 < self.tokens.len() {
+            self.idx += 1;
+        }
+        v
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        Error::at(
+            self.pos(),
+            format!("expected {}, found {}", expected, self.peek().describe()),
+        )
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if *self.peek() == Token::Sym(c) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", c)))
+        }
+    }
+
+    fn ident(&mut self, expected: &str) -> Result<(String, Pos), Error> {
+        match self.peek().clone() {
+            Token::Ident(v) => Ok((v, self.next().1)),
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn docs(&mut self) -> Vec<String> {
+        let mut doc = vec![];
+        while let Token::Doc(v) = self.peek().clone() {
+            doc.push(v);
+            self.next();
+        }
+        doc
+    }
+
+    fn file(&mut self) -> Result<Idl, Error> {
+        let mut idl = Idl::default();
+        loop {
+            let doc = self.docs();
+            match self.peek().clone() {
+                Token::Eof => return Ok(idl),
+                Token::Ident(v) if v == "message" => {
+                    self.next();
+                    idl.messages.push(self.message(doc)?);
+                }
+                Token::Ident(v) if v == "service" => {
+                    self.next();
+                    idl.services.push(self.service(doc)?);
+                }
+                _ => return Err(self.unexpected("`message` or `service`")),
+            }
+        }
+    }
+
+    fn message(&mut self, doc: Vec<String>) -> Result<Message, Error> {
+        let (name, pos) = self.ident("a message name")?;
+        self.expect('{')?;
+        let mut fields = vec![];
+        loop {
+            let doc = self.docs();
+            if self.eat('}') {
+                break;
+            }
+            fields.push(self.field(doc, "a field name or `}`")?);
+            self.expect(';')?;
+        }
+        Ok(Message {
+            name,
+            doc,
+            fields,
+            pos,
+        })
+    }
+
+    fn field(&mut self, doc: Vec<String>, expected: &str) -> Result<Field, Error> {
+        let (name, pos) = self.ident(expected)?;
+        self.expect(':')?;
+        let ty = self.ty()?;
+        Ok(Field { name, doc, ty, pos })
+    }
+
+    fn ty(&mut self) -> Result<Type, Error> {
+        let (name, pos) = self.ident("a type")?;
+        if let Some(v) = Type::scalar(&name) {
+            return Ok(v);
+        }
+        let ty = match name.as_str() {
+            "list" | "option" => {
+                self.expect('<')?;
+                let inner = Box::new(self.ty()?);
+                self.expect('>')?;
+                if name == "list" {
+                    Type::List(inner)
+                } else {
+                    Type::Option(inner)
+                }
+            }
+            "map" => {
+                self.expect('<')?;
+                let key_pos = self.pos();
+                let key = self.ty()?;
+                if !key.is_key() {
+                    return Err(Error::at(
+                        key_pos,
+                        "map key must be bool, an integer, string or bytes",
+                    ));
+                }
+                self.expect(',')?;
+                let value = self.ty()?;
+                self.expect('>')?;
+                Type::Map(Box::new(key), Box::new(value))
+            }
+            _ => Type::Message(name, pos),
+        };
+        Ok(ty)
+    }
+
+    fn service(&mut self, doc: Vec<String>) -> Result<Service, Error> {
+        let (name, pos) = self.ident("a service name")?;
+        self.expect('{')?;
+        let mut methods = vec![];
+        loop {
+            let doc = self.docs();
+            if self.eat('}') {
+                break;
+            }
+            methods.push(self.method(doc)?);
+        }
+        Ok(Service {
+            name,
+            doc,
+            methods,
+            pos,
+        })
+    }
+
+    fn method(&mut self, doc: Vec<String>) -> Result<Method, Error> {
+        let (name, pos) = self.ident("a method name or `}`")?;
+        self.expect('(')?;
+        let mut params = vec![];
+        if !self.eat(')') {
+            loop {
+                params.push(self.field(vec![], "a parameter name")?);
+                if self.eat(')') {
+                    break;
+                }
+                self.expect(',')?;
+            }
+        }
+        let resp = if *self.peek() == Token::Arrow {
+            self.next();
+            Some(self.ty()?)
+        } else {
+            None
+        };
+        self.expect(';')?;
+        Ok(Method {
+            name,
+            doc,
+            params,
+            resp,
+            pos,
+        })
+    }
+}
+
+/// Parse and check an IDL source.
+pub fn parse(src: &str) -> Result<Idl, Error> {
+    let mut parser = Parser {
+        tokens: lex(src)?,
+        idx: 0,
+    };
+    let idl = parser.file()?;
+    check(&idl)?;
+    Ok(idl)
+}
+
+fn check(idl: &Idl) -> Result<(), Error> {
+    let mut names = HashSet::new();
+    let items = idl
+        .messages
+        .iter()
+        .map(|v| (&v.name, v.pos))
+        .chain(idl.services.iter().map(|v| (&v.name, v.pos)));
+    for (name, pos) in items {
+        check_name(name, pos)?;
+        if Type::is_builtin(name) {
+            return Err(Error::at(pos, format!("`{}` is a builtin type", name)));
+        }
+        if !names.insert(name.as_str()) {
+            return Err(Error::at(
+                pos,
+                format!("`{}` is defined more than once", name),
+            ));
+        }
+    }
+    let messages: HashSet<&str> = idl.messages.iter().map(|v| v.name.as_str()).collect();
+    let check_fields = |fields: &[Field], what: &str| -> Result<(), Error> {
+        let mut names = HashSet::new();
+        for f in fields {
+            check_name(&f.name, f.pos)?;
+            if !names.insert(f.name.as_str()) {
+                return Err(Error::at(
+                    f.pos,
+                    format!("{} `{}` is defined more than once", what, f.name),
+                ));
+            }
+            check_type(&f.ty, &messages)?;
+        }
+        Ok(())
+    };
+    for m in &idl.messages {
+        check_fields(&m.fields, "field")?;
+    }
+    check_cycles(idl)?;
+    for s in &idl.services {
+        let mut names = HashSet::new();
+        for m in &s.methods {
+            check_name(&m.name, m.pos)?;
+            if m.name == "new" {
+                return Err(Error::at(
+                    m.pos,
+                    format!(
+                        "method `{}.new` clashes with `{}Client::new`",
+                        s.name, s.name
+                    ),
+                ));
+            }
+            if !names.insert(m.name.as_str()) {
+                return Err(Error::at(
+                    m.pos,
+                    format!("method `{}.{}` is defined more than once", s.name, m.name),
+                ));
+            }
+            check_fields(&m.params, "parameter")?;
+            if let Some(ty) = &m.resp {
+                check_type(ty, &messages)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A name Rust takes, if need be as a raw identifier(`r#type`).
+fn check_name(name: &str, pos: Pos) -> Result<(), Error> {
+    if matches!(name, "self" | "Self" | "super" | "crate") {
+        return Err(Error::at(pos, format!("`{}` can not be a name", name)));
+    }
+    Ok(())
+}
+
+/// The message a field holds inline, through options. A list or map holds it behind a pointer.
+fn inline(ty: &Type) -> Option<&str> {
+    match ty {
+        Type::Option(v) => inline(v),
+        Type::Message(name, _) => Some(name),
+        _ => None,
+    }
+}
+
+/// Messages holding each other inline would have an infinite size,
+/// only a list or map can close a cycle of them.
+fn check_cycles(idl: &Idl) -> Result<(), Error> {
+    let messages: HashMap<&str, &Message> =
+        idl.messages.iter().map(|m| (m.name.as_str(), m)).collect();
+    let mut done = HashSet::new();
+    for m in &idl.messages {
+        visit(m, &messages, &mut vec![], &mut done)?;
+    }
+    Ok(())
+}
+
+/// Depth first from `m`, `path` is the messages holding it.
+fn visit<'a>(
+    m: &'a Message,
+    messages: &HashMap<&str, &'a Message>,
+    path: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+) -> Result<(), Error> {
+    if done.contains(m.name.as_str()) {
+        return Ok(());
+    }
+    path.push(&m.name);
+    for f in &m.fields {
+        let name = match inline(&f.ty) {
+            Some(name) => name,
+            None => continue,
+        };
+        if let Some(at) = path.iter().position(|v| *v == name) {
+            let msg = if at + 1 == path.len() {
+                format!(
+                    "message `{}` can only contain itself in a list or map",
+                    name
+                )
+            } else {
+                let cycle: Vec<String> = path[at..]
+                    .iter()
+                    .chain([&name])
+                    .map(|v| format!("`{}`", v))
+                    .collect();
+                format!(
+                    "messages {} can only contain each other in a list or map",
+                    cycle.join(" -> ")
+                )
+            };
+            return Err(Error::at(f.pos, msg));
+        }
+        if let Some(next) = messages.get(name) {
+            visit(next, messages, path, done)?;
+        }
+    }
+    path.pop();
+    done.insert(&m.name);
+    Ok(())
+}
+
+fn check_type(ty: &Type, messages: &HashSet<&str>) -> Result<(), Error> {
+    match ty {
+        Type::List(v) | Type::Option(v) => check_type(v, messages),
+        Type::Map(k, v) => {
+            check_type(k, messages)?;
+            check_type(v, messages)
+        }
+        Type::Message(name, pos) if !messages.contains(name.as_str()) => {
+            Err(Error::at(*pos, format!("unknown type `{}`", name)))
+        }
+        _ => Ok(()),
+    }
+}
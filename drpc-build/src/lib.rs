@@ -0,0 +1,135 @@
+//! Generate drpc messages, clients and server registration from `.drpc` IDL files.
+//!
+//! In `build.rs`:
+//! ```no_run
+//! drpc_build::compile(&["idl/greeter.drpc"]).unwrap();
+//! ```
+//! then include the generated `greeter.rs`:
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/greeter.rs"));
+//! ```
+//! The generated code depends on the `serde` crate and the `drpc` crate with its `macros`
+//! feature, a service is a `#[drpc::service]` trait.
+//!
+//! A `.drpc` file:
+//! ```text
+//! /// a user
+//! message User {
+//!     name: string;
+//!     age: i32;
+//!     tags: list<string>;
+//!     friends: map<string, User>;
+//! }
+//!
+//! service Greeter {
+//!     hello(user: User) -> string;
+//!     add(a: i32, b: i32) -> i32;
+//!     reset();
+//! }
+//! ```
+pub mod gen;
+pub mod idl;
+
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+pub use gen::generate;
+pub use idl::parse;
+
+/// An error of a `.drpc` file, displayed as `file:line:col: message` like rustc's.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Error {
+    pub file: Option<PathBuf>,
+    pub pos: Option<idl::Pos>,
+    pub msg: String,
+}
+
+impl Error {
+    pub fn new<M: Into<String>>(msg: M) -> Self {
+        Self {
+            file: None,
+            pos: None,
+            msg: msg.into(),
+        }
+    }
+
+    pub fn at<M: Into<String>>(pos: idl::Pos, msg: M) -> Self {
+        Self {
+            file: None,
+            pos: Some(pos),
+            msg: msg.into(),
+        }
+    }
+
+    fn file(mut self, file: &Path) -> Self {
+        self.file = Some(file.to_path_buf());
+        self
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if let Some(pos) = &self.pos {
+            write!(f, "{}:{}:", pos.line, pos.col)?;
+        }
+        if self.file.is_some() || self.pos.is_some() {
+            f.write_str(" ")?;
+        }
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compile `.drpc` files, see `compile`.
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    out_dir: Option<PathBuf>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write the generated files in `out_dir`, instead of `$OUT_DIR`.
+    pub fn out_dir<P: Into<PathBuf>>(mut self, out_dir: P) -> Self {
+        self.out_dir = Some(out_dir.into());
+        self
+    }
+
+    /// Generate `<out_dir>/<file stem>.rs` for each of `files`,
+    /// asking cargo to rerun the build script when one changes.
+    pub fn compile<P: AsRef<Path>>(&self, files: &[P]) -> Result<(), Error> {
+        let out_dir = match &self.out_dir {
+            Some(v) => v.clone(),
+            None => std::env::var_os("OUT_DIR")
+                .map(PathBuf::from)
+                .ok_or_else(|| {
+                    Error::new("OUT_DIR is not set, call from build.rs or set out_dir")
+                })?,
+        };
+        for file in files {
+            let file = file.as_ref();
+            println!("cargo:rerun-if-changed={}", file.display());
+            let src = std::fs::read_to_string(file)
+                .map_err(|e| Error::new(format!("read failed: {}", e)).file(file))?;
+            let idl = parse(&src).map_err(|e| e.file(file))?;
+            let stem = file
+                .file_stem()
+                .ok_or_else(|| Error::new("no file name").file(file))?;
+            let out = out_dir.join(format!("{}.rs", stem.to_string_lossy()));
+            std::fs::write(&out, generate(&idl))
+                .map_err(|e| Error::new(format!("write {} failed: {}", out.display(), e)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Generate `$OUT_DIR/<file stem>.rs` for each of `files`.
+pub fn compile<P: AsRef<Path>>(files: &[P]) -> Result<(), Error> {
+    Builder::new().compile(files)
+}
@@ -0,0 +1,157 @@
+#[cfg(test)]
+mod test {
+    use drpc_build::idl::{Pos, Type};
+    use drpc_build::{generate, parse, Builder};
+
+    const GREETER: &str = r#"
+// not a doc
+/// a user
+message User {
+    name: string;
+    /// the tags
+    tags: list<string>;
+    friends: map<string, User>;
+    nick: option<string>;
+    type: bytes;
+}
+
+service Greeter {
+    /// greet
+    hello(user: User) -> string;
+    add(a: i32, b: i32) -> i32;
+    ping();
+}
+"#;
+
+    fn err(src: &str) -> String {
+        parse(src).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        let idl = parse(GREETER).unwrap();
+        assert_eq!(idl.messages.len(), 1);
+        let user = &idl.messages[0];
+        assert_eq!(user.doc, vec!["a user"]);
+        assert_eq!(user.pos, Pos { line: 4, col: 9 });
+        assert_eq!(user.fields[1].doc, vec!["the tags"]);
+        assert_eq!(user.fields[1].ty, Type::List(Box::new(Type::String)));
+        let greeter = &idl.services[0];
+        assert_eq!(greeter.methods.len(), 3);
+        assert_eq!(greeter.methods[1].params.len(), 2);
+        assert_eq!(greeter.methods[1].resp, Some(Type::I32));
+        assert_eq!(greeter.methods[2].resp, None);
+    }
+
+    #[test]
+    fn test_generate() {
+        let code = generate(&parse(GREETER).unwrap());
+        assert!(code.contains("pub struct User {"));
+        assert!(code
+            .contains("    /// the tags\n    pub tags: ::std::vec::Vec<::std::string::String>,"));
+        assert!(code.contains("pub r#type: ::std::vec::Vec<u8>,"));
+        assert!(code.contains("#[::drpc::service]\npub trait Greeter {"));
+        assert!(code.contains("    /// greet\n    async fn hello(&self, user: User)"));
+        assert!(code.contains("    async fn add(&self, a: i32, b: i32) -> ::drpc::Result<i32>;"));
+        assert!(code.contains("    async fn ping(&self) -> ::drpc::Result<()>;"));
+        // keywords are raw wherever they name a type
+        let code = generate(
+            &parse("message type { a: i32; }\nservice loop { f(x: type) -> type; }").unwrap(),
+        );
+        assert!(code.contains("pub struct r#type {"));
+        assert!(code.contains("pub trait r#loop {"));
+        assert!(code.contains("async fn f(&self, x: r#type) -> ::drpc::Result<r#type>;"));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(
+            err("message User {\n    name: strin;\n}"),
+            "2:11: unknown type `strin`"
+        );
+        assert_eq!(
+            err("message User {\n    name: string\n}"),
+            "3:1: expected `;`, found `}`"
+        );
+        assert_eq!(
+            err("servce A {}"),
+            "1:1: expected `message` or `service`, found `servce`"
+        );
+        assert_eq!(
+            err("service A {\n  a() -> i32;\n  a(x: i32);\n}"),
+            "3:3: method `A.a` is defined more than once"
+        );
+        assert_eq!(
+            err("message A { x: i32; x: i64; }"),
+            "1:21: field `x` is defined more than once"
+        );
+        assert_eq!(
+            err("message A {}\nservice A {}"),
+            "2:9: `A` is defined more than once"
+        );
+        assert_eq!(err("message string {}"), "1:9: `string` is a builtin type");
+        assert_eq!(
+            err("message A { m: map<f64, i32>; }"),
+            "1:20: map key must be bool, an integer, string or bytes"
+        );
+        assert_eq!(
+            err("message A { a: option<A>; }"),
+            "1:13: message `A` can only contain itself in a list or map"
+        );
+        assert_eq!(
+            err("message A { a: option<option<A>>; }"),
+            "1:13: message `A` can only contain itself in a list or map"
+        );
+        assert_eq!(
+            err("message A { b: B; }\nmessage B { c: option<C>; }\nmessage C { a: A; }"),
+            "3:13: messages `A` -> `B` -> `C` -> `A` can only contain each other in a list or map"
+        );
+        // a list closes the cycle
+        assert!(parse("message A { b: list<B>; }\nmessage B { a: A; }").is_ok());
+        assert_eq!(err("message Self {}"), "1:9: `Self` can not be a name");
+        assert_eq!(
+            err("message A { self: i32; }"),
+            "1:13: `self` can not be a name"
+        );
+        assert_eq!(
+            err("service A { crate(); }"),
+            "1:13: `crate` can not be a name"
+        );
+        assert_eq!(
+            err("service A { new(); }"),
+            "1:13: method `A.new` clashes with `AClient::new`"
+        );
+        assert_eq!(
+            err("message A { a: i32 = 1; }"),
+            "1:20: unexpected character `=`"
+        );
+        assert_eq!(
+            err("service A { a(x: i32 -> i32; }"),
+            "1:22: expected `,`, found `->`"
+        );
+        assert_eq!(
+            err("message A {"),
+            "1:12: expected a field name or `}`, found end of file"
+        );
+    }
+
+    #[test]
+    fn test_compile() {
+        let dir = std::env::temp_dir().join(format!("drpc-build-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let idl = dir.join("greeter.drpc");
+        std::fs::write(&idl, GREETER).unwrap();
+        Builder::new().out_dir(&dir).compile(&[&idl]).unwrap();
+        let code = std::fs::read_to_string(dir.join("greeter.rs")).unwrap();
+        assert!(code.starts_with("// Generated by drpc-build"));
+
+        let bad = dir.join("bad.drpc");
+        std::fs::write(&bad, "message A {\n  a: B;\n}").unwrap();
+        let e = Builder::new().out_dir(&dir).compile(&[&bad]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!("{}:2:6: unknown type `B`", bad.display())
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,247 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+use syn::{
+    parse_macro_input, parse_quote, Error, FnArg, GenericArgument, Ident, ItemTrait, Pat,
+    PathArguments, ReturnType, TraitItem, TraitItemFn, Type,
+};
+
+/// Define a service as a trait of `async fn(&self, ..) -> drpc::Result<T>` methods.
+///
+/// For `trait Greeter` it generates:
+/// * the trait, its methods returning `impl Future + Send`, an impl may still write `async fn`
+/// * `GreeterClient<T: drpc::client::Call>`, a typed client over a `Client` or a `BalanceService`
+/// * `register_greeter(&mut Server<C>, impl Greeter) -> drpc::Result<()>`, registering every
+///   method on the server
+///
+/// The method `hello` is called `Greeter.hello`, its arguments are packed as a tuple
+/// when there are several, `()` when there is none.
+#[proc_macro_attribute]
+pub fn service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(Span::call_site(), "#[drpc::service] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(item as ItemTrait);
+    match expand(item) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct Method {
+    ident: Ident,
+    name: String,
+    args: Vec<(Ident, Type)>,
+    resp: Type,
+}
+
+impl Method {
+    /// The request type, the tuple of the argument types.
+    fn req(&self) -> TokenStream2 {
+        match self.args.as_slice() {
+            [(_, ty)] => quote!(#ty),
+            args => {
+                let tys = args.iter().map(|(_, ty)| ty);
+                quote!((#(#tys,)*))
+            }
+        }
+    }
+
+    /// The request value or pattern, the tuple of the argument names.
+    fn req_value(&self) -> TokenStream2 {
+        match self.args.as_slice() {
+            [(ident, _)] => quote!(#ident),
+            args => {
+                let idents = args.iter().map(|(ident, _)| ident);
+                quote!((#(#idents,)*))
+            }
+        }
+    }
+}
+
+fn expand(mut item: ItemTrait) -> syn::Result<TokenStream2> {
+    if !item.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &item.generics,
+            "#[drpc::service] trait can not be generic",
+        ));
+    }
+    let trait_ident = item.ident.clone();
+    let mut methods = vec![];
+    for v in item.items.iter_mut() {
+        if let TraitItem::Fn(f) = v {
+            methods.push(method(&trait_ident, f)?);
+            desugar(f);
+        }
+    }
+    let vis = &item.vis;
+    let client_ident = format_ident!("{}Client", trait_ident);
+    let register_ident = format_ident!("register_{}", snake_case(&trait_ident.unraw().to_string()));
+
+    let client_methods = methods.iter().map(|m| {
+        let ident = &m.ident;
+        let name = &m.name;
+        let resp = &m.resp;
+        let req = m.req();
+        let req_value = m.req_value();
+        let params = m.args.iter().map(|(ident, ty)| quote!(#ident: #ty));
+        quote! {
+            pub async fn #ident(&self, #(#params),*) -> ::drpc::Result<#resp>
+            where
+                #req: ::drpc::codec::Encode<T::Codec> + Send,
+                #resp: ::drpc::codec::Decode<T::Codec>,
+            {
+                self.inner.call(#name, #req_value).await
+            }
+        }
+    });
+    let register_bounds = methods.iter().map(|m| {
+        let req = m.req();
+        let resp = &m.resp;
+        quote! {
+            #req: ::drpc::codec::Decode<C> + Send + 'static,
+            #resp: ::drpc::codec::Encode<C> + 'static,
+        }
+    });
+    let registers = methods.iter().map(|m| {
+        let ident = &m.ident;
+        let name = &m.name;
+        let req = m.req();
+        let req_value = m.req_value();
+        let args = m.args.iter().map(|(ident, _)| ident);
+        quote! {
+            {
+                let service = service.clone();
+                server.register_fn(#name, move |#req_value: #req| {
+                    let service = service.clone();
+                    async move { service.#ident(#(#args),*).await }
+                })?;
+            }
+        }
+    });
+    let client_doc = format!("The typed client of `{}`.", trait_ident);
+    let register_doc = format!("Register the methods of a `{}` on `server`.", trait_ident);
+    Ok(quote! {
+        #item
+
+        #[doc = #client_doc]
+        #[derive(Debug)]
+        #vis struct #client_ident<T> {
+            pub inner: T,
+        }
+
+        impl<T: ::drpc::client::Call> #client_ident<T> {
+            pub fn new(inner: T) -> Self {
+                Self { inner }
+            }
+
+            #(#client_methods)*
+        }
+
+        #[doc = #register_doc]
+        #vis fn #register_ident<C, S>(
+            server: &mut ::drpc::server::Server<C>,
+            service: S,
+        ) -> ::drpc::Result<()>
+        where
+            C: ::drpc::codec::Format + 'static,
+            S: #trait_ident + Send + Sync + 'static,
+            #(#register_bounds)*
+        {
+            let service = ::std::sync::Arc::new(service);
+            #(#registers)*
+            Ok(())
+        }
+    })
+}
+
+fn method(trait_ident: &Ident, f: &TraitItemFn) -> syn::Result<Method> {
+    let sig = &f.sig;
+    if sig.asyncness.is_none() {
+        return Err(Error::new_spanned(sig, "service method must be `async fn`"));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &sig.generics,
+            "service method can not be generic",
+        ));
+    }
+    let mut inputs = sig.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_none() => {}
+        _ => return Err(Error::new_spanned(sig, "service method must take `&self`")),
+    }
+    let mut args = vec![];
+    for (i, arg) in inputs.enumerate() {
+        let FnArg::Typed(arg) = arg else {
+            unreachable!()
+        };
+        let ident = match arg.pat.as_ref() {
+            Pat::Ident(v) => v.ident.clone(),
+            _ => format_ident!("arg{}", i),
+        };
+        args.push((ident, arg.ty.as_ref().clone()));
+    }
+    Ok(Method {
+        ident: sig.ident.clone(),
+        // without the `r#` of a keyword, the name a `.drpc` file gives it
+        name: format!("{}.{}", trait_ident.unraw(), sig.ident.unraw()),
+        args,
+        resp: resp_type(&sig.output)?,
+    })
+}
+
+/// The `T` of `-> Result<T>`.
+fn resp_type(output: &ReturnType) -> syn::Result<Type> {
+    let err = || Error::new_spanned(output, "service method must return `drpc::Result<T>`");
+    let ReturnType::Type(_, ty) = output else {
+        return Err(err());
+    };
+    let Type::Path(path) = ty.as_ref() else {
+        return Err(err());
+    };
+    let last = path.path.segments.last().ok_or_else(err)?;
+    if last.ident != "Result" {
+        return Err(err());
+    }
+    let PathArguments::AngleBracketed(args) = &last.arguments else {
+        return Err(err());
+    };
+    match args.args.first() {
+        Some(GenericArgument::Type(ty)) if args.args.len() == 1 => Ok(ty.clone()),
+        _ => Err(err()),
+    }
+}
+
+/// `async fn f(..) -> R` to `fn f(..) -> impl Future<Output = R> + Send`,
+/// so the server can spawn the futures of any impl.
+fn desugar(f: &mut TraitItemFn) {
+    let sig = &mut f.sig;
+    sig.asyncness = None;
+    let output = match &sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+    sig.output = parse_quote!(-> impl ::std::future::Future<Output = #output> + Send);
+    if let Some(body) = f.default.take() {
+        f.default = Some(parse_quote!({ async move #body }));
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i != 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
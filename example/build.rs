@@ -0,0 +1,5 @@
+fn main() {
+    if let Err(e) = drpc_build::compile(&["idl/greeter.drpc"]) {
+        panic!("{}", e);
+    }
+}
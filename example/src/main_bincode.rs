@@ -22,6 +22,6 @@ async fn main() {
         exit(0);
     });
     let mut s = Server::<BinCodec>::new();
-    s.register_fn("handle", handle);
+    s.register_fn("handle", handle).unwrap();
     s.serve("0.0.0.0:10000").await;
 }
@@ -41,7 +41,7 @@ async fn main() {
         exit(0);
     });
     let mut s = Server::<BsonCodec>::new();
-    s.register_fn("handle", handle);
+    s.register_fn("handle", handle).unwrap();
     s.serve("0.0.0.0:10000").await;
 }
 
@@ -56,4 +56,11 @@ impl Codec for BsonCodec {
     fn decode<T: DeserializeOwned>(&self, arg: &[u8]) -> std::result::Result<T, drpc::Error> {
         bson::from_slice(arg).map_err(|e| drpc::err!("{}", e.to_string()))
     }
+
+    fn decode_borrowed<'de, T: Deserialize<'de>>(
+        &self,
+        arg: &'de [u8],
+    ) -> std::result::Result<T, drpc::Error> {
+        bson::from_slice(arg).map_err(|e| drpc::err!("{}", e.to_string()))
+    }
 }
@@ -0,0 +1,50 @@
+use drpc::client::Client;
+use drpc::codec::BinCodec;
+use drpc::server::Server;
+use fast_log::config::Config;
+use std::process::exit;
+use std::time::Duration;
+use tokio::time::sleep;
+
+include!(concat!(env!("OUT_DIR"), "/greeter.rs"));
+
+pub struct GreeterImpl {}
+
+impl Greeter for GreeterImpl {
+    async fn hello(&self, user: User) -> drpc::Result<String> {
+        Ok(format!(
+            "hello {}, {} friends",
+            user.name,
+            user.friends.len()
+        ))
+    }
+
+    async fn add(&self, a: i32, b: i32) -> drpc::Result<i32> {
+        Ok(a + b)
+    }
+
+    async fn ping(&self) -> drpc::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    fast_log::init(Config::new().console()).expect("fast_log init fail");
+    tokio::spawn(async move {
+        sleep(Duration::from_secs(1)).await;
+        let c = GreeterClient::new(Client::<BinCodec>::dial("127.0.0.1:10000").await.unwrap());
+        println!("dial success");
+        let user = User {
+            name: "drpc".to_string(),
+            ..Default::default()
+        };
+        println!("resp=>>>>>>>>>>>>>> :{:?}", c.hello(user).await.unwrap());
+        println!("resp=>>>>>>>>>>>>>> :{:?}", c.add(1, 2).await.unwrap());
+        c.ping().await.unwrap();
+        exit(0);
+    });
+    let mut s = Server::<BinCodec>::new();
+    register_greeter(&mut s, GreeterImpl {}).unwrap();
+    s.serve("0.0.0.0:10000").await;
+}
@@ -40,6 +40,6 @@ async fn main() {
         exit(0);
     });
     let mut s = Server::<JsonCodec>::new();
-    s.register_fn("handle", handle);
+    s.register_fn("handle", handle).unwrap();
     s.serve("0.0.0.0:10000").await;
 }
@@ -80,14 +80,14 @@ impl RegistryCenter for RedisCenter {
                 addr.to_string(),
                 addr.to_string(),
             )
-                .await
-                .map_err(|e| Error::from(e.to_string()))?;
+            .await
+            .map_err(|e| Error::from(e.to_string()))?;
             l.expire::<String, ()>(
                 format!("{}{}", &self.server_prefix, service),
                 ex.as_secs() as usize,
             )
-                .await
-                .map_err(|e| Error::from(e.to_string()))?;
+            .await
+            .map_err(|e| Error::from(e.to_string()))?;
         }
         return Ok(());
     }
@@ -100,6 +100,7 @@ async fn spawn_server(manager: Arc<BalanceManger<BinCodec, RedisCenter>>) {
             .await;
     });
     let mut s = Server::default();
-    s.register_fn("handle", |arg: i32| async move { Ok(arg + 1) });
+    s.register_fn("handle", |arg: i32| async move { Ok(arg + 1) })
+        .unwrap();
     s.serve("127.0.0.1:10000").await;
 }
@@ -1,16 +1,18 @@
 use dark_std::err;
 use dark_std::errors::Result;
 use dark_std::sync::SyncHashMap;
-use serde::de::DeserializeOwned;
-use serde::Serialize;
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
 
 use crate::balance::{LoadBalance, LoadBalanceType};
-use crate::client::Client;
-use crate::codec::Codec;
+use crate::client::{Call, Client};
+use crate::codec::{Decode, Encode, Format};
+use crate::handshake::Handshake;
+use crate::interceptor::{Interceptor, Interceptors};
+use crate::method::Method;
 
 /// To fetch remote service addr list
 
@@ -24,6 +26,10 @@ pub trait RegistryCenter: Sync + Send {
 pub struct ManagerConfig {
     pub balance: LoadBalanceType,
     pub interval: Duration,
+    /// the options asked for when dialing a server
+    pub handshake: Handshake,
+    /// run around the calls of every client dialed, see `Client::add_interceptor`
+    pub interceptors: Interceptors,
 }
 
 impl ManagerConfig {
@@ -38,6 +44,14 @@ impl ManagerConfig {
         self.interval = d;
         self
     }
+    pub fn handshake(mut self, handshake: Handshake) -> Self {
+        self.handshake = handshake;
+        self
+    }
+    pub fn interceptor<I: Interceptor + 'static>(mut self, i: I) -> Self {
+        self.interceptors.push(i);
+        self
+    }
 }
 
 impl Default for ManagerConfig {
@@ -45,20 +59,21 @@ impl Default for ManagerConfig {
         ManagerConfig {
             balance: LoadBalanceType::Round,
             interval: Duration::from_secs(5),
+            handshake: Handshake::default(),
+            interceptors: Interceptors::default(),
         }
     }
 }
 
 /// A connect manager that accepts a server addresses and make a client list.
-pub struct BalanceManger<C: Codec, Registry: RegistryCenter> {
+pub struct BalanceManger<C: Format, Registry: RegistryCenter> {
     pub config: ManagerConfig,
     pub clients: SyncHashMap<String, LoadBalance<Client<C>>>,
     pub fetcher: Arc<Registry>,
 }
 
-impl<C: Codec, Registry: RegistryCenter> BalanceManger<C, Registry> {
-    pub fn new(cfg: ManagerConfig, f: Registry) -> Arc<Self>
-    {
+impl<C: Format, Registry: RegistryCenter> BalanceManger<C, Registry> {
+    pub fn new(cfg: ManagerConfig, f: Registry) -> Arc<Self> {
         Arc::new(Self {
             config: cfg,
             clients: SyncHashMap::new(),
@@ -77,7 +92,7 @@ impl<C: Codec, Registry: RegistryCenter> BalanceManger<C, Registry> {
             if let Some(clients) = balance {
                 for addr in &addrs {
                     if !clients.contains(addr) {
-                        let c = Client::dial(addr).await?;
+                        let c = self.dial(addr).await?;
                         clients.put(c);
                     }
                 }
@@ -99,7 +114,7 @@ impl<C: Codec, Registry: RegistryCenter> BalanceManger<C, Registry> {
             } else {
                 let clients = LoadBalance::new();
                 for x in addrs {
-                    let c = Client::dial(&x).await?;
+                    let c = self.dial(&x).await?;
                     clients.put(c);
                 }
                 self.clients.insert(s, clients);
@@ -108,6 +123,11 @@ impl<C: Codec, Registry: RegistryCenter> BalanceManger<C, Registry> {
         return Ok(());
     }
 
+    async fn dial(&self, addr: &str) -> Result<Client<C>> {
+        let c = Client::dial_with(addr, self.config.handshake.clone()).await?;
+        Ok(c.set_interceptors(self.config.interceptors.clone()))
+    }
+
     /// Spawn an loop pull
     pub async fn spawn_pull(&self) {
         loop {
@@ -142,9 +162,9 @@ impl<C: Codec, Registry: RegistryCenter> BalanceManger<C, Registry> {
     }
 
     pub async fn call<Arg, Resp>(&self, service: &str, func: &str, arg: Arg) -> Result<Resp>
-        where
-            Arg: Serialize,
-            Resp: DeserializeOwned,
+    where
+        Arg: Encode<C>,
+        Resp: Decode<C>,
     {
         return match self
             .clients
@@ -156,4 +176,44 @@ impl<C: Codec, Registry: RegistryCenter> BalanceManger<C, Registry> {
             Some(c) => c.call(func, arg).await,
         };
     }
+
+    /// Call `method` of `service`, the request and response types are the ones of its descriptor.
+    pub async fn invoke<Req, Resp>(
+        &self,
+        service: &str,
+        method: &Method<Req, Resp>,
+        req: Req,
+    ) -> Result<Resp>
+    where
+        Req: Encode<C>,
+        Resp: Decode<C>,
+    {
+        self.call(service, method.name, req).await
+    }
+
+    /// The calls to `service`, balanced over its clients.
+    pub fn service(self: &Arc<Self>, service: &str) -> BalanceService<C, Registry> {
+        BalanceService {
+            manager: self.clone(),
+            service: service.to_string(),
+        }
+    }
+}
+
+/// The calls of a `BalanceManger` to one service, e.g. for the client of a `#[drpc::service]`.
+pub struct BalanceService<C: Format, Registry: RegistryCenter> {
+    pub manager: Arc<BalanceManger<C, Registry>>,
+    pub service: String,
+}
+
+impl<C: Format, Registry: RegistryCenter> Call for BalanceService<C, Registry> {
+    type Codec = C;
+
+    fn call<Arg, Resp>(&self, func: &str, arg: Arg) -> impl Future<Output = Result<Resp>> + Send
+    where
+        Arg: Encode<C> + Send,
+        Resp: Decode<C>,
+    {
+        self.manager.call(&self.service, func, arg)
+    }
 }
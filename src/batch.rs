@@ -0,0 +1,248 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use dark_std::errors::{Error, Result};
+
+use crate::client::Client;
+use crate::codec::{Decode, Encode, Format};
+use crate::frame::{get_varint, put_varint, Frame};
+use crate::metadata::{self, Metadata};
+use crate::stub::method_id;
+
+// batch request payload
+// concurrent(u8) + count(varint) + [name_len(varint) + name + arg_len(varint) + arg] * count
+// batch response payload, in the order of the requests
+// count(varint) + [ok(u8) + len(varint) + data or error string] * count
+
+/// the built-in method running a batch of calls, see `Client::batch`
+pub const METHOD: &str = "drpc.batch";
+
+/// Is `req` the request of a batch, the server runs it instead of resolving a method.
+pub fn is_batch(req: &Frame) -> bool {
+    match req.method_id {
+        Some(id) => id == method_id(METHOD),
+        None => req
+            .data
+            .strip_prefix(METHOD.as_bytes())
+            .is_some_and(|v| v.first() == Some(&b'\n')),
+    }
+}
+
+/// The calls of a batch, each arg already encoded.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct BatchRequest {
+    pub items: Vec<(String, Bytes)>,
+    /// run the calls concurrently, else one after the other
+    pub concurrent: bool,
+    /// the index and fingerprint of the checked calls, sent in `metadata::FINGERPRINTS`
+    pub fingerprints: Vec<(usize, u64)>,
+}
+
+impl BatchRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a call, its index in the `BatchResponse` is returned.
+    pub fn push<C: Format, Arg: Encode<C>>(
+        &mut self,
+        method: &str,
+        arg: Arg,
+        codec: &C,
+    ) -> Result<usize> {
+        let mut buf = BytesMut::new();
+        arg.encode_into(codec, &mut buf)?;
+        self.items.push((method.to_string(), buf.freeze()));
+        Ok(self.items.len() - 1)
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.put_u8(self.concurrent as u8);
+        put_varint(buf, self.items.len() as u64);
+        for (method, arg) in &self.items {
+            put_varint(buf, method.len() as u64);
+            buf.put_slice(method.as_bytes());
+            put_varint(buf, arg.len() as u64);
+            buf.put_slice(arg);
+        }
+    }
+
+    pub fn decode(data: &Bytes) -> Result<Self> {
+        let concurrent = *data.first().ok_or_else(|| Error::from("batch is empty!"))? != 0;
+        let mut pos = 1;
+        let count = read_len(data, &mut pos)?;
+        let mut items = Vec::new();
+        for _ in 0..count {
+            let method = read_bytes(data, &mut pos)?;
+            let method = std::str::from_utf8(&method)
+                .map_err(|_| Error::from("method is not utf8!"))?
+                .to_string();
+            items.push((method, read_bytes(data, &mut pos)?));
+        }
+        Ok(Self {
+            items,
+            concurrent,
+            fingerprints: vec![],
+        })
+    }
+
+    /// The metadata of the batch frame, the fingerprints of its checked calls.
+    pub fn metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new();
+        if !self.fingerprints.is_empty() {
+            let mut buf = BytesMut::new();
+            for (index, fingerprint) in &self.fingerprints {
+                put_varint(&mut buf, *index as u64);
+                buf.put_u64(*fingerprint);
+            }
+            metadata.insert(metadata::FINGERPRINTS, buf.to_vec());
+        }
+        metadata
+    }
+}
+
+/// The fingerprint each call of a batch of `count` calls was sent with, see `BatchRequest::metadata`.
+pub fn fingerprints(metadata: &Metadata, count: usize) -> Result<Vec<Option<u64>>> {
+    let mut fingerprints = vec![None; count];
+    let data = match metadata.get(metadata::FINGERPRINTS) {
+        Some(v) => v.clone(),
+        None => return Ok(fingerprints),
+    };
+    let mut pos = 0;
+    while pos < data.len() {
+        let index = read_len(&data, &mut pos)?;
+        let v = data
+            .get(pos..pos + 8)
+            .and_then(|v| <[u8; 8]>::try_from(v).ok())
+            .ok_or_else(|| Error::from("batch fingerprints are truncated!"))?;
+        pos += 8;
+        *fingerprints
+            .get_mut(index)
+            .ok_or_else(|| Error::from(format!("batch has no call {}!", index)))? =
+            Some(u64::from_be_bytes(v));
+    }
+    Ok(fingerprints)
+}
+
+/// The results of a batch, in the order of its calls.
+#[derive(Debug, Clone)]
+pub struct BatchResponse<C: Format> {
+    items: Vec<std::result::Result<Bytes, String>>,
+    codec: C,
+}
+
+impl<C: Format> BatchResponse<C> {
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The result of the call at `index`, the error of the call when it failed.
+    pub fn get<Resp: Decode<C>>(&self, index: usize) -> Result<Resp> {
+        match self.items.get(index) {
+            Some(Ok(data)) => Resp::decode(&self.codec, data),
+            Some(Err(e)) => Err(Error::from(e.as_str())),
+            None => Err(Error::from(format!("batch has no call {}!", index))),
+        }
+    }
+
+    pub fn decode(data: &Bytes, codec: C) -> Result<Self> {
+        let mut pos = 0;
+        let count = read_len(data, &mut pos)?;
+        let mut items = Vec::new();
+        for _ in 0..count {
+            let ok = *data
+                .get(pos)
+                .ok_or_else(|| Error::from("batch response is truncated!"))?;
+            pos += 1;
+            let item = read_bytes(data, &mut pos)?;
+            items.push(match ok {
+                1 => Ok(item),
+                _ => Err(String::from_utf8_lossy(&item).to_string()),
+            });
+        }
+        Ok(Self { items, codec })
+    }
+}
+
+/// Encode the results of the calls of a batch, see `BatchResponse`.
+pub fn encode_response(results: Vec<Result<Vec<u8>>>) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    put_varint(&mut buf, results.len() as u64);
+    for r in results {
+        let (ok, data) = match r {
+            Ok(data) => (1, data),
+            Err(e) => (0, e.to_string().into_bytes()),
+        };
+        buf.put_u8(ok);
+        put_varint(&mut buf, data.len() as u64);
+        buf.put_slice(&data);
+    }
+    buf.to_vec()
+}
+
+fn read_len(data: &Bytes, pos: &mut usize) -> Result<usize> {
+    get_varint(data, pos)?
+        .map(|v| v as usize)
+        .ok_or_else(|| Error::from("batch is truncated!"))
+}
+
+fn read_bytes(data: &Bytes, pos: &mut usize) -> Result<Bytes> {
+    let len = read_len(data, pos)?;
+    let end = pos
+        .checked_add(len)
+        .filter(|end| *end <= data.len())
+        .ok_or_else(|| Error::from("batch is truncated!"))?;
+    let v = data.slice(*pos..end);
+    *pos = end;
+    Ok(v)
+}
+
+/// A batch of calls over a `Client`, sent in one request frame, see `Client::batch`.
+/// The result or error of each call comes back in one `BatchResponse`.
+pub struct Batch<'a, C: Format> {
+    client: &'a Client<C>,
+    req: BatchRequest,
+}
+
+impl<'a, C: Format> Batch<'a, C> {
+    pub fn new(client: &'a Client<C>) -> Self {
+        Self {
+            client,
+            req: BatchRequest::new(),
+        }
+    }
+
+    /// Run the calls concurrently on the server, one after the other by default.
+    pub fn set_concurrent(mut self, concurrent: bool) -> Self {
+        self.req.concurrent = concurrent;
+        self
+    }
+
+    /// Add a call, its index in the `BatchResponse` is returned.
+    pub fn push<Arg: Encode<C>>(&mut self, func: &str, arg: Arg) -> Result<usize> {
+        self.req.push(func, arg, &self.client.codec)
+    }
+
+    /// Same as `push`, with the fingerprint of `Arg` and `Resp` checked by a server
+    /// checking fingerprints(`Server::set_fingerprint`), see `Client::call_checked`.
+    #[cfg(feature = "fingerprint")]
+    pub fn push_checked<Arg, Resp>(&mut self, func: &str, arg: Arg) -> Result<usize>
+    where
+        C: 'static,
+        Arg: Encode<C> + Decode<C> + 'static,
+        Resp: Decode<C> + 'static,
+    {
+        let index = self.push(func, arg)?;
+        if let Some(v) = crate::fingerprint::method_cached::<C, Arg, Resp>() {
+            self.req.fingerprints.push((index, v));
+        }
+        Ok(index)
+    }
+
+    /// Send the calls, the errors of the calls are in the response.
+    pub async fn send(self) -> Result<BatchResponse<C>> {
+        self.client.call_batch(&self.req).await
+    }
+}
@@ -0,0 +1,427 @@
+//! A CBOR(RFC 8949) deserializer over a slice, for `CborCodec::decode_borrowed`.
+//! ciborium reads text and byte strings through a scratch buffer, here a definite
+//! length string borrows from the input. It reads what ciborium writes.
+
+use serde::de::{self, DeserializeSeed, Visitor};
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+
+/// the deepest nesting of arrays and maps read
+const MAX_DEPTH: usize = 256;
+
+/// the break stop code ending an indefinite length item
+const BREAK: u8 = 0xff;
+
+/// Decode a `T` from `buf`, its `&str`/`&[u8]` fields borrow from `buf`.
+/// Bytes after the value are ignored, as ciborium does.
+pub fn from_slice<'de, T: Deserialize<'de>>(buf: &'de [u8]) -> Result<T> {
+    T::deserialize(&mut Deserializer {
+        buf,
+        pos: 0,
+        depth: MAX_DEPTH,
+    })
+}
+
+#[derive(Debug)]
+pub struct CborError(String);
+
+impl Display for CborError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CborError {}
+
+impl de::Error for CborError {
+    fn custom<T: Display>(msg: T) -> Self {
+        CborError(msg.to_string())
+    }
+}
+
+type Result<T> = std::result::Result<T, CborError>;
+
+/// The head of a data item
+enum Head {
+    Uint(u64),
+    /// the value is `-1 - n`
+    Nint(u64),
+    /// `None` is an indefinite length
+    Bytes(Option<u64>),
+    Text(Option<u64>),
+    Array(Option<u64>),
+    Map(Option<u64>),
+    Tag(u64),
+    Simple(u8),
+    F16(u16),
+    F32(f32),
+    F64(f64),
+    Break,
+}
+
+struct Deserializer<'de> {
+    buf: &'de [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'de> Deserializer<'de> {
+    fn error(&self, msg: &str) -> CborError {
+        CborError(format!("cbor: {} at offset {}", msg, self.pos))
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.buf
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| self.error("unexpected end"))
+    }
+
+    fn take(&mut self, len: u64) -> Result<&'de [u8]> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.pos.checked_add(len))
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| self.error("unexpected end"))?;
+        let v = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(v)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut v = [0u8; N];
+        v.copy_from_slice(self.take(N as u64)?);
+        Ok(v)
+    }
+
+    fn head(&mut self) -> Result<Head> {
+        let b = self.peek()?;
+        self.pos += 1;
+        let (major, info) = (b >> 5, b & 0x1f);
+        let arg = match info {
+            0..=23 => Some(info as u64),
+            24 => Some(self.take_array::<1>()?[0] as u64),
+            25 => Some(u16::from_be_bytes(self.take_array()?) as u64),
+            26 => Some(u32::from_be_bytes(self.take_array()?) as u64),
+            27 => Some(u64::from_be_bytes(self.take_array()?)),
+            31 => None,
+            _ => return Err(self.error("invalid head")),
+        };
+        Ok(match (major, arg) {
+            (0, Some(v)) => Head::Uint(v),
+            (1, Some(v)) => Head::Nint(v),
+            (2, len) => Head::Bytes(len),
+            (3, len) => Head::Text(len),
+            (4, len) => Head::Array(len),
+            (5, len) => Head::Map(len),
+            (6, Some(v)) => Head::Tag(v),
+            (7, None) => Head::Break,
+            (7, Some(v)) => match info {
+                25 => Head::F16(v as u16),
+                26 => Head::F32(f32::from_bits(v as u32)),
+                27 => Head::F64(f64::from_bits(v)),
+                _ => Head::Simple(v as u8),
+            },
+            _ => return Err(self.error("invalid head")),
+        })
+    }
+
+    /// Skip the tags before an item
+    fn skip_tags(&mut self) -> Result<()> {
+        while self.peek()? >> 5 == 6 {
+            self.head()?;
+        }
+        Ok(())
+    }
+
+    /// Consume the break ending an indefinite length item, if it is next
+    fn take_break(&mut self) -> Result<bool> {
+        if self.peek()? == BREAK {
+            self.pos += 1;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// The chunks of an indefinite length string of `major` type, joined
+    fn chunks(&mut self, major: u8) -> Result<Vec<u8>> {
+        let mut v = vec![];
+        while !self.take_break()? {
+            match self.head()? {
+                Head::Bytes(Some(len)) if major == 2 => v.extend_from_slice(self.take(len)?),
+                Head::Text(Some(len)) if major == 3 => v.extend_from_slice(self.take(len)?),
+                _ => return Err(self.error("invalid string chunk")),
+            }
+        }
+        Ok(v)
+    }
+
+    fn text(&mut self, len: u64) -> Result<&'de str> {
+        let v = self.take(len)?;
+        std::str::from_utf8(v).map_err(|_| self.error("invalid utf-8"))
+    }
+
+    /// Read the items of an array or a map, they all must be read by the visitor
+    fn nested<V, F>(&mut self, len: Option<u64>, visit: F) -> Result<V>
+    where
+        F: FnOnce(&mut Access<'_, 'de>) -> Result<V>,
+    {
+        if self.depth == 0 {
+            return Err(self.error("nested too deep"));
+        }
+        self.depth -= 1;
+        let mut access = Access { de: self, len };
+        let v = visit(&mut access)?;
+        let done = match access.len {
+            Some(len) => len == 0,
+            None => access.de.take_break()?,
+        };
+        self.depth += 1;
+        if !done {
+            return Err(self.error("items left unread"));
+        }
+        Ok(v)
+    }
+
+    /// A bignum(tag 2 or 3) of at most 16 bytes
+    fn bignum(&mut self) -> Result<u128> {
+        match self.head()? {
+            Head::Bytes(Some(len)) if len <= 16 => Ok(self
+                .take(len)?
+                .iter()
+                .fold(0u128, |v, b| (v << 8) | *b as u128)),
+            _ => Err(self.error("invalid bignum")),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = CborError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        loop {
+            return match self.head()? {
+                Head::Uint(v) => visitor.visit_u64(v),
+                Head::Nint(v) => match i64::try_from(v) {
+                    Ok(v) => visitor.visit_i64(-1 - v),
+                    Err(_) => visitor.visit_i128(-1 - v as i128),
+                },
+                Head::Bytes(Some(len)) => visitor.visit_borrowed_bytes(self.take(len)?),
+                Head::Bytes(None) => visitor.visit_byte_buf(self.chunks(2)?),
+                Head::Text(Some(len)) => visitor.visit_borrowed_str(self.text(len)?),
+                Head::Text(None) => match String::from_utf8(self.chunks(3)?) {
+                    Ok(v) => visitor.visit_string(v),
+                    Err(_) => Err(self.error("invalid utf-8")),
+                },
+                Head::Array(len) => self.nested(len, |access| visitor.visit_seq(access)),
+                Head::Map(len) => self.nested(len, |access| visitor.visit_map(access)),
+                Head::Tag(2) => visitor.visit_u128(self.bignum()?),
+                Head::Tag(3) => match i128::try_from(self.bignum()?) {
+                    Ok(v) => visitor.visit_i128(-1 - v),
+                    Err(_) => Err(self.error("bignum overflows an i128")),
+                },
+                Head::Tag(_) => continue,
+                Head::Simple(20) => visitor.visit_bool(false),
+                Head::Simple(21) => visitor.visit_bool(true),
+                Head::Simple(22) | Head::Simple(23) => visitor.visit_unit(),
+                Head::F16(v) => visitor.visit_f32(f16_to_f32(v)),
+                Head::F32(v) => visitor.visit_f32(v),
+                Head::F64(v) => visitor.visit_f64(v),
+                Head::Simple(_) | Head::Break => Err(self.error("unexpected item")),
+            };
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.skip_tags()?;
+        // null or undefined
+        if let 0xf6 | 0xf7 = self.peek()? {
+            self.pos += 1;
+            return visitor.visit_none();
+        }
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.skip_tags()?;
+        // a unit variant is its name, the others a map of one entry: name => value
+        if self.peek()? >> 5 == 3 {
+            return visitor.visit_enum(Enum {
+                de: self,
+                len: None,
+                unit: true,
+            });
+        }
+        match self.head()? {
+            Head::Map(len @ (Some(1) | None)) => visitor.visit_enum(Enum {
+                de: self,
+                len,
+                unit: false,
+            }),
+            _ => Err(self.error("expected an enum")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// The items of an array or the entries of a map
+struct Access<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    /// the items left, `None` until the break of an indefinite length
+    len: Option<u64>,
+}
+
+impl<'de> Access<'_, 'de> {
+    fn has_next(&mut self) -> Result<bool> {
+        match &mut self.len {
+            Some(0) => Ok(false),
+            Some(len) => {
+                *len -= 1;
+                Ok(true)
+            }
+            None => Ok(self.de.peek()? != BREAK),
+        }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for &mut Access<'_, 'de> {
+    type Error = CborError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if !self.has_next()? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.len.and_then(|len| usize::try_from(len).ok())
+    }
+}
+
+impl<'de> de::MapAccess<'de> for &mut Access<'_, 'de> {
+    type Error = CborError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if !self.has_next()? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.len.and_then(|len| usize::try_from(len).ok())
+    }
+}
+
+/// An enum variant: a name for a unit variant, else a map of one entry
+struct Enum<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    /// the length of the map, `None` when indefinite
+    len: Option<u64>,
+    unit: bool,
+}
+
+impl Enum<'_, '_> {
+    /// The value is read, an indefinite map ends with a break
+    fn end(self) -> Result<()> {
+        if self.len.is_none() && !self.de.take_break()? {
+            return Err(self.de.error("expected one enum entry"));
+        }
+        Ok(())
+    }
+
+    fn not_unit(&self) -> Result<()> {
+        if self.unit {
+            return Err(self.de.error("expected an enum entry"));
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for Enum<'a, 'de> {
+    type Error = CborError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let variant = seed.deserialize(&mut *self.de)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Enum<'_, 'de> {
+    type Error = CborError;
+
+    fn unit_variant(self) -> Result<()> {
+        if !self.unit {
+            // ciborium writes unit variants as names, a map holds a null
+            de::IgnoredAny::deserialize(&mut *self.de)?;
+            return self.end();
+        }
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        self.not_unit()?;
+        let v = seed.deserialize(&mut *self.de)?;
+        self.end()?;
+        Ok(v)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.not_unit()?;
+        let v = de::Deserializer::deserialize_any(&mut *self.de, visitor)?;
+        self.end()?;
+        Ok(v)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.not_unit()?;
+        let v = de::Deserializer::deserialize_any(&mut *self.de, visitor)?;
+        self.end()?;
+        Ok(v)
+    }
+}
+
+/// A half precision float, exactly
+fn f16_to_f32(v: u16) -> f32 {
+    let exp = (v >> 10) & 0x1f;
+    let mant = (v & 0x3ff) as f32;
+    let abs = match exp {
+        0 => mant * 2f32.powi(-24),
+        31 if mant == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1024.0 + mant) * 2f32.powi(exp as i32 - 25),
+    };
+    if v >> 15 == 1 {
+        -abs
+    } else {
+        abs
+    }
+}
@@ -1,14 +1,26 @@
+use bytes::Bytes;
 use dark_std::errors::{Error, Result};
-use serde::de::DeserializeOwned;
-use serde::Serialize;
-use std::ops::DerefMut;
+use futures::Stream;
+use std::future::Future;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio_util::codec::Framed;
 
 use crate::balance::RpcClient;
-use crate::codec::Codec;
+use crate::batch::{Batch, BatchRequest, BatchResponse};
+use crate::codec::{Decode, Encode, Format};
+#[cfg(feature = "fingerprint")]
+use crate::fingerprint;
+use crate::frame::FrameCodec;
+use crate::handshake::Handshake;
+use crate::interceptor::{Interceptor, Interceptors};
+use crate::metadata::Metadata;
+use crate::method::Method;
+use crate::mux::Mux;
+use crate::reflect::{self, ServiceInfo};
+use crate::server::Server;
+use crate::stream::{RequestSender, ResponseStream};
 use crate::stub::ClientStub;
 
 //TODO parse addr: tcp://addr,http://addr
@@ -29,25 +41,61 @@ use crate::stub::ClientStub;
 ///
 /// ```
 #[derive(Debug)]
-pub struct Client<C: Codec> {
+pub struct Client<C: Format> {
     pub addr: String,
     pub codec: C,
     pub stub: ClientStub,
-    pub stream: Option<Mutex<TcpStream>>,
+    /// the connection, shared by the calls in flight
+    pub stream: Option<Mux>,
 }
 
-impl<C: Codec> Client<C> {
+impl<C: Format> Client<C> {
+    /// Dial the server, asking for every option this build supports.
+    /// A server that does not speak the handshake is called with the defaults.
     pub async fn dial(addr: &str) -> std::io::Result<Self> {
+        Self::dial_with(addr, Handshake::default())
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))
+    }
+
+    /// Dial the server and negotiate the connection options of `handshake`.
+    /// The codec named by `handshake` is spoken when `C` can, else the one of `C::default()`.
+    pub async fn dial_with(addr: &str, mut handshake: Handshake) -> Result<Self> {
         let address = addr.to_string();
-        let stream = TcpStream::connect(addr).await?;
+        let tcp = TcpStream::connect(addr).await?;
+        // the small frames of the streams are not held back for the acks
+        tcp.set_nodelay(true)?;
+        let mut stream = Framed::new(tcp, FrameCodec::default());
+        let mut codec = C::default();
+        if let Some(c) = handshake.codec.and_then(|id| codec.select(id)) {
+            codec = c;
+        }
+        if codec.id() != 0 {
+            handshake.codec = Some(codec.id());
+        }
+        let mut stub = ClientStub::new();
+        stub.handshake(&handshake, &mut stream).await?;
         Ok(Self {
             addr: address,
-            codec: C::default(),
-            stub: ClientStub::new(),
-            stream: Some(Mutex::new(stream)),
+            codec,
+            stub,
+            stream: Some(Mux::new(stream)),
         })
     }
 
+    /// Set the codec, for a codec configured other than its `Default`.
+    /// A codec other than the one negotiated at dial is named in the metadata of each call.
+    pub fn set_codec(mut self, codec: C) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Set the items a stream sends ahead of the ones read, see `call_stream`.
+    pub fn set_window(mut self, window: u32) -> Self {
+        self.stub.window = window;
+        self
+    }
+
     /// Set the client's timeout.
     pub fn set_timeout(mut self, timeout: Option<Duration>) -> Self {
         self.stub.timeout = timeout;
@@ -59,45 +107,268 @@ impl<C: Codec> Client<C> {
         &self.stub.timeout
     }
 
+    /// Address methods by name instead of by `method_id`, handy for debugging.
+    pub fn set_method_id(mut self, method_id: bool) -> Self {
+        self.stub.method_id = method_id;
+        self
+    }
+
+    /// Answer the calls the server makes back over the connection(`Peer`)
+    /// by the unary methods registered on `callbacks`. Until it is set,
+    /// the calls back fail with `client serves no callbacks!`.
+    pub fn set_callbacks(self, callbacks: Server<C>) -> Self
+    where
+        C: 'static,
+    {
+        if let Some(mux) = &self.stream {
+            let server = Arc::new(callbacks);
+            let compression = self.stub.compression;
+            mux.set_callbacks(Arc::new(move |req| {
+                let server = server.clone();
+                Box::pin(async move {
+                    server
+                        .stub
+                        .call_frame(
+                            &server.handles,
+                            &server.ids,
+                            &server.codec,
+                            req,
+                            compression,
+                        )
+                        .await
+                })
+            }));
+        }
+        self
+    }
+
+    /// Run `i` around the unary calls and notifications, after the interceptors added before.
+    pub fn add_interceptor<I: Interceptor + 'static>(mut self, i: I) -> Self {
+        self.stub.interceptors.push(i);
+        self
+    }
+
+    /// Set the interceptors, e.g. the ones shared by the clients of a `BalanceManger`.
+    pub fn set_interceptors(mut self, interceptors: Interceptors) -> Self {
+        self.stub.interceptors = interceptors;
+        self
+    }
+
+    /// Compress the requests that are at least `threshold` bytes.
+    /// Only works when the server agreed on a compression at dial.
+    pub fn set_compress_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.stub.compress_threshold = threshold;
+        self
+    }
+
     pub async fn call<Arg, Resp>(&self, func: &str, arg: Arg) -> Result<Resp>
     where
-        Arg: Serialize,
-        Resp: DeserializeOwned,
+        Arg: Encode<C>,
+        Resp: Decode<C>,
+    {
+        self.call_with(func, arg, false, Metadata::new()).await
+    }
+
+    /// Call `func` with a request encoded by the client's codec, the response comes back encoded.
+    pub async fn call_encoded(&self, func: &str, body: Bytes, metadata: Metadata) -> Result<Bytes> {
+        match self.stream.as_ref() {
+            Some(v) => {
+                self.stub
+                    .call_encoded(func, body, &self.codec, false, metadata, v.call())
+                    .await
+            }
+            None => Err(Error::from("stream is shutdown!")),
+        }
+    }
+
+    /// Send a notification to `func`, without waiting for an answer, see `ClientStub::notify`.
+    pub async fn notify<Arg>(&self, func: &str, arg: Arg) -> Result<()>
+    where
+        Arg: Encode<C>,
+    {
+        match self.stream.as_ref() {
+            Some(v) => self.stub.notify(func, arg, &self.codec, v.call()).await,
+            None => Err(Error::from("stream is shutdown!")),
+        }
+    }
+
+    /// A batch of calls sent in one request frame, see `Batch`.
+    pub fn batch(&self) -> Batch<'_, C> {
+        Batch::new(self)
+    }
+
+    /// Send the calls of `batch` in one request frame, see `ClientStub::call_batch`.
+    pub async fn call_batch(&self, batch: &BatchRequest) -> Result<BatchResponse<C>> {
+        match self.stream.as_ref() {
+            Some(v) => self.stub.call_batch(batch, &self.codec, v.call()).await,
+            None => Err(Error::from("stream is shutdown!")),
+        }
+    }
+
+    /// Call `method`, the request and response types are the ones of its descriptor.
+    pub async fn invoke<Req, Resp>(&self, method: &Method<Req, Resp>, req: Req) -> Result<Resp>
+    where
+        Req: Encode<C>,
+        Resp: Decode<C>,
+    {
+        self.call(method.name, req).await
+    }
+
+    /// Call a method answering a stream(`Server::register_stream_fn`),
+    /// the other calls go on while it is read.
+    pub async fn call_stream<'a, Arg, Resp>(
+        &'a self,
+        func: &str,
+        arg: Arg,
+    ) -> Result<ResponseStream<'a, Resp>>
+    where
+        Arg: Encode<C>,
+        Resp: Decode<C> + Send + 'a,
+    {
+        match self.stream.as_ref() {
+            Some(v) => {
+                self.stub
+                    .call_stream(func, arg, &self.codec, v.call())
+                    .await
+            }
+            None => Err(Error::from("stream is shutdown!")),
+        }
+    }
+
+    /// Call a method reading a stream of requests(`Server::register_upload_fn`),
+    /// the requests are sent while the server grants them.
+    pub async fn call_upload<Arg, Resp, I>(&self, func: &str, reqs: I) -> Result<Resp>
+    where
+        Arg: Encode<C>,
+        Resp: Decode<C>,
+        I: Stream<Item = Arg>,
+    {
+        match self.stream.as_ref() {
+            Some(v) => {
+                self.stub
+                    .call_upload(func, reqs, &self.codec, v.call())
+                    .await
+            }
+            None => Err(Error::from("stream is shutdown!")),
+        }
+    }
+
+    /// Call a bidi streaming method(`Server::register_bidi_fn`), see `ClientStub::call_bidi`.
+    pub async fn call_bidi<'a, Arg, Resp>(
+        &'a self,
+        func: &str,
+    ) -> Result<(RequestSender<'a, C, Arg>, ResponseStream<'a, Resp>)>
+    where
+        Arg: Encode<C>,
+        Resp: Decode<C> + Send + 'a,
     {
-        return if let Some(v) = self.stream.as_ref() {
-            let mut stream = v.lock().await;
+        match self.stream.as_ref() {
+            Some(v) => self.stub.call_bidi(func, &self.codec, v).await,
+            None => Err(Error::from("stream is shutdown!")),
+        }
+    }
+
+    /// Call with the request compressed whatever its size,
+    /// the response comes back compressed too.
+    pub async fn call_compressed<Arg, Resp>(&self, func: &str, arg: Arg) -> Result<Resp>
+    where
+        Arg: Encode<C>,
+        Resp: Decode<C>,
+    {
+        self.call_with(func, arg, true, Metadata::new()).await
+    }
+
+    /// Call with the fingerprint of `Arg` and `Resp`,
+    /// a server checking fingerprints rejects the call when its types differ.
+    #[cfg(feature = "fingerprint")]
+    pub async fn call_checked<Arg, Resp>(&self, func: &str, arg: Arg) -> Result<Resp>
+    where
+        C: 'static,
+        Arg: Encode<C> + Decode<C> + 'static,
+        Resp: Decode<C> + 'static,
+    {
+        let mut metadata = Metadata::new();
+        if let Some(v) = fingerprint::method_cached::<C, Arg, Resp>() {
+            metadata.insert(crate::metadata::FINGERPRINT, v.to_be_bytes().to_vec());
+        }
+        self.call_with(func, arg, false, metadata).await
+    }
+
+    /// The methods of the server, when it answers the reflection(`Server::set_reflection`).
+    pub async fn reflect(&self) -> Result<ServiceInfo>
+    where
+        (): Encode<C>,
+        ServiceInfo: Decode<C>,
+    {
+        self.call(reflect::METHOD, ()).await
+    }
+
+    async fn call_with<Arg, Resp>(
+        &self,
+        func: &str,
+        arg: Arg,
+        compress: bool,
+        metadata: Metadata,
+    ) -> Result<Resp>
+    where
+        Arg: Encode<C>,
+        Resp: Decode<C>,
+    {
+        if let Some(v) = self.stream.as_ref() {
             let resp: Resp = self
                 .stub
-                .call(func, arg, &self.codec, stream.deref_mut())
+                .call_with_metadata(func, arg, &self.codec, compress, metadata, v.call())
                 .await?;
             Ok(resp)
         } else {
             Err(Error::from("stream is shutdown!"))
-        };
+        }
     }
 
     /// Shutdown the client.
     pub async fn shutdown(&mut self) {
         if let Some(v) = self.stream.take() {
-            let mut stream = v.into_inner();
-            let _ = stream.shutdown().await;
+            v.shutdown().await;
         }
     }
 }
 
-impl<C: Codec> RpcClient for Client<C> {
-    fn addr(&self) -> &str {
-        self.addr.as_str()
+/// What the client of a `#[drpc::service]` calls through, a `Client` or a `BalanceService`.
+pub trait Call: Sync + Send {
+    type Codec: Format;
+    fn call<Arg, Resp>(&self, func: &str, arg: Arg) -> impl Future<Output = Result<Resp>> + Send
+    where
+        Arg: Encode<Self::Codec> + Send,
+        Resp: Decode<Self::Codec>;
+
+    /// Call `method`, the request and response types are the ones of its descriptor.
+    fn invoke<Req, Resp>(
+        &self,
+        method: &Method<Req, Resp>,
+        req: Req,
+    ) -> impl Future<Output = Result<Resp>> + Send
+    where
+        Req: Encode<Self::Codec> + Send,
+        Resp: Decode<Self::Codec>,
+    {
+        self.call(method.name, req)
     }
 }
 
-impl<C: Codec> Drop for Client<C> {
-    fn drop(&mut self) {
-        if let Some(v) = self.stream.take() {
-            let mut stream = v.into_inner();
-            tokio::spawn(async move {
-                let _ = stream.shutdown().await;
-            });
-        }
+impl<C: Format> Call for Client<C> {
+    type Codec = C;
+
+    fn call<Arg, Resp>(&self, func: &str, arg: Arg) -> impl Future<Output = Result<Resp>> + Send
+    where
+        Arg: Encode<C> + Send,
+        Resp: Decode<C>,
+    {
+        Client::call(self, func, arg)
+    }
+}
+
+impl<C: Format> RpcClient for Client<C> {
+    fn addr(&self) -> &str {
+        self.addr.as_str()
     }
 }
@@ -1,11 +1,112 @@
+use bytes::{BufMut, BytesMut};
 use dark_std::err;
 use dark_std::errors::Error;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+/// the codec ids on the wire, sent in the handshake and the `metadata::CODEC` of a call.
+/// 0 is a codec the peer can not name, a custom one.
+pub const CODEC_BIN: u8 = 1;
+pub const CODEC_JSON: u8 = 2;
+pub const CODEC_MSGPACK: u8 = 3;
+pub const CODEC_CBOR: u8 = 4;
+pub const CODEC_PROST: u8 = 5;
 
 pub trait Codec: Sync + Send + Clone + Default {
     fn encode<T: Serialize>(&self, arg: T) -> Result<Vec<u8>, Error>;
     fn decode<T: DeserializeOwned>(&self, arg: &[u8]) -> Result<T, Error>;
+
+    /// The `CODEC_*` id of the codec, 0 for a custom one.
+    fn codec_id(&self) -> u8 {
+        0
+    }
+
+    /// The codec to answer a caller speaking the codec `id`, `None` when not supported.
+    fn select_codec(&self, id: u8) -> Option<Self> {
+        (id == self.codec_id()).then(|| self.clone())
+    }
+
+    /// Encode `arg` at the end of `buf`.
+    /// The default copies the output of `encode`, override it to write in place.
+    fn encode_into<T: Serialize>(&self, arg: T, buf: &mut BytesMut) -> Result<(), Error> {
+        buf.extend_from_slice(&self.encode(arg)?);
+        Ok(())
+    }
+
+    /// Decode a `T` whose `&str`/`&[u8]` fields borrow from `arg`, for `BorrowHandler`.
+    /// A format that can not borrow may copy them, it still decodes `T`.
+    fn decode_borrowed<'de, T: Deserialize<'de>>(&self, arg: &'de [u8]) -> Result<T, Error>;
+}
+
+/// A wire format the `Client`/`Server` can be typed over.
+/// Every serde `Codec` is one, so is `ProstCodec`; the message types it handles
+/// are told by `Encode`/`Decode`.
+pub trait Format: Sync + Send + Clone + Default {
+    /// The `CODEC_*` id of the format, 0 for a custom one.
+    fn id(&self) -> u8;
+
+    /// The format to answer a caller speaking the format `id`, `None` when not supported.
+    fn select(&self, id: u8) -> Option<Self>;
+}
+
+impl<C: Codec> Format for C {
+    fn id(&self) -> u8 {
+        self.codec_id()
+    }
+
+    fn select(&self, id: u8) -> Option<Self> {
+        self.select_codec(id)
+    }
+}
+
+/// A message the format `C` can encode
+pub trait Encode<C: Format> {
+    fn encode(self, codec: &C) -> Result<Vec<u8>, Error>;
+    /// Encode at the end of `buf`
+    fn encode_into(self, codec: &C, buf: &mut BytesMut) -> Result<(), Error>;
+}
+
+/// A message the format `C` can decode, it may borrow from the input
+pub trait DecodeBorrowed<'de, C: Format>: Sized {
+    fn decode_borrowed(codec: &C, arg: &'de [u8]) -> Result<Self, Error>;
+}
+
+/// A message the format `C` can decode into an owned value
+pub trait Decode<C: Format>: for<'de> DecodeBorrowed<'de, C> {
+    fn decode(codec: &C, arg: &[u8]) -> Result<Self, Error>;
+
+    /// The shape of the type, see `fingerprint::of`. `None` is never checked,
+    /// nor without the `fingerprint` feature.
+    fn fingerprint() -> Option<u64> {
+        None
+    }
+}
+
+impl<C: Codec, T: Serialize> Encode<C> for T {
+    fn encode(self, codec: &C) -> Result<Vec<u8>, Error> {
+        codec.encode(self)
+    }
+
+    fn encode_into(self, codec: &C, buf: &mut BytesMut) -> Result<(), Error> {
+        codec.encode_into(self, buf)
+    }
+}
+
+impl<'de, C: Codec, T: Deserialize<'de>> DecodeBorrowed<'de, C> for T {
+    fn decode_borrowed(codec: &C, arg: &'de [u8]) -> Result<Self, Error> {
+        codec.decode_borrowed(arg)
+    }
+}
+
+impl<C: Codec, T: DeserializeOwned> Decode<C> for T {
+    fn decode(codec: &C, arg: &[u8]) -> Result<Self, Error> {
+        codec.decode(arg)
+    }
+
+    #[cfg(feature = "fingerprint")]
+    fn fingerprint() -> Option<u64> {
+        crate::fingerprint::of::<T>()
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -25,6 +126,19 @@ impl Codec for JsonCodec {
             Err(e) => Err(err!("{}", e)),
         }
     }
+
+    fn codec_id(&self) -> u8 {
+        CODEC_JSON
+    }
+
+    fn encode_into<T: Serialize>(&self, arg: T, buf: &mut BytesMut) -> Result<(), Error> {
+        serde_json::to_writer((&mut *buf).writer(), &arg).map_err(|e| err!("{}", e))
+    }
+
+    /// A borrowed `&str` fails on escaped strings, use `Cow<str>` for those.
+    fn decode_borrowed<'de, T: Deserialize<'de>>(&self, arg: &'de [u8]) -> Result<T, Error> {
+        serde_json::from_slice(arg).map_err(|e| err!("{}", e))
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -44,4 +158,263 @@ impl Codec for BinCodec {
             Err(e) => Err(err!("{}", e)),
         }
     }
+
+    fn codec_id(&self) -> u8 {
+        CODEC_BIN
+    }
+
+    fn encode_into<T: Serialize>(&self, arg: T, buf: &mut BytesMut) -> Result<(), Error> {
+        bincode::serialize_into((&mut *buf).writer(), &arg).map_err(|e| err!("{}", e))
+    }
+
+    fn decode_borrowed<'de, T: Deserialize<'de>>(&self, arg: &'de [u8]) -> Result<T, Error> {
+        bincode::deserialize(arg).map_err(|e| err!("{}", e))
+    }
+}
+
+/// MessagePack, compact and schema-less, readable from most languages.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy)]
+pub struct MsgPackCodec {
+    /// encode structs as maps keyed by field name(default), or as arrays of field values.
+    /// Arrays are smaller but both ends must agree on the field order,
+    /// decode accepts both.
+    pub struct_map: bool,
+}
+
+#[cfg(feature = "msgpack")]
+impl MsgPackCodec {
+    /// structs as maps keyed by field name
+    pub fn map() -> Self {
+        Self { struct_map: true }
+    }
+
+    /// structs as arrays of field values
+    pub fn array() -> Self {
+        Self { struct_map: false }
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl Default for MsgPackCodec {
+    fn default() -> Self {
+        Self::map()
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPackCodec {
+    fn encode<T: Serialize>(&self, arg: T) -> Result<Vec<u8>, Error> {
+        let r = if self.struct_map {
+            rmp_serde::to_vec_named(&arg)
+        } else {
+            rmp_serde::to_vec(&arg)
+        };
+        r.map_err(|e| err!("{}", e))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, arg: &[u8]) -> Result<T, Error> {
+        rmp_serde::from_slice(arg).map_err(|e| err!("{}", e))
+    }
+
+    fn codec_id(&self) -> u8 {
+        CODEC_MSGPACK
+    }
+
+    fn encode_into<T: Serialize>(&self, arg: T, buf: &mut BytesMut) -> Result<(), Error> {
+        let mut w = (&mut *buf).writer();
+        let r = if self.struct_map {
+            rmp_serde::encode::write_named(&mut w, &arg)
+        } else {
+            rmp_serde::encode::write(&mut w, &arg)
+        };
+        r.map_err(|e| err!("{}", e))
+    }
+
+    fn decode_borrowed<'de, T: Deserialize<'de>>(&self, arg: &'de [u8]) -> Result<T, Error> {
+        rmp_serde::from_slice(arg).map_err(|e| err!("{}", e))
+    }
+}
+
+/// CBOR(RFC 8949). `decode_borrowed` reads the strings in place, `decode` goes by ciborium.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec {
+    /// prefix every message with the self-describe tag 55799(`0xd9d9f7`),
+    /// decode accepts messages with or without it.
+    pub self_describe: bool,
+}
+
+/// the encoded self-describe tag 55799
+#[cfg(feature = "cbor")]
+pub const CBOR_SELF_DESCRIBE: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+#[cfg(feature = "cbor")]
+impl CborCodec {
+    /// Write the self-describe tag(`CBOR_SELF_DESCRIBE`) before each value,
+    /// a value read is taken with or without it.
+    pub fn self_describe(mut self, self_describe: bool) -> Self {
+        self.self_describe = self_describe;
+        self
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    fn encode<T: Serialize>(&self, arg: T) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![];
+        if self.self_describe {
+            buf.extend_from_slice(&CBOR_SELF_DESCRIBE);
+        }
+        ciborium::into_writer(&arg, &mut buf).map_err(|e| err!("{}", e))?;
+        Ok(buf)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, arg: &[u8]) -> Result<T, Error> {
+        let arg = arg.strip_prefix(&CBOR_SELF_DESCRIBE[..]).unwrap_or(arg);
+        ciborium::from_reader(arg).map_err(|e| err!("{}", e))
+    }
+
+    fn codec_id(&self) -> u8 {
+        CODEC_CBOR
+    }
+
+    fn encode_into<T: Serialize>(&self, arg: T, buf: &mut BytesMut) -> Result<(), Error> {
+        if self.self_describe {
+            buf.extend_from_slice(&CBOR_SELF_DESCRIBE);
+        }
+        ciborium::into_writer(&arg, (&mut *buf).writer()).map_err(|e| err!("{}", e))
+    }
+
+    fn decode_borrowed<'de, T: Deserialize<'de>>(&self, arg: &'de [u8]) -> Result<T, Error> {
+        let arg = arg.strip_prefix(&CBOR_SELF_DESCRIBE[..]).unwrap_or(arg);
+        crate::cbor::from_slice(arg).map_err(|e| err!("{}", e))
+    }
+}
+
+/// Speaks every serde codec of this build with an id, picked per connection by the client
+/// handshake or per call by the `metadata::CODEC` of the request.
+/// The handlers are registered once, each caller is answered in its own codec.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AnyCodec {
+    /// the codec spoken, one of `AnyCodec::ids()`
+    pub id: u8,
+}
+
+impl Default for AnyCodec {
+    fn default() -> Self {
+        Self { id: CODEC_BIN }
+    }
+}
+
+macro_rules! any_codec {
+    ($id:expr, $codec:ident => $e:expr) => {
+        match $id {
+            CODEC_BIN => {
+                let $codec = BinCodec {};
+                $e
+            }
+            CODEC_JSON => {
+                let $codec = JsonCodec {};
+                $e
+            }
+            #[cfg(feature = "msgpack")]
+            CODEC_MSGPACK => {
+                let $codec = MsgPackCodec::default();
+                $e
+            }
+            #[cfg(feature = "cbor")]
+            CODEC_CBOR => {
+                let $codec = CborCodec::default();
+                $e
+            }
+            id => Err(err!("codec id={} not support!", id)),
+        }
+    };
+}
+
+impl AnyCodec {
+    pub fn new(id: u8) -> Self {
+        Self { id }
+    }
+
+    /// the codec ids this build speaks
+    pub fn ids() -> Vec<u8> {
+        vec![
+            CODEC_BIN,
+            CODEC_JSON,
+            #[cfg(feature = "msgpack")]
+            CODEC_MSGPACK,
+            #[cfg(feature = "cbor")]
+            CODEC_CBOR,
+        ]
+    }
+}
+
+impl Codec for AnyCodec {
+    fn encode<T: Serialize>(&self, arg: T) -> Result<Vec<u8>, Error> {
+        any_codec!(self.id, c => c.encode(arg))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, arg: &[u8]) -> Result<T, Error> {
+        any_codec!(self.id, c => c.decode(arg))
+    }
+
+    fn codec_id(&self) -> u8 {
+        self.id
+    }
+
+    fn select_codec(&self, id: u8) -> Option<Self> {
+        Self::ids().contains(&id).then_some(Self { id })
+    }
+
+    fn encode_into<T: Serialize>(&self, arg: T, buf: &mut BytesMut) -> Result<(), Error> {
+        any_codec!(self.id, c => c.encode_into(arg, buf))
+    }
+
+    fn decode_borrowed<'de, T: Deserialize<'de>>(&self, arg: &'de [u8]) -> Result<T, Error> {
+        any_codec!(self.id, c => c.decode_borrowed(arg))
+    }
+}
+
+/// Protobuf, for `prost::Message` types instead of serde ones.
+/// Decoding is always owned.
+#[cfg(feature = "prost")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProstCodec {}
+
+#[cfg(feature = "prost")]
+impl Format for ProstCodec {
+    fn id(&self) -> u8 {
+        CODEC_PROST
+    }
+
+    fn select(&self, id: u8) -> Option<Self> {
+        (id == CODEC_PROST).then_some(*self)
+    }
+}
+
+#[cfg(feature = "prost")]
+impl<T: prost::Message> Encode<ProstCodec> for T {
+    fn encode(self, _codec: &ProstCodec) -> Result<Vec<u8>, Error> {
+        Ok(self.encode_to_vec())
+    }
+
+    fn encode_into(self, _codec: &ProstCodec, buf: &mut BytesMut) -> Result<(), Error> {
+        prost::Message::encode(&self, buf).map_err(|e| err!("{}", e))
+    }
+}
+
+#[cfg(feature = "prost")]
+impl<'de, T: prost::Message + Default> DecodeBorrowed<'de, ProstCodec> for T {
+    fn decode_borrowed(codec: &ProstCodec, arg: &'de [u8]) -> Result<Self, Error> {
+        <T as Decode<ProstCodec>>::decode(codec, arg)
+    }
+}
+
+#[cfg(feature = "prost")]
+impl<T: prost::Message + Default> Decode<ProstCodec> for T {
+    fn decode(_codec: &ProstCodec, arg: &[u8]) -> Result<Self, Error> {
+        <T as prost::Message>::decode(arg).map_err(|e| err!("{}", e))
+    }
 }
@@ -0,0 +1,88 @@
+use dark_std::err;
+use dark_std::errors::Result;
+
+/// Payload compression algorithm, negotiated at connection setup.
+/// A frame with `FLAG_COMPRESSED` is compressed by the algorithm the connection agreed on.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Compression {
+    /// lz4 block format, needs the `lz4` feature
+    Lz4,
+    /// zstd, needs the `zstd` feature
+    Zstd,
+}
+
+impl Compression {
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The algorithms compiled into this build, in preference order.
+    pub fn supported() -> Vec<Compression> {
+        vec![
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd,
+        ]
+    }
+
+    #[allow(unused_variables)]
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)
+                .map_err(|e| err!("{}", e)),
+            #[allow(unreachable_patterns)]
+            _ => Err(err!("compression {:?} is not enabled!", self)),
+        }
+    }
+
+    /// Decompress `data` into at most `max` bytes, a longer output is an error
+    /// found before it is allocated, so a peer cannot send a decompression bomb.
+    #[allow(unused_variables)]
+    pub fn decompress(&self, data: &[u8], max: usize) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                // the size the peer prepended, trusted only below the max
+                let len = data
+                    .get(..4)
+                    .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]) as usize)
+                    .ok_or_else(|| err!("lz4 data is too short!"))?;
+                if len > max {
+                    return Err(err!("decompressed length {} is over the max {}", len, max));
+                }
+                lz4_flex::decompress_size_prepended(data).map_err(|e| err!("{}", e))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                use std::io::Read;
+                let mut out = vec![];
+                zstd::stream::read::Decoder::new(data)
+                    .map_err(|e| err!("{}", e))?
+                    .take(max as u64 + 1)
+                    .read_to_end(&mut out)
+                    .map_err(|e| err!("{}", e))?;
+                if out.len() > max {
+                    return Err(err!("decompressed length is over the max {}", max));
+                }
+                Ok(out)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(err!("compression {:?} is not enabled!", self)),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use dark_std::errors::{Error, Result};
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::metadata::Metadata;
+
+/// The call a handler answers, taken by the handlers of `Server::register_fn`
+/// before their request, with the other extractors(`FromContext`):
+/// ```rust
+/// use drpc::context::{Context, State};
+/// use drpc::server::Server;
+///
+/// let mut s = Server::default().set_state(String::from("db"));
+/// s.register_fn("who", |ctx: Context, State(db): State<String>, req: i32| async move {
+///     Ok(format!("{} {} from {:?} in {}", ctx.method, req, ctx.peer_addr, db))
+/// })
+/// .unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct Context {
+    /// the id of the call on its connection
+    pub id: u64,
+    pub method: String,
+    pub metadata: Metadata,
+    /// the address of the caller, when the server knows it(`Server::call_from`)
+    pub peer_addr: Option<SocketAddr>,
+    state: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl Context {
+    pub fn new(
+        id: u64,
+        method: &str,
+        metadata: Metadata,
+        peer_addr: Option<SocketAddr>,
+        state: Option<Arc<dyn Any + Send + Sync>>,
+    ) -> Self {
+        Self {
+            id,
+            method: method.to_string(),
+            metadata,
+            peer_addr,
+            state,
+        }
+    }
+
+    /// The state of the server(`Server::set_state`), `None` when it is not a `T`.
+    pub fn state<T: Clone + 'static>(&self) -> Option<T> {
+        self.state.as_ref()?.downcast_ref::<T>().cloned()
+    }
+}
+
+impl Debug for Context {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Context")
+            .field("id", &self.id)
+            .field("method", &self.method)
+            .field("metadata", &self.metadata)
+            .field("peer_addr", &self.peer_addr)
+            .finish()
+    }
+}
+
+/// A handler argument taken from the call, a failure answers the call with its error.
+/// A handler of `Server::register_fn` takes up to 3 before its request.
+pub trait FromContext: Sized {
+    fn from_context(ctx: &Context) -> Result<Self>;
+
+    /// Check at registration that the server can give it, `state` is the one of `Server::set_state`.
+    fn check(_state: Option<&(dyn Any + Send + Sync)>) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl FromContext for Context {
+    fn from_context(ctx: &Context) -> Result<Self> {
+        Ok(ctx.clone())
+    }
+}
+
+impl FromContext for Metadata {
+    fn from_context(ctx: &Context) -> Result<Self> {
+        Ok(ctx.metadata.clone())
+    }
+}
+
+/// The state of the server(`Server::set_state`), e.g. an `Arc` of a database pool.
+/// A handler taking a `State<T>` fails to register unless the state is a `T`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct State<T>(pub T);
+
+impl<T: Clone + 'static> FromContext for State<T> {
+    fn from_context(ctx: &Context) -> Result<Self> {
+        ctx.state::<T>().map(State).ok_or_else(not_set::<T>)
+    }
+
+    fn check(state: Option<&(dyn Any + Send + Sync)>) -> Result<()> {
+        match state {
+            Some(state) if state.is::<T>() => Ok(()),
+            _ => Err(not_set::<T>()),
+        }
+    }
+}
+
+fn not_set<T>() -> Error {
+    Error::from(format!("state {} is not set!", std::any::type_name::<T>()))
+}
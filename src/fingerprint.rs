@@ -0,0 +1,100 @@
+use serde::de::DeserializeOwned;
+use serde_reflection::{
+    self as reflection, ContainerFormat, FormatHolder, Registry, Samples, Tracer, TracerConfig,
+};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use crate::codec::{Decode, Format};
+
+type Cache = Mutex<HashMap<(TypeId, TypeId, TypeId), Option<u64>>>;
+
+/// The fingerprint of a serde type, a 64 bits FNV-1a hash of its shape traced by serde-reflection.
+/// Field and variant names are part of the shape, the names of the Rust types are not:
+/// a type renamed with the same fields keeps its fingerprint.
+/// `None` when the type can not be traced, e.g. its `Deserialize` checks the values.
+pub fn of<T: DeserializeOwned>() -> Option<u64> {
+    let mut tracer = Tracer::new(TracerConfig::default());
+    let samples = Samples::new();
+    let (format, _) = tracer.trace_type::<T>(&samples).ok()?;
+    let registry = tracer.registry().ok()?;
+    let shape = bincode::serialize(&structural(format, &registry)?).ok()?;
+    Some(fnv1a(&shape))
+}
+
+/// The containers reached from `format` in the order they are found, each one named
+/// by its position, so only the structure is left. Recursive types refer to their position.
+fn structural(
+    mut format: reflection::Format,
+    registry: &Registry,
+) -> Option<(reflection::Format, Vec<ContainerFormat>)> {
+    let mut names: Vec<String> = vec![];
+    format.visit(&mut |f| type_name(f, &mut names)).ok()?;
+    let mut containers: Vec<ContainerFormat> = vec![];
+    while let Some(name) = names.get(containers.len()) {
+        let container = registry.get(name)?.clone();
+        container.visit(&mut |f| type_name(f, &mut names)).ok()?;
+        containers.push(container);
+    }
+    let mut rename = |f: &mut reflection::Format| {
+        if let reflection::Format::TypeName(name) = f {
+            if let Some(at) = names.iter().position(|v| v == name) {
+                *name = at.to_string();
+            }
+        }
+        Ok(())
+    };
+    format.visit_mut(&mut rename).ok()?;
+    for container in &mut containers {
+        container.visit_mut(&mut rename).ok()?;
+    }
+    Some((format, containers))
+}
+
+/// Add the container named by `f` to `names`, once.
+fn type_name(f: &reflection::Format, names: &mut Vec<String>) -> reflection::Result<()> {
+    if let reflection::Format::TypeName(name) = f {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    Ok(())
+}
+
+/// The fingerprint of a method taking `Req` and answering `Resp`, `None` when either has none.
+pub fn method<C: Format, Req: Decode<C>, Resp: Decode<C>>() -> Option<u64> {
+    let req = Req::fingerprint()?;
+    let resp = Resp::fingerprint()?;
+    let mut buf = [0u8; 16];
+    buf[..8].copy_from_slice(&req.to_be_bytes());
+    buf[8..].copy_from_slice(&resp.to_be_bytes());
+    Some(fnv1a(&buf))
+}
+
+/// Same as `method`, traced once per types.
+pub fn method_cached<C, Req, Resp>() -> Option<u64>
+where
+    C: Format + 'static,
+    Req: Decode<C> + 'static,
+    Resp: Decode<C> + 'static,
+{
+    static CACHE: OnceLock<Cache> = OnceLock::new();
+    let key = (TypeId::of::<C>(), TypeId::of::<Req>(), TypeId::of::<Resp>());
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(v) = cache.lock().unwrap().get(&key) {
+        return *v;
+    }
+    let v = method::<C, Req, Resp>();
+    cache.lock().unwrap().insert(key, v);
+    v
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
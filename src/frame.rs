@@ -1,11 +1,12 @@
-use byteorder::{BigEndian, WriteBytesExt};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use log::debug;
-use std::future::Future;
+use std::ops::Range;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
 
-// Frame layout
+// Frame layout(v1)
 // id(u64) + ok(u8) + len(u64) + payload([u8; len])
 
 // req frame layout
@@ -14,6 +15,76 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 // rsp frame layout(ok=0,payload is string,ok=1,payload is data)
 // id(u64) + ok(u8) + len(u64) + payload/string ([u8; len])
 
+// Frame layout(v2)
+// flags(u8) + id(varint) + [method_id(u64)] + [meta_len(varint) + metadata([u8; meta_len])] + len(varint) + payload([u8; len])
+// the method id is only present when FLAG_METHOD_ID is set, the payload is then the bare arg_data.
+// it is a hash, so it goes as 8 bytes rather than a varint of up to 10.
+// a small call by method id has an 11 bytes header and a small response 3, a v1 header is 17.
+// the metadata block is only present when FLAG_METADATA is set.
+// v2 frames always have FLAG_V2 set, v1 frames start with the high byte of the id,
+// so the first byte tells the two versions apart.
+// when the connection negotiated a checksum, a v2 frame ends with crc32c(u32) of all bytes before it,
+// and a v1 frame is refused.
+
+// streams, all frames of a stream share the id of its request
+// request: FLAG_STREAM set, the caller reads a stream of responses
+// item: FLAG_STREAM, payload is one response
+// end: FLAG_STREAM | FLAG_CONTROL, ok=1 the stream is done, ok=0 payload is an error string
+// the caller grants items with CONTROL_WINDOW frames and stops the stream with CONTROL_CANCEL
+
+// notifications: a request with FLAG_ONEWAY set is served and never answered
+
+/// the response is ok(payload is data), otherwise payload is an error string
+pub const FLAG_OK: u8 = 0b0000_0001;
+/// payload is compressed
+pub const FLAG_COMPRESSED: u8 = 0b0000_0010;
+/// frame carries a metadata block before the payload
+pub const FLAG_METADATA: u8 = 0b0000_0100;
+/// frame is part of a stream
+pub const FLAG_STREAM: u8 = 0b0000_1000;
+/// frame is a control frame, not a request/response
+pub const FLAG_CONTROL: u8 = 0b0001_0000;
+/// request carries a numeric method id in the header instead of a method name in the payload
+pub const FLAG_METHOD_ID: u8 = 0b0010_0000;
+/// request expects no response, see `ClientStub::notify`
+pub const FLAG_ONEWAY: u8 = 0b0100_0000;
+/// marks a v2 frame
+pub const FLAG_V2: u8 = 0b1000_0000;
+
+/// the first byte of a handshake, see `Handshake`. Control frames never carry it
+pub const CONTROL_HANDSHAKE: u8 = 1;
+/// control frame kind: the caller can take `n`(varint) more items of the stream with the frame id
+pub const CONTROL_WINDOW: u8 = 2;
+/// control frame kind: the caller dropped the stream with the frame id
+pub const CONTROL_CANCEL: u8 = 3;
+
+/// the max length of a u64 varint
+const MAX_VARINT_LEN: usize = 10;
+
+/// the header length of a v1 frame
+const V1_HEAD_LEN: usize = 17;
+
+/// the shortest header of any frame: a v2 flags(u8) + id(varint) + len(varint)
+const MIN_HEAD_LEN: usize = 3;
+
+/// the max length of the header before the metadata block: flags, id, method id and metadata length
+const PREFIX_LEN: usize = 1 + MAX_VARINT_LEN + 8 + MAX_VARINT_LEN;
+
+/// the longest frame a `FrameCodec` reads unless set, 64 MiB
+pub const DEFAULT_MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// frame wire version
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum FrameVersion {
+    /// fixed 17 bytes header: id(u64) + ok(u8) + len(u64), the default.
+    /// Every server reads it, a client switches to v2 once the handshake is agreed
+    #[default]
+    V1,
+    /// flags(u8) + id(varint) + [method_id(u64)] + [metadata] + len(varint),
+    /// see the layout at the top of this file
+    V2,
+}
+
 /// raw frame wrapper, low level protocol
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Frame {
@@ -21,8 +92,22 @@ pub struct Frame {
     pub id: u64,
     /// is ok,false=0, true = 1
     pub ok: u8,
-    /// payload data
-    pub data: Vec<u8>,
+    /// v2 flags besides `FLAG_OK`, see `FLAG_*`. v1 frames can not carry them.
+    pub flags: u8,
+    /// the numeric method id of a request, only v2 frames carry it
+    pub method_id: Option<u64>,
+    /// opaque metadata block, only v2 frames carry it
+    pub metadata: Bytes,
+    /// payload data, a decoded frame shares it with the read buffer
+    pub data: Bytes,
+    /// the wire version, a decoded frame keeps the version it was read with
+    pub version: FrameVersion,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Frame {
@@ -30,27 +115,51 @@ impl Frame {
         Self {
             id: 0,
             ok: 0,
-            data: vec![],
+            flags: 0,
+            method_id: None,
+            metadata: Bytes::new(),
+            data: Bytes::new(),
+            version: FrameVersion::V1,
         }
     }
 
-    /// Decode a frame from the reader.
+    /// Is the flag set on this frame
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag == flag
+    }
+
+    /// Decode a frame from the reader, both v1 and v2 frames are accepted.
     pub async fn decode_from<R: AsyncRead + Unpin>(r: &mut R) -> std::io::Result<Self> {
-        let id = r.read_u64().await?;
-        debug!("decode id = {:?}", id);
-
-        let ok = r.read_u8().await?;
-        debug!("decode ok = {:?}", ok);
-
-        let len = r.read_u64().await?;
-        debug!("decode len = {:?}", len);
-        let mut datas = Vec::with_capacity(len as usize);
-        unsafe { datas.set_len(len as usize) }; // it's safety,avoid one memset
-        r.read_exact(&mut datas).await?;
-        Ok(Frame {
-            id,
-            ok,
-            data: datas,
+        Self::decode_with(r, false).await
+    }
+
+    /// Decode a frame from the reader, `checksum` verifies the crc32c trailer of v2 frames.
+    /// A mismatch is an `InvalidData` error wrapping a `ChecksumError`, a v1 frame
+    /// is an `InvalidData` error too.
+    /// It reads exactly one frame of at most `DEFAULT_MAX_FRAME_LEN`, use `FrameCodec`
+    /// to decode a connection.
+    pub async fn decode_with<R: AsyncRead + Unpin>(
+        r: &mut R,
+        checksum: bool,
+    ) -> std::io::Result<Self> {
+        let mut codec = FrameCodec::default().set_checksum(checksum);
+        let mut buf = BytesMut::with_capacity(V1_HEAD_LEN);
+        // read at least what the header still takes in one call, never past this frame
+        let mut need = MIN_HEAD_LEN;
+        let head = loop {
+            let start = buf.len();
+            buf.resize(need, 0);
+            r.read_exact(&mut buf[start..]).await?;
+            match Head::parse(&buf, checksum, codec.max_frame_len)? {
+                Parse::Need(n) => need = n,
+                Parse::Head(head) => break head,
+            }
+        };
+        let start = buf.len();
+        buf.resize(head.frame_len, 0);
+        r.read_exact(&mut buf[start..]).await?;
+        codec.decode(&mut buf)?.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "frame is incomplete")
         })
     }
 
@@ -62,36 +171,391 @@ impl Frame {
 
     /// Convert self into raw buf that can be send as a frame
     pub fn finish(self, id: u64) -> Vec<u8> {
-        let len = self.data.len() as u64;
-        let mut buf = Vec::with_capacity((17 + len) as usize);
-        let _ = WriteBytesExt::write_u64::<BigEndian>(&mut buf, id);
-        let _ = WriteBytesExt::write_u8(&mut buf, self.ok as u8);
-        let _ = WriteBytesExt::write_u64::<BigEndian>(&mut buf, len);
-        buf.extend(self.data);
-        buf
+        self.finish_with(id, false)
+    }
+
+    /// Same as `finish`, `checksum` appends the crc32c trailer to v2 frames.
+    pub fn finish_with(mut self, id: u64, checksum: bool) -> Vec<u8> {
+        self.id = id;
+        let mut buf = BytesMut::new();
+        let _ = FrameCodec::default()
+            .set_checksum(checksum)
+            .encode(self, &mut buf);
+        buf.to_vec()
+    }
+
+    /// Write the frame into `w`, the header and the payload go out in one vectored write
+    /// without copying the payload.
+    pub async fn write_to<W: AsyncWrite + Unpin>(
+        self,
+        w: &mut W,
+        checksum: bool,
+    ) -> std::io::Result<()> {
+        // the header goes on the stack, the metadata block and the payload are not copied
+        let mut prefix = [0u8; PREFIX_LEN];
+        let mut rest = &mut prefix[..];
+        self.encode_prefix(&mut rest);
+        let prefix_len = PREFIX_LEN - rest.len();
+        let mut len = [0u8; MAX_VARINT_LEN];
+        let mut rest = &mut len[..];
+        self.encode_len(&mut rest);
+        let len_len = MAX_VARINT_LEN - rest.len();
+        let (prefix, len) = (&prefix[..prefix_len], &len[..len_len]);
+        let mut trailer = [0u8; 4];
+        let trailer_len = if checksum && self.version == FrameVersion::V2 {
+            let mut crc = crc32c::crc32c(prefix);
+            crc = crc32c::crc32c_append(crc, &self.metadata);
+            crc = crc32c::crc32c_append(crc, len);
+            crc = crc32c::crc32c_append(crc, &self.data);
+            trailer = crc.to_be_bytes();
+            4
+        } else {
+            0
+        };
+        let mut buf = Buf::chain(prefix, self.metadata)
+            .chain(len)
+            .chain(self.data)
+            .chain(&trailer[..trailer_len]);
+        w.write_all_buf(&mut buf).await
+    }
+
+    /// the max length of the header, the metadata block included
+    fn head_len(&self) -> usize {
+        PREFIX_LEN + MAX_VARINT_LEN + self.metadata.len()
+    }
+
+    /// Encode the header up to the metadata block, the whole header of a v1 frame
+    fn encode_prefix<B: BufMut>(&self, buf: &mut B) {
+        match self.version {
+            FrameVersion::V1 => {
+                buf.put_u64(self.id);
+                buf.put_u8(self.ok);
+                buf.put_u64(self.data.len() as u64);
+            }
+            FrameVersion::V2 => {
+                let mut flags = (self.flags & !(FLAG_OK | FLAG_METADATA | FLAG_METHOD_ID))
+                    | FLAG_V2
+                    | (self.ok & FLAG_OK);
+                if self.method_id.is_some() {
+                    flags |= FLAG_METHOD_ID;
+                }
+                if !self.metadata.is_empty() {
+                    flags |= FLAG_METADATA;
+                }
+                buf.put_u8(flags);
+                put_varint(buf, self.id);
+                if let Some(method_id) = self.method_id {
+                    buf.put_u64(method_id);
+                }
+                if !self.metadata.is_empty() {
+                    put_varint(buf, self.metadata.len() as u64);
+                }
+            }
+        }
+    }
+
+    /// Encode the header after the metadata block, the payload length of a v2 frame
+    fn encode_len<B: BufMut>(&self, buf: &mut B) {
+        if self.version == FrameVersion::V2 {
+            put_varint(buf, self.data.len() as u64);
+        }
+    }
+}
+
+/// Frame codec for `tokio_util::codec::Framed`, one per connection.
+/// Decoded payloads are split off the read buffer, no copy.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameCodec {
+    /// v2 frames carry a crc32c trailer, negotiated at connection setup
+    pub checksum: bool,
+    /// the longest frame read, trailer included. A longer one is an `InvalidData` error
+    /// before anything is allocated for it
+    pub max_frame_len: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self {
+            checksum: false,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl FrameCodec {
+    pub fn set_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn set_max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let head = match Head::parse(src, self.checksum, self.max_frame_len)? {
+            Parse::Need(n) => {
+                src.reserve(n - src.len());
+                return Ok(None);
+            }
+            Parse::Head(head) => head,
+        };
+        if src.len() < head.frame_len {
+            src.reserve(head.frame_len - src.len());
+            return Ok(None);
+        }
+        let buf = src.split_to(head.frame_len).freeze();
+        if self.checksum {
+            let crc_at = head.frame_len - 4;
+            let expected = (&buf[crc_at..]).get_u32();
+            let actual = crc32c::crc32c(&buf[..crc_at]);
+            if expected != actual {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    ChecksumError { expected, actual },
+                ));
+            }
+        }
+        debug!("decode id = {:?}, len = {:?}", head.id, head.data.len());
+        Ok(Some(Frame {
+            id: head.id,
+            ok: head.ok,
+            flags: head.flags,
+            method_id: head.method_id,
+            metadata: buf.slice(head.metadata),
+            data: buf.slice(head.data),
+            version: head.version,
+        }))
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let start = dst.len();
+        dst.reserve(item.head_len() + item.data.len() + 4);
+        item.encode_prefix(dst);
+        dst.extend_from_slice(&item.metadata);
+        item.encode_len(dst);
+        dst.extend_from_slice(&item.data);
+        if self.checksum && item.version == FrameVersion::V2 {
+            let crc = crc32c::crc32c(&dst[start..]);
+            dst.put_u32(crc);
+        }
+        Ok(())
+    }
+}
+
+/// The parsed header of a frame at the start of a buffer
+struct Head {
+    version: FrameVersion,
+    id: u64,
+    ok: u8,
+    flags: u8,
+    method_id: Option<u64>,
+    metadata: Range<usize>,
+    data: Range<usize>,
+    /// the whole frame length, trailer included
+    frame_len: usize,
+}
+
+/// The result of `Head::parse`
+enum Parse {
+    Head(Head),
+    /// the header is at least this long, more than the buffer holds
+    Need(usize),
+}
+
+impl Head {
+    /// Parse the header, `Parse::Need` when `buf` does not hold the whole header yet.
+    /// A frame longer than `max` is an error.
+    fn parse(buf: &[u8], checksum: bool, max: usize) -> std::io::Result<Parse> {
+        let first = match buf.first() {
+            None => return Ok(Parse::Need(MIN_HEAD_LEN)),
+            Some(first) => *first,
+        };
+        if first & FLAG_V2 == 0 {
+            if checksum {
+                // v1 frames have no trailer, one bit would turn off the check
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "v1 frame on a checksummed connection",
+                ));
+            }
+            if buf.len() < V1_HEAD_LEN {
+                return Ok(Parse::Need(V1_HEAD_LEN));
+            }
+            let mut head = &buf[..V1_HEAD_LEN];
+            let id = head.get_u64();
+            let ok = head.get_u8();
+            let len = to_len(head.get_u64())?;
+            let frame_len = max_len(checked_len(V1_HEAD_LEN, len)?, max)?;
+            return Ok(Parse::Head(Head {
+                version: FrameVersion::V1,
+                id,
+                ok,
+                flags: 0,
+                method_id: None,
+                metadata: V1_HEAD_LEN..V1_HEAD_LEN,
+                data: V1_HEAD_LEN..frame_len,
+                frame_len,
+            }));
+        }
+        // the least bytes the fields after the id take, an unfinished varint takes one more byte
+        let method_len = if first & FLAG_METHOD_ID != 0 { 8 } else { 0 };
+        let meta_len_len = if first & FLAG_METADATA != 0 { 1 } else { 0 };
+        let mut pos = 1;
+        let id = match get_varint(buf, &mut pos)? {
+            None => return Ok(Parse::Need(buf.len() + 1 + method_len + meta_len_len + 1)),
+            Some(id) => id,
+        };
+        let mut method_id = None;
+        if method_len != 0 {
+            match buf.get(pos..pos + 8) {
+                None => return Ok(Parse::Need(pos + 8 + meta_len_len + 1)),
+                Some(mut v) => method_id = Some(v.get_u64()),
+            }
+            pos += 8;
+        }
+        let mut metadata = pos..pos;
+        if meta_len_len != 0 {
+            let meta_len = match get_varint(buf, &mut pos)? {
+                None => return Ok(Parse::Need(buf.len() + 1 + 1)),
+                Some(v) => to_len(v)?,
+            };
+            let end = max_len(checked_len(pos, meta_len)?, max)?;
+            metadata = pos..end;
+            pos = end;
+            if buf.len() < pos {
+                return Ok(Parse::Need(pos + 1));
+            }
+        }
+        let len = match get_varint(buf, &mut pos)? {
+            None => return Ok(Parse::Need(buf.len() + 1)),
+            Some(v) => to_len(v)?,
+        };
+        let end = checked_len(pos, len)?;
+        let frame_len = max_len(if checksum { checked_len(end, 4)? } else { end }, max)?;
+        Ok(Parse::Head(Head {
+            version: FrameVersion::V2,
+            id,
+            ok: first & FLAG_OK,
+            flags: first & !(FLAG_OK | FLAG_V2),
+            method_id,
+            metadata,
+            data: pos..end,
+            frame_len,
+        }))
+    }
+}
+
+fn to_len(v: u64) -> std::io::Result<usize> {
+    usize::try_from(v).map_err(|_| too_long())
+}
+
+fn checked_len(a: usize, b: usize) -> std::io::Result<usize> {
+    a.checked_add(b).ok_or_else(too_long)
+}
+
+fn max_len(len: usize, max: usize) -> std::io::Result<usize> {
+    if len > max {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame length {} is over the max {}", len, max),
+        ));
+    }
+    Ok(len)
+}
+
+fn too_long() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "frame is too long")
+}
+
+/// The crc32c trailer of a frame does not match its content.
+/// The frame was corrupted on the way, the connection should be closed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ChecksumError {
+    /// the checksum carried by the frame
+    pub expected: u32,
+    /// the checksum of the received bytes
+    pub actual: u32,
+}
+
+impl ChecksumError {
+    /// Find the `ChecksumError` inside an error returned by `Frame::decode_with` or `FrameCodec`
+    pub fn from_io(e: &std::io::Error) -> Option<&ChecksumError> {
+        e.get_ref().and_then(|e| e.downcast_ref::<ChecksumError>())
+    }
+}
+
+impl std::fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "frame checksum mismatch: expected = {:#010x}, actual = {:#010x}",
+            self.expected, self.actual
+        )
     }
 }
 
+impl std::error::Error for ChecksumError {}
+
+/// Append `v` as an unsigned LEB128 varint
+pub fn put_varint<B: BufMut>(buf: &mut B, mut v: u64) {
+    while v >= 0x80 {
+        buf.put_u8((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.put_u8(v as u8);
+}
+
+/// Read an unsigned LEB128 varint at `pos`, `None` when `buf` ends before it does.
+pub fn get_varint(buf: &[u8], pos: &mut usize) -> std::io::Result<Option<u64>> {
+    let mut v = 0u64;
+    for i in 0..MAX_VARINT_LEN {
+        let b = match buf.get(*pos + i) {
+            None => return Ok(None),
+            Some(b) => *b,
+        };
+        // the 10th byte holds only the top bit of a u64
+        if i == MAX_VARINT_LEN - 1 && b > 1 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "varint overflows a u64",
+            ));
+        }
+        v |= ((b & 0x7f) as u64) << (i * 7);
+        if b & 0x80 == 0 {
+            *pos += i + 1;
+            return Ok(Some(v));
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "varint is too long",
+    ))
+}
+
 impl AsyncWrite for Frame {
     fn poll_write(
         mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
+        _cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, std::io::Error>> {
-        let mut p = Box::pin(AsyncWriteExt::write(&mut self.data, buf));
-        loop {
-            match Pin::new(&mut p).poll(cx) {
-                Poll::Ready(v) => match v {
-                    Ok(v) => {
-                        return Poll::Ready(Ok(v));
-                    }
-                    Err(e) => {
-                        return Poll::Ready(Err(e));
-                    }
-                },
-                Poll::Pending => {}
-            }
-        }
+        // grow the payload in place while no one else holds it, amortized like a Vec
+        let mut data = std::mem::take(&mut self.data)
+            .try_into_mut()
+            .unwrap_or_else(|data| BytesMut::from(&data[..]));
+        data.extend_from_slice(buf);
+        self.data = data.freeze();
+        Poll::Ready(Ok(buf.len()))
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
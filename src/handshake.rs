@@ -0,0 +1,165 @@
+use bytes::Bytes;
+use dark_std::err;
+use dark_std::errors::Result;
+
+use crate::compress::Compression;
+use crate::frame::{Frame, FrameVersion, CONTROL_HANDSHAKE};
+
+// handshake payload layout
+// kind(u8 = CONTROL_HANDSHAKE) + compressions_len(u8) + compressions([u8; compressions_len]) + checksum(u8) + codec(u8, 0 = none)
+// unknown trailing bytes are ignored, so newer peers can append fields
+
+// the handshake goes in v1 frames, the only ones a server from before it reads
+// request: a call of `METHOD`, payload = "drpc.handshake\n" + handshake
+// answer: ok=1, payload = the agreed handshake
+// a server from before the handshake answers it as an unknown method, the client then
+// stays on v1 frames. An agreed handshake switches both sides to v2 frames.
+
+/// the method a handshake request calls
+pub const METHOD: &str = "drpc.handshake";
+
+/// Connection options negotiated when a client dials a server.
+/// The client sends what it supports, the server answers with what the connection will use.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Handshake {
+    /// payload compressions, in preference order
+    pub compressions: Vec<Compression>,
+    /// crc32c trailer on every frame after the handshake
+    pub checksum: bool,
+    /// the `codec::CODEC_*` id the calls are encoded with, when the server speaks it
+    pub codec: Option<u8>,
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        Self {
+            compressions: Compression::supported(),
+            checksum: false,
+            codec: None,
+        }
+    }
+}
+
+impl Handshake {
+    /// A handshake that asks for nothing, the connection uses the defaults.
+    pub fn none() -> Self {
+        Self {
+            compressions: vec![],
+            checksum: false,
+            codec: None,
+        }
+    }
+
+    pub fn compressions(mut self, compressions: Vec<Compression>) -> Self {
+        self.compressions = compressions;
+        self
+    }
+
+    /// Ask for a crc32c checksum on each frame of the connection,
+    /// a corrupted frame or a v1 frame without it then closes the connection.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn codec(mut self, codec: Option<u8>) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// The compression the connection agreed on.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compressions.first().copied()
+    }
+
+    /// Pick the options of `req` that `self` supports, the client's preference wins.
+    /// The codec is left to the caller, it depends on the server's `Format`.
+    pub fn accept(&self, req: &Handshake) -> Handshake {
+        Handshake {
+            compressions: req
+                .compressions
+                .iter()
+                .find(|x| self.compressions.contains(x))
+                .map(|x| vec![*x])
+                .unwrap_or_default(),
+            checksum: req.checksum && self.checksum,
+            codec: None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + self.compressions.len());
+        buf.push(CONTROL_HANDSHAKE);
+        buf.push(self.compressions.len() as u8);
+        for x in &self.compressions {
+            buf.push(x.id());
+        }
+        buf.push(self.checksum as u8);
+        buf.push(self.codec.unwrap_or(0));
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.first() != Some(&CONTROL_HANDSHAKE) {
+            return Err(err!("not a handshake!"));
+        }
+        let len = *buf.get(1).ok_or_else(|| err!("handshake is too short!"))? as usize;
+        let ids = buf
+            .get(2..2 + len)
+            .ok_or_else(|| err!("handshake is too short!"))?;
+        Ok(Self {
+            // skip the algorithms this build does not know
+            compressions: ids
+                .iter()
+                .filter_map(|x| Compression::from_id(*x))
+                .collect(),
+            checksum: buf.get(2 + len).map(|x| *x == 1).unwrap_or(false),
+            codec: buf.get(3 + len).copied().filter(|x| *x != 0),
+        })
+    }
+
+    /// Is the frame a handshake request
+    pub fn is_handshake(frame: &Frame) -> bool {
+        frame.version == FrameVersion::V1
+            && frame
+                .data
+                .strip_prefix(METHOD.as_bytes())
+                .and_then(|x| x.first())
+                == Some(&b'\n')
+    }
+
+    /// Decode the handshake of a request, see `is_handshake`
+    pub fn from_request(frame: &Frame) -> Result<Self> {
+        Self::decode(frame.data.get(METHOD.len() + 1..).unwrap_or_default())
+    }
+
+    /// Decode the handshake of an answer, `None` when the server does not speak the handshake
+    pub fn from_answer(frame: &Frame) -> Option<Self> {
+        if frame.ok != 1 {
+            return None;
+        }
+        Self::decode(&frame.data).ok()
+    }
+
+    /// Wrap self into a handshake request
+    pub fn into_frame(self) -> Frame {
+        let mut data = Vec::with_capacity(METHOD.len() + 5 + self.compressions.len());
+        data.extend_from_slice(METHOD.as_bytes());
+        data.push(b'\n');
+        data.extend(self.encode());
+        let mut f = Frame::new();
+        f.version = FrameVersion::V1;
+        f.data = Bytes::from(data);
+        f
+    }
+
+    /// Wrap self into the answer of the request `id`
+    pub fn into_answer(self, id: u64) -> Frame {
+        let mut f = Frame::new();
+        f.id = id;
+        f.ok = 1;
+        f.version = FrameVersion::V1;
+        f.data = Bytes::from(self.encode());
+        f
+    }
+}
@@ -0,0 +1,102 @@
+use bytes::Bytes;
+use dark_std::errors::Result;
+use futures::future::BoxFuture;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use crate::metadata::Metadata;
+
+/// An outgoing call as the interceptors see it, its metadata may be changed.
+#[derive(Debug, Clone)]
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub metadata: Metadata,
+    /// the encoded request, before compression
+    pub body: Bytes,
+}
+
+/// Runs around the unary calls and notifications of a client, see `Client::add_interceptor`.
+/// It may change the request, answer instead of calling `next` or rewrite its result.
+/// The responses it sees are encoded, a notification answers an empty one.
+pub trait Interceptor: Send + Sync {
+    fn intercept<'a>(&'a self, req: Request<'a>, next: Next<'a>) -> BoxFuture<'a, Result<Bytes>>;
+}
+
+/// An interceptor of a function, see `from_fn`.
+pub struct FromFn<F>(F);
+
+/// An interceptor of a function or a closure:
+/// ```rust
+/// use drpc::interceptor::from_fn;
+///
+/// let auth = from_fn(|mut req, next| {
+///     Box::pin(async move {
+///         req.metadata.insert("token", "secret");
+///         next.run(req).await
+///     })
+/// });
+/// ```
+pub fn from_fn<F>(f: F) -> FromFn<F>
+where
+    F: for<'a> Fn(Request<'a>, Next<'a>) -> BoxFuture<'a, Result<Bytes>> + Send + Sync,
+{
+    FromFn(f)
+}
+
+impl<F> Interceptor for FromFn<F>
+where
+    F: for<'a> Fn(Request<'a>, Next<'a>) -> BoxFuture<'a, Result<Bytes>> + Send + Sync,
+{
+    fn intercept<'a>(&'a self, req: Request<'a>, next: Next<'a>) -> BoxFuture<'a, Result<Bytes>> {
+        (self.0)(req, next)
+    }
+}
+
+/// The interceptors of a client, run in the order added.
+#[derive(Clone, Default)]
+pub struct Interceptors(pub Vec<Arc<dyn Interceptor>>);
+
+impl Interceptors {
+    pub fn push<I: Interceptor + 'static>(&mut self, i: I) {
+        self.0.push(Arc::new(i));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Debug for Interceptors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interceptors({})", self.0.len())
+    }
+}
+
+/// Sends a request over the connection, the end of the chain.
+pub type SendFn<'a> = Box<dyn FnOnce(Request<'a>) -> BoxFuture<'a, Result<Bytes>> + Send + 'a>;
+
+/// The rest of the chain of a call: the interceptors left, then the connection.
+pub struct Next<'a> {
+    rest: &'a [Arc<dyn Interceptor>],
+    send: SendFn<'a>,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(interceptors: &'a Interceptors, send: SendFn<'a>) -> Self {
+        Self {
+            rest: &interceptors.0,
+            send,
+        }
+    }
+
+    /// Run the next interceptor, or send the request after the last one.
+    pub async fn run(mut self, req: Request<'a>) -> Result<Bytes> {
+        match self.rest.split_first() {
+            Some((i, rest)) => {
+                self.rest = rest;
+                i.intercept(req, self).await
+            }
+            None => (self.send)(req).await,
+        }
+    }
+}
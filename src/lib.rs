@@ -1,12 +1,32 @@
 #![allow(async_fn_in_trait)]
 pub mod balance;
 pub mod balance_manager;
+pub mod batch;
+#[cfg(feature = "cbor")]
+mod cbor;
 pub mod client;
 pub mod codec;
+pub mod compress;
+pub mod context;
+#[cfg(feature = "fingerprint")]
+pub mod fingerprint;
 pub mod frame;
+pub mod handshake;
+pub mod interceptor;
+pub mod metadata;
+pub mod method;
+pub mod middleware;
+pub mod mux;
+pub mod peer;
+pub mod reflect;
 pub mod server;
+pub mod stream;
 pub mod stub;
+#[cfg(feature = "tower")]
+pub mod tower;
 pub use balance_manager::*;
 pub use dark_std::errors::Error;
 pub use dark_std::errors::Result;
 pub use dark_std::*;
+#[cfg(feature = "macros")]
+pub use drpc_macros::service;
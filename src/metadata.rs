@@ -0,0 +1,109 @@
+use bytes::{Bytes, BytesMut};
+use dark_std::err;
+use dark_std::errors::Result;
+
+use crate::frame::{get_varint, put_varint};
+
+// metadata block layout, repeated until the end of the block
+// key_len(varint) + key([u8; key_len], utf8) + value_len(varint) + value([u8; value_len])
+
+/// the codec id(u8) a call is encoded with, when it is not the one of the connection
+pub const CODEC: &str = "drpc-codec";
+/// the `fingerprint::method` (u64, big endian) of the types the caller encodes and decodes
+pub const FINGERPRINT: &str = "drpc-fingerprint";
+/// the fingerprints of the checked calls of a batch,
+/// [index(varint) + `fingerprint::method`(u64, big endian)] per call
+pub const FINGERPRINTS: &str = "drpc-fingerprints";
+/// the items(u32, big endian) a stream may send before the caller grants more
+pub const WINDOW: &str = "drpc-window";
+/// set(empty) when the caller sends a stream of requests after the request frame
+pub const UPLOAD: &str = "drpc-upload";
+
+/// Key/value pairs carried in the metadata block of a frame.
+/// Keys starting with `drpc-` are reserved.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Metadata {
+    entries: Vec<(String, Bytes)>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `key`, replacing the old value.
+    pub fn insert<K: Into<String>, V: Into<Bytes>>(&mut self, key: K, value: V) {
+        let key = key.into();
+        let value = value.into();
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Bytes> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// The value of `key`, if it is utf8.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(|v| std::str::from_utf8(v).ok())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Bytes> {
+        let idx = self.entries.iter().position(|(k, _)| k == key)?;
+        Some(self.entries.remove(idx).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Bytes)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn encode(&self) -> Bytes {
+        if self.entries.is_empty() {
+            return Bytes::new();
+        }
+        let mut buf = BytesMut::new();
+        for (k, v) in &self.entries {
+            put_varint(&mut buf, k.len() as u64);
+            buf.extend_from_slice(k.as_bytes());
+            put_varint(&mut buf, v.len() as u64);
+            buf.extend_from_slice(v);
+        }
+        buf.freeze()
+    }
+
+    /// Decode a metadata block, the values are slices of `buf`.
+    pub fn decode(buf: &Bytes) -> Result<Self> {
+        let mut entries = vec![];
+        let mut pos = 0;
+        while pos < buf.len() {
+            let key = Self::next(buf, &mut pos)?;
+            let key =
+                String::from_utf8(key.to_vec()).map_err(|_| err!("metadata key is not utf8!"))?;
+            let value = Self::next(buf, &mut pos)?;
+            entries.push((key, value));
+        }
+        Ok(Self { entries })
+    }
+
+    fn next(buf: &Bytes, pos: &mut usize) -> Result<Bytes> {
+        let len = get_varint(buf, pos)
+            .map_err(|e| err!("{}", e))?
+            .ok_or_else(|| err!("metadata is too short!"))? as usize;
+        if buf.len() - *pos < len {
+            return Err(err!("metadata is too short!"));
+        }
+        let v = buf.slice(*pos..*pos + len);
+        *pos += len;
+        Ok(v)
+    }
+}
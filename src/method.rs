@@ -0,0 +1,46 @@
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+use crate::stub::method_id;
+
+/// A method name paired with its request and response types,
+/// shared by the server(`Server::register_method`) and the client(`Client::invoke`)
+/// so that a call with other types does not compile.
+///
+/// ```rust
+/// use drpc::method::Method;
+///
+/// pub const ADD: Method<(i32, i32), i32> = Method::new("add");
+/// ```
+pub struct Method<Req, Resp> {
+    pub name: &'static str,
+    _types: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<Req, Resp> Method<Req, Resp> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _types: PhantomData,
+        }
+    }
+
+    /// The `method_id` of the name.
+    pub const fn id(&self) -> u64 {
+        method_id(self.name)
+    }
+}
+
+impl<Req, Resp> Clone for Method<Req, Resp> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Req, Resp> Copy for Method<Req, Resp> {}
+
+impl<Req, Resp> Debug for Method<Req, Resp> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Method").field("name", &self.name).finish()
+    }
+}
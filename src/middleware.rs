@@ -0,0 +1,90 @@
+use dark_std::errors::Result;
+use futures::future::BoxFuture;
+
+use crate::metadata::Metadata;
+
+/// A call as the middlewares see it, before its request is decoded.
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub metadata: &'a Metadata,
+    /// the encoded request, decompressed
+    pub body: &'a [u8],
+}
+
+/// Runs around the calls of a server, see `Server::add_middleware`.
+/// It may answer an error instead of calling `next`, or wrap the future of `next`.
+/// The responses it sees are encoded. A streaming call passes the middlewares
+/// before its stream opens, `next` then answers an empty response.
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(&'a self, req: Request<'a>, next: Next<'a>) -> BoxFuture<'a, Result<Vec<u8>>>;
+}
+
+/// A middleware of a function, see `from_fn`.
+pub struct FromFn<F>(F);
+
+/// A middleware of a function or a closure:
+/// ```rust
+/// use drpc::middleware::from_fn;
+///
+/// let log = from_fn(|req, next| {
+///     Box::pin(async move {
+///         println!("call {}", req.method);
+///         next.run().await
+///     })
+/// });
+/// ```
+pub fn from_fn<F>(f: F) -> FromFn<F>
+where
+    F: for<'a> Fn(Request<'a>, Next<'a>) -> BoxFuture<'a, Result<Vec<u8>>> + Send + Sync,
+{
+    FromFn(f)
+}
+
+impl<F> Middleware for FromFn<F>
+where
+    F: for<'a> Fn(Request<'a>, Next<'a>) -> BoxFuture<'a, Result<Vec<u8>>> + Send + Sync,
+{
+    fn handle<'a>(&'a self, req: Request<'a>, next: Next<'a>) -> BoxFuture<'a, Result<Vec<u8>>> {
+        (self.0)(req, next)
+    }
+}
+
+/// The rest of the chain of a call: the middlewares left, then the method.
+pub struct Next<'a> {
+    req: Request<'a>,
+    global: &'a [Box<dyn Middleware>],
+    method: &'a [Box<dyn Middleware>],
+    handler: BoxFuture<'a, Result<Vec<u8>>>,
+}
+
+impl<'a> Next<'a> {
+    /// The global middlewares run before the ones of the method.
+    pub fn new(
+        req: Request<'a>,
+        global: &'a [Box<dyn Middleware>],
+        method: &'a [Box<dyn Middleware>],
+        handler: BoxFuture<'a, Result<Vec<u8>>>,
+    ) -> Self {
+        Self {
+            req,
+            global,
+            method,
+            handler,
+        }
+    }
+
+    /// Run the next middleware, or the method after the last one.
+    pub async fn run(mut self) -> Result<Vec<u8>> {
+        let m = if let Some((m, rest)) = self.global.split_first() {
+            self.global = rest;
+            m
+        } else if let Some((m, rest)) = self.method.split_first() {
+            self.method = rest;
+            m
+        } else {
+            return self.handler.await;
+        };
+        m.handle(self.req, self).await
+    }
+}
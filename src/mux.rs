@@ -0,0 +1,284 @@
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use log::{debug, error};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use tokio_util::codec::Framed;
+
+use crate::frame::{Frame, FrameCodec, FLAG_CONTROL, FLAG_ONEWAY, FLAG_STREAM};
+use crate::stream::Control;
+use crate::stub::{is_callback, Transport};
+
+/// Answers a call the server makes back over the connection, see `Client::set_callbacks`.
+pub type Callbacks = Arc<dyn Fn(Frame) -> BoxFuture<'static, Frame> + Send + Sync>;
+
+/// Where the frames read with the id of a call go.
+#[derive(Debug)]
+struct Route {
+    frames: mpsc::UnboundedSender<Frame>,
+    /// the requests the server grants a bidi call, taken by its `RequestSender`
+    credits: Option<Arc<Semaphore>>,
+}
+
+/// The calls in flight over a connection by id.
+#[derive(Debug, Default)]
+pub struct Routes {
+    inner: Mutex<HashMap<u64, Route>>,
+}
+
+impl Routes {
+    /// Route the frames with `id` to the returned receiver, until `close`.
+    pub(crate) fn open(
+        &self,
+        id: u64,
+        credits: Option<Arc<Semaphore>>,
+    ) -> mpsc::UnboundedReceiver<Frame> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.inner.lock().unwrap().insert(
+            id,
+            Route {
+                frames: tx,
+                credits,
+            },
+        );
+        rx
+    }
+
+    pub(crate) fn close(&self, id: u64) {
+        self.inner.lock().unwrap().remove(&id);
+    }
+
+    /// Hand `frame` to its call, the frame comes back when no call has its id.
+    pub(crate) fn route(&self, frame: Frame) -> Result<(), Frame> {
+        let routes = self.inner.lock().unwrap();
+        let route = match routes.get(&frame.id) {
+            Some(v) => v,
+            None => return Err(frame),
+        };
+        if let (Some(credits), Some(Control::Window(n))) = (&route.credits, Control::decode(&frame))
+        {
+            credits.add_permits(n as usize);
+            return Ok(());
+        }
+        let _ = route.frames.send(frame);
+        Ok(())
+    }
+
+    /// Drop every route, the calls in flight read the end of the connection.
+    pub(crate) fn clear(&self) {
+        for (_, route) in self.inner.lock().unwrap().drain() {
+            if let Some(credits) = route.credits {
+                credits.close();
+            }
+        }
+    }
+}
+
+/// A connection shared by concurrent calls. A task writes the frames of the calls
+/// and routes the frames it reads to the calls by id, so the streams and the unary
+/// calls of a connection interleave.
+pub struct Mux {
+    out: mpsc::UnboundedSender<Frame>,
+    routes: Arc<Routes>,
+    callbacks: Arc<RwLock<Option<Callbacks>>>,
+    task: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for Mux {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mux")
+            .field("routes", &self.routes)
+            .field("task", &self.task)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mux {
+    /// Drive `framed` in a task, after its handshake.
+    pub fn new<S>(framed: Framed<S, FrameCodec>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (out, frames) = mpsc::unbounded_channel();
+        let routes = Arc::new(Routes::default());
+        let callbacks = Arc::new(RwLock::new(None));
+        let task = tokio::spawn(Self::run(framed, frames, routes.clone(), callbacks.clone()));
+        Self {
+            out,
+            routes,
+            callbacks,
+            task,
+        }
+    }
+
+    async fn run<S>(
+        mut framed: Framed<S, FrameCodec>,
+        mut frames: mpsc::UnboundedReceiver<Frame>,
+        routes: Arc<Routes>,
+        callbacks: Arc<RwLock<Option<Callbacks>>>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        // the answers of the calls back from the server
+        let (answer, mut answers) = mpsc::unbounded_channel();
+        loop {
+            // unbiased, a busy writer must not starve the reads of the responses
+            let frame = tokio::select! {
+                frame = frames.recv() => match frame {
+                    Some(frame) => frame,
+                    // the mux is dropped
+                    None => break,
+                },
+                Some(frame) = answers.recv() => frame,
+                frame = framed.next() => {
+                    match frame {
+                        Some(Ok(frame)) => {
+                            if let Err(frame) = routes.route(frame) {
+                                Self::serve(&callbacks, &answer, frame);
+                            }
+                        }
+                        Some(Err(e)) => {
+                            error!("mux read: err = {}", e);
+                            break;
+                        }
+                        None => {
+                            debug!("mux read: connection closed");
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            };
+            let checksum = framed.codec().checksum;
+            if let Err(e) = frame.write_to(framed.get_mut(), checksum).await {
+                error!("mux write: err = {}", e);
+                break;
+            }
+        }
+        routes.clear();
+        let _ = framed.get_mut().shutdown().await;
+    }
+
+    /// Answer a request of the server by the callbacks, any other frame no call
+    /// reads is discarded.
+    fn serve(
+        callbacks: &RwLock<Option<Callbacks>>,
+        answer: &mpsc::UnboundedSender<Frame>,
+        frame: Frame,
+    ) {
+        if !is_callback(frame.id) || frame.has_flag(FLAG_CONTROL) || frame.has_flag(FLAG_STREAM) {
+            debug!("mux discard frame id={}", frame.id);
+            return;
+        }
+        let callbacks = callbacks.read().unwrap().clone();
+        let answer = answer.clone();
+        let oneway = frame.has_flag(FLAG_ONEWAY);
+        tokio::spawn(async move {
+            let rsp = match callbacks {
+                Some(f) => f(frame).await,
+                None => {
+                    let mut rsp = Frame::new();
+                    rsp.id = frame.id;
+                    rsp.version = frame.version;
+                    rsp.data = bytes::Bytes::from("client serves no callbacks!");
+                    rsp
+                }
+            };
+            if !oneway {
+                let _ = answer.send(rsp);
+            }
+        });
+    }
+
+    /// Answer the calls the server makes back over the connection by `callbacks`.
+    pub fn set_callbacks(&self, callbacks: Callbacks) {
+        *self.callbacks.write().unwrap() = Some(callbacks);
+    }
+
+    /// A call over the connection.
+    pub fn call(&self) -> MuxCall<'_> {
+        MuxCall {
+            mux: self,
+            credits: None,
+            open: None,
+            frames: None,
+        }
+    }
+
+    /// Same as `call`, the request windows the server grants go to `credits`.
+    pub fn call_with_credits(&self, credits: Arc<Semaphore>) -> MuxCall<'_> {
+        MuxCall {
+            credits: Some(credits),
+            ..self.call()
+        }
+    }
+
+    /// Write a frame, fails once the connection is closed.
+    pub fn send(&self, frame: Frame) -> std::io::Result<()> {
+        self.out.send(frame).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::NotConnected, "connection closed!")
+        })
+    }
+
+    /// Close the connection once the frames sent are written.
+    pub async fn shutdown(self) {
+        drop(self.out);
+        let _ = self.task.await;
+    }
+}
+
+/// Keeps the route of a call, until the halves of the call are dropped.
+#[derive(Debug)]
+pub struct Open<'a> {
+    routes: &'a Routes,
+    id: u64,
+}
+
+impl<'a> Open<'a> {
+    pub(crate) fn new(routes: &'a Routes, id: u64) -> Self {
+        Self { routes, id }
+    }
+}
+
+impl Drop for Open<'_> {
+    fn drop(&mut self) {
+        self.routes.close(self.id);
+    }
+}
+
+/// A call of a `Mux`, it reads the frames with the id it opens.
+pub struct MuxCall<'a> {
+    mux: &'a Mux,
+    credits: Option<Arc<Semaphore>>,
+    open: Option<Arc<Open<'a>>>,
+    frames: Option<mpsc::UnboundedReceiver<Frame>>,
+}
+
+impl<'a> MuxCall<'a> {
+    /// The route of the call, kept while it is held.
+    pub fn route(&self) -> Option<Arc<Open<'a>>> {
+        self.open.clone()
+    }
+}
+
+impl Transport for MuxCall<'_> {
+    fn open(&mut self, id: u64) {
+        self.frames = Some(self.mux.routes.open(id, self.credits.clone()));
+        self.open = Some(Arc::new(Open::new(&self.mux.routes, id)));
+    }
+
+    async fn send(&mut self, frame: Frame) -> std::io::Result<()> {
+        self.mux.send(frame)
+    }
+
+    async fn recv(&mut self) -> Option<std::io::Result<Frame>> {
+        self.frames.as_mut()?.recv().await.map(Ok)
+    }
+
+    fn sender(&self) -> Option<mpsc::UnboundedSender<Frame>> {
+        Some(self.mux.out.clone())
+    }
+}
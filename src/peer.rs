@@ -0,0 +1,111 @@
+use dark_std::errors::Result;
+use std::future::Future;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use crate::client::Call;
+use crate::codec::{Decode, Encode, Format};
+use crate::frame::{Frame, FrameVersion};
+use crate::mux::{Open, Routes};
+use crate::stub::{ClientStub, Transport};
+
+/// A connected client, as the server sees it. The methods it serves(`Client::set_callbacks`)
+/// are called back over its connection, see `Server::set_on_connect`.
+/// The clones of a peer share its connection.
+#[derive(Debug, Clone)]
+pub struct Peer<C: Format> {
+    codec: C,
+    conn: Arc<PeerConn>,
+}
+
+#[derive(Debug)]
+struct PeerConn {
+    stub: ClientStub,
+    out: mpsc::Sender<Frame>,
+    /// the calls back in flight, answered by the connection loop
+    routes: Arc<Routes>,
+}
+
+impl<C: Format> Peer<C> {
+    pub(crate) fn new(codec: C, out: mpsc::Sender<Frame>, routes: Arc<Routes>) -> Self {
+        let mut stub = ClientStub::new();
+        stub.id_bit = 0;
+        // 0 is the id of the handshake
+        stub.tag = AtomicU64::new(1);
+        // a client reading the calls back took the handshake, it speaks v2
+        stub.version = FrameVersion::V2;
+        Self {
+            codec,
+            conn: Arc::new(PeerConn { stub, out, routes }),
+        }
+    }
+
+    /// Call a method the client serves.
+    pub async fn call<Arg, Resp>(&self, func: &str, arg: Arg) -> Result<Resp>
+    where
+        Arg: Encode<C>,
+        Resp: Decode<C>,
+    {
+        let call = PeerCall {
+            conn: &self.conn,
+            open: None,
+            frames: None,
+        };
+        self.conn.stub.call(func, arg, &self.codec, call).await
+    }
+
+    /// Send a notification to a method the client serves, without waiting for an answer.
+    pub async fn notify<Arg>(&self, func: &str, arg: Arg) -> Result<()>
+    where
+        Arg: Encode<C>,
+    {
+        let call = PeerCall {
+            conn: &self.conn,
+            open: None,
+            frames: None,
+        };
+        self.conn.stub.notify(func, arg, &self.codec, call).await
+    }
+
+    /// Is the connection closed, the calls back fail from then on.
+    pub fn is_closed(&self) -> bool {
+        self.conn.out.is_closed()
+    }
+}
+
+impl<C: Format> Call for Peer<C> {
+    type Codec = C;
+
+    fn call<Arg, Resp>(&self, func: &str, arg: Arg) -> impl Future<Output = Result<Resp>> + Send
+    where
+        Arg: Encode<C> + Send,
+        Resp: Decode<C>,
+    {
+        Peer::call(self, func, arg)
+    }
+}
+
+/// A call back, it reads the answer the connection loop routes to its id.
+struct PeerCall<'a> {
+    conn: &'a PeerConn,
+    open: Option<Open<'a>>,
+    frames: Option<mpsc::UnboundedReceiver<Frame>>,
+}
+
+impl Transport for PeerCall<'_> {
+    fn open(&mut self, id: u64) {
+        self.frames = Some(self.conn.routes.open(id, None));
+        self.open = Some(Open::new(&self.conn.routes, id));
+    }
+
+    async fn send(&mut self, frame: Frame) -> std::io::Result<()> {
+        self.conn.out.send(frame).await.map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::NotConnected, "connection closed!")
+        })
+    }
+
+    async fn recv(&mut self) -> Option<std::io::Result<Frame>> {
+        self.frames.as_mut()?.recv().await.map(Ok)
+    }
+}
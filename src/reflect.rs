@@ -0,0 +1,50 @@
+use dark_std::sync::SyncHashMap;
+use serde::{Deserialize, Serialize};
+
+/// the built-in method answering the `ServiceInfo` of a server, see `Server::set_reflection`
+pub const METHOD: &str = "drpc.reflect";
+
+/// A registered method, the schemas are JSON Schema documents in JSON text,
+/// so that any codec carries them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct MethodInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub request: Option<String>,
+    pub response: Option<String>,
+}
+
+/// The methods of a server, sorted by name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ServiceInfo {
+    pub methods: Vec<MethodInfo>,
+}
+
+impl ServiceInfo {
+    pub fn of(methods: &SyncHashMap<String, MethodInfo>) -> Self {
+        let mut methods: Vec<MethodInfo> = methods.iter().map(|(_, v)| v.clone()).collect();
+        methods.sort_by(|a, b| a.name.cmp(&b.name));
+        Self { methods }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MethodInfo> {
+        self.methods.iter().find(|v| v.name == name)
+    }
+}
+
+/// The types whose JSON Schema `Server::register_method` records, any type without
+/// the `schema` feature, a `schemars::JsonSchema` with it.
+#[cfg(feature = "schema")]
+pub trait Schema: schemars::JsonSchema {}
+#[cfg(feature = "schema")]
+impl<T: schemars::JsonSchema> Schema for T {}
+#[cfg(not(feature = "schema"))]
+pub trait Schema {}
+#[cfg(not(feature = "schema"))]
+impl<T> Schema for T {}
+
+/// The JSON Schema of `T` in JSON text.
+#[cfg(feature = "schema")]
+pub fn schema<T: schemars::JsonSchema>() -> String {
+    serde_json::to_string(&schemars::schema_for!(T)).unwrap_or_default()
+}
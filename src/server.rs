@@ -1,27 +1,55 @@
-use crate::codec::{BinCodec, Codec};
-use crate::stub::ServerStub;
-use dark_std::errors::Result;
+use crate::batch;
+use crate::codec::{BinCodec, Decode, DecodeBorrowed, Encode, Format};
+use crate::context::{Context, FromContext};
+#[cfg(feature = "fingerprint")]
+use crate::fingerprint;
+use crate::handshake::{self, Handshake};
+use crate::method::Method;
+use crate::middleware::Middleware;
+use crate::peer::Peer;
+use crate::reflect::{self, MethodInfo, Schema, ServiceInfo};
+use crate::stream::{RequestStream, ResponseSender};
+use crate::stub::{method_id, Metrics, ServerStub};
+#[cfg(feature = "tower")]
+use crate::tower;
+use bytes::Bytes;
+use dark_std::errors::{Error, Result};
 use dark_std::sync::SyncHashMap;
 use futures::future::BoxFuture;
-use serde::de::DeserializeOwned;
-use serde::Serialize;
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use std::any::Any;
 use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+#[cfg(feature = "tower")]
+use tower_service::Service;
 
-pub struct Server<C: Codec> {
-    pub handles: SyncHashMap<String, Box<dyn Stub<C>>>,
+pub struct Server<C: Format> {
+    pub handles: Arc<SyncHashMap<String, Arc<dyn Stub<C>>>>,
+    /// method_id -> method name and its handle, filled at register so a request
+    /// dispatched by id hashes no string
+    pub ids: SyncHashMap<u64, (String, Arc<dyn Stub<C>>)>,
     pub codec: C,
     pub stub: ServerStub,
+    /// method name -> the description and schemas answered by the reflection
+    pub methods: Arc<SyncHashMap<String, MethodInfo>>,
+    /// takes the `Peer` of each connection, see `set_on_connect`
+    pub on_connect: Option<Arc<dyn Fn(Peer<C>) + Send + Sync>>,
 }
 
-impl<C: Codec> Server<C> {
+impl<C: Format> Server<C> {
     pub fn new() -> Self {
         Self {
-            handles: SyncHashMap::new(),
+            handles: Arc::new(SyncHashMap::new()),
+            ids: SyncHashMap::new(),
             codec: C::default(),
             stub: ServerStub::new(),
+            methods: Arc::new(SyncHashMap::new()),
+            on_connect: None,
         }
     }
 }
@@ -29,33 +57,208 @@ impl<C: Codec> Server<C> {
 impl Default for Server<BinCodec> {
     fn default() -> Self {
         Self {
-            handles: SyncHashMap::new(),
+            handles: Arc::new(SyncHashMap::new()),
+            ids: SyncHashMap::new(),
             codec: BinCodec {},
             stub: ServerStub::new(),
+            methods: Arc::new(SyncHashMap::new()),
+            on_connect: None,
         }
     }
 }
 
-impl<C: Codec> Server<C> {
+impl<C: Format> Server<C> {
+    /// Set the codec, for a codec configured other than its `Default`.
+    pub fn set_codec(mut self, codec: C) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Reject the calls whose fingerprint differs from the method's, see `register_fn_checked`.
+    /// A `Client::call_checked` or `Batch::push_checked` of other types fails with `incompatible schema`.
+    pub fn set_fingerprint(mut self, fingerprint: bool) -> Self {
+        self.stub.fingerprint = fingerprint;
+        self
+    }
+
+    /// Set the options a client handshake can pick from.
+    pub fn set_handshake(mut self, handshake: Handshake) -> Self {
+        self.stub.handshake = handshake;
+        self
+    }
+
+    /// Compress the responses that are at least `threshold` bytes,
+    /// when the connection agreed on a compression.
+    pub fn set_compress_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.stub.compress_threshold = threshold;
+        self
+    }
+
+    /// Set the requests an upload sends ahead of the ones read, see `register_upload_fn`.
+    pub fn set_window(mut self, window: u32) -> Self {
+        self.stub.window = window;
+        self
+    }
+
+    /// Set the longest frame a client may send, a longer one closes its connection.
+    /// It bounds the decompressed payloads too, a longer one fails its call.
+    pub fn set_max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.stub.max_frame_len = max_frame_len;
+        self
+    }
+
+    /// The counters of the calls served, notifications included.
+    pub fn metrics(&self) -> &Metrics {
+        &self.stub.metrics
+    }
+
+    /// Call `f` with the `Peer` of each connection as it opens,
+    /// the peer calls back the methods its client serves(`Client::set_callbacks`).
+    pub fn set_on_connect<F>(mut self, f: F) -> Self
+    where
+        F: Fn(Peer<C>) + Send + Sync + 'static,
+    {
+        self.on_connect = Some(Arc::new(f));
+        self
+    }
+
+    /// Run `m` around the calls of every method, after the middlewares added before.
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, m: M) {
+        self.stub.middlewares.push(Box::new(m));
+    }
+
+    /// Run `m` around the calls of `method`, after the global middlewares.
+    pub fn add_method_middleware<M: Middleware + 'static>(&mut self, method: &str, m: M) {
+        self.stub
+            .method_middlewares
+            .entry(method.to_string())
+            .or_default()
+            .push(Box::new(m));
+    }
+
+    /// Answer the unary calls, notifications and batches included, through the tower `layer`
+    /// around the handlers(`tower::Dispatch`). It runs inside the middlewares.
+    /// Each call clones the service and waits for the clone to be ready, so a service not ready
+    /// holds back its own call only. The clones of a tower service share its limits
+    /// (`ConcurrencyLimit`), a service that can not be cloned(`RateLimit`) goes behind a
+    /// `tower::buffer::BufferLayer`.
+    #[cfg(feature = "tower")]
+    pub fn set_layer<L>(mut self, layer: L) -> Self
+    where
+        C: 'static,
+        L: tower_layer::Layer<tower::Dispatch<C>>,
+        L::Service: Service<tower::Request, Response = Bytes> + Clone + Send + Sync + 'static,
+        <L::Service as Service<tower::Request>>::Error: Into<tower::BoxError>,
+        <L::Service as Service<tower::Request>>::Future: Send + 'static,
+    {
+        let service = layer.layer(tower::Dispatch::new(&self));
+        let error = |e: tower::BoxError| Error::from(e.to_string());
+        self.stub.dispatcher = Some(Arc::new(move |ctx: Context, body, codec| {
+            let mut service = service.clone();
+            Box::pin(async move {
+                futures::future::poll_fn(|cx| service.poll_ready(cx))
+                    .await
+                    .map_err(|e| error(e.into()))?;
+                let rsp = service.call(tower::Request {
+                    method: ctx.method.clone(),
+                    metadata: ctx.metadata.clone(),
+                    body,
+                    codec: Some(codec),
+                    context: Some(ctx),
+                });
+                rsp.await.map_err(|e| error(e.into()))
+            })
+        }));
+        self
+    }
+
+    /// Set the state the handlers extract(`context::State<T>`), e.g. an `Arc` of a database pool.
+    /// Set it before registering them, a handler taking a `State` of another type fails to register.
+    pub fn set_state<T: Send + Sync + 'static>(mut self, state: T) -> Self {
+        self.stub.state = Some(Arc::new(state));
+        self
+    }
+
     /// Call the server method
     #[inline]
     pub async fn call<S>(&self, stream: S)
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        self.stub.call(&self.handles, &self.codec, stream).await;
+        self.serve_conn(stream, None).await;
+    }
+
+    /// Same as `call`, the handlers see `peer_addr` in their `Context`.
+    pub async fn call_from<S>(&self, stream: S, peer_addr: SocketAddr)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.serve_conn(stream, Some(peer_addr)).await;
+    }
+
+    async fn serve_conn<S>(&self, stream: S, peer_addr: Option<SocketAddr>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (stubs, ids, codec) = (&self.handles, &self.ids, &self.codec);
+        self.stub
+            .call_with_peer(stubs, ids, codec, stream, peer_addr, |peer| {
+                if let Some(f) = &self.on_connect {
+                    f(peer);
+                }
+            })
+            .await;
     }
 }
 
-pub trait Stub<C: Codec>: Sync + Send {
-    fn accept(&self, arg: &[u8], codec: &C) -> BoxFuture<Result<Vec<u8>>>;
+pub trait Stub<C: Format>: Sync + Send {
+    fn accept<'a>(&'a self, arg: &'a [u8], codec: &'a C) -> BoxFuture<'a, Result<Vec<u8>>>;
+    /// Same as `accept`, a handler taking extractors(`FromContext`) reads them from `ctx`.
+    fn accept_with<'a>(
+        &'a self,
+        _ctx: &Context,
+        arg: &'a [u8],
+        codec: &'a C,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        self.accept(arg, codec)
+    }
+    /// The responses of a method answering a stream, `None` for a unary method.
+    fn accept_stream<'a>(
+        &'a self,
+        _arg: &'a [u8],
+        _codec: &'a C,
+    ) -> Option<BoxStream<'a, Result<Vec<u8>>>> {
+        None
+    }
+    /// The response of a method reading a stream of requests, `None` for any other method.
+    fn accept_upload<'a>(
+        &'a self,
+        _reqs: BoxStream<'static, Result<Bytes>>,
+        _codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<Vec<u8>>>> {
+        None
+    }
+    /// Run a bidi streaming method, its responses go to `rsps`. `None` for any other method.
+    fn accept_bidi<'a>(
+        &'a self,
+        _reqs: BoxStream<'static, Result<Bytes>>,
+        _rsps: mpsc::Sender<Vec<u8>>,
+        _codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<()>>> {
+        None
+    }
+    /// The `fingerprint::method` of the request and response types, checked against the
+    /// caller's when the server checks fingerprints. `None` is never checked.
+    fn fingerprint(&self) -> Option<u64> {
+        None
+    }
 }
 
-pub trait Handler<C: 'static + Codec>: Stub<C> + Sync + Send {
-    type Req: DeserializeOwned + Send;
-    type Resp: Serialize;
-    fn accept(&self, arg: &[u8], codec: &C) -> BoxFuture<Result<Vec<u8>>> {
-        let req = codec.decode::<Self::Req>(arg);
+pub trait Handler<C: 'static + Format>: Stub<C> + Sync + Send {
+    type Req: Decode<C> + Send;
+    type Resp: Encode<C>;
+    fn accept<'a>(&'a self, arg: &'a [u8], codec: &'a C) -> BoxFuture<'a, Result<Vec<u8>>> {
+        let req = <Self::Req as Decode<C>>::decode(codec, arg);
         let f = {
             if req.is_err() {
                 Err(req.err().unwrap())
@@ -67,106 +270,753 @@ pub trait Handler<C: 'static + Codec>: Stub<C> + Sync + Send {
         Box::pin(async move {
             let f = f?;
             let data = f.await?;
-            Ok(codec.encode(data)?)
+            Ok(data.encode(&codec)?)
         })
     }
-    fn handle(&self, req: Self::Req) -> BoxFuture<Result<Self::Resp>>;
+    /// Same as `accept`, see `Stub::accept_with`.
+    fn accept_with<'a>(
+        &'a self,
+        _ctx: &Context,
+        arg: &'a [u8],
+        codec: &'a C,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        <Self as Handler<C>>::accept(self, arg, codec)
+    }
+    fn handle(&self, req: Self::Req) -> BoxFuture<'_, Result<Self::Resp>>;
+    fn fingerprint(&self) -> Option<u64> {
+        None
+    }
+    /// The responses of a method answering a stream, see `Stub::accept_stream`.
+    fn accept_stream<'a>(
+        &'a self,
+        _arg: &'a [u8],
+        _codec: &'a C,
+    ) -> Option<BoxStream<'a, Result<Vec<u8>>>> {
+        None
+    }
+    /// The response of a method reading a stream of requests, see `Stub::accept_upload`.
+    fn accept_upload<'a>(
+        &'a self,
+        _reqs: BoxStream<'static, Result<Bytes>>,
+        _codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<Vec<u8>>>> {
+        None
+    }
+    /// Run a bidi streaming method, see `Stub::accept_bidi`.
+    fn accept_bidi<'a>(
+        &'a self,
+        _reqs: BoxStream<'static, Result<Bytes>>,
+        _rsps: mpsc::Sender<Vec<u8>>,
+        _codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<()>>> {
+        None
+    }
 }
 
-impl<C: Codec + 'static, H: Handler<C>> Stub<C> for H {
-    fn accept(&self, arg: &[u8], codec: &C) -> BoxFuture<Result<Vec<u8>>> {
-        <H as Handler<C>>::accept(self, arg, codec)
+/// A handler whose request may borrow from the frame buffer, e.g. `&'a str` or `&'a [u8]` fields.
+/// The request is decoded by `DecodeBorrowed`, `Codec::decode_borrowed` for the serde codecs.
+/// Every `Handler` is one.
+pub trait BorrowHandler<C: 'static + Format>: Sync + Send {
+    type Req<'a>: DecodeBorrowed<'a, C> + Send
+    where
+        Self: 'a;
+    type Resp: Encode<C>;
+    fn accept<'a>(&'a self, arg: &'a [u8], codec: &'a C) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let req = <Self::Req<'a> as DecodeBorrowed<'a, C>>::decode_borrowed(codec, arg)?;
+            let data = self.handle(req).await?;
+            data.encode(codec)
+        })
+    }
+    /// Same as `accept`, see `Stub::accept_with`.
+    fn accept_with<'a>(
+        &'a self,
+        _ctx: &Context,
+        arg: &'a [u8],
+        codec: &'a C,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        <Self as BorrowHandler<C>>::accept(self, arg, codec)
+    }
+    fn handle<'a>(&'a self, req: Self::Req<'a>) -> BoxFuture<'a, Result<Self::Resp>>;
+    fn fingerprint(&self) -> Option<u64> {
+        None
+    }
+    /// The responses of a method answering a stream, see `Stub::accept_stream`.
+    fn accept_stream<'a>(
+        &'a self,
+        _arg: &'a [u8],
+        _codec: &'a C,
+    ) -> Option<BoxStream<'a, Result<Vec<u8>>>> {
+        None
+    }
+    /// The response of a method reading a stream of requests, see `Stub::accept_upload`.
+    fn accept_upload<'a>(
+        &'a self,
+        _reqs: BoxStream<'static, Result<Bytes>>,
+        _codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<Vec<u8>>>> {
+        None
+    }
+    /// Run a bidi streaming method, see `Stub::accept_bidi`.
+    fn accept_bidi<'a>(
+        &'a self,
+        _reqs: BoxStream<'static, Result<Bytes>>,
+        _rsps: mpsc::Sender<Vec<u8>>,
+        _codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<()>>> {
+        None
     }
 }
 
-pub struct HandleFn<Req: DeserializeOwned, Resp: Serialize> {
-    pub f: Box<dyn Fn(Req) -> BoxFuture<'static, Result<Resp>>>,
+impl<C: Format + 'static, H: Handler<C>> BorrowHandler<C> for H {
+    type Req<'a>
+        = <H as Handler<C>>::Req
+    where
+        H: 'a;
+    type Resp = <H as Handler<C>>::Resp;
+
+    fn accept<'a>(&'a self, arg: &'a [u8], codec: &'a C) -> BoxFuture<'a, Result<Vec<u8>>> {
+        <H as Handler<C>>::accept(self, arg, codec)
+    }
+
+    fn accept_with<'a>(
+        &'a self,
+        ctx: &Context,
+        arg: &'a [u8],
+        codec: &'a C,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        <H as Handler<C>>::accept_with(self, ctx, arg, codec)
+    }
+
+    fn handle<'a>(&'a self, req: Self::Req<'a>) -> BoxFuture<'a, Result<Self::Resp>> {
+        <H as Handler<C>>::handle(self, req)
+    }
+
+    fn fingerprint(&self) -> Option<u64> {
+        <H as Handler<C>>::fingerprint(self)
+    }
+
+    fn accept_stream<'a>(
+        &'a self,
+        arg: &'a [u8],
+        codec: &'a C,
+    ) -> Option<BoxStream<'a, Result<Vec<u8>>>> {
+        <H as Handler<C>>::accept_stream(self, arg, codec)
+    }
+
+    fn accept_upload<'a>(
+        &'a self,
+        reqs: BoxStream<'static, Result<Bytes>>,
+        codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<Vec<u8>>>> {
+        <H as Handler<C>>::accept_upload(self, reqs, codec)
+    }
+
+    fn accept_bidi<'a>(
+        &'a self,
+        reqs: BoxStream<'static, Result<Bytes>>,
+        rsps: mpsc::Sender<Vec<u8>>,
+        codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<()>>> {
+        <H as Handler<C>>::accept_bidi(self, reqs, rsps, codec)
+    }
 }
 
-// It's safe
-unsafe impl<Req: DeserializeOwned, Resp: Serialize> Sync for HandleFn<Req, Resp> {}
+impl<C: Format + 'static, H: BorrowHandler<C>> Stub<C> for H {
+    fn accept<'a>(&'a self, arg: &'a [u8], codec: &'a C) -> BoxFuture<'a, Result<Vec<u8>>> {
+        <H as BorrowHandler<C>>::accept(self, arg, codec)
+    }
+
+    fn accept_with<'a>(
+        &'a self,
+        ctx: &Context,
+        arg: &'a [u8],
+        codec: &'a C,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        <H as BorrowHandler<C>>::accept_with(self, ctx, arg, codec)
+    }
+
+    fn fingerprint(&self) -> Option<u64> {
+        <H as BorrowHandler<C>>::fingerprint(self)
+    }
+
+    fn accept_stream<'a>(
+        &'a self,
+        arg: &'a [u8],
+        codec: &'a C,
+    ) -> Option<BoxStream<'a, Result<Vec<u8>>>> {
+        <H as BorrowHandler<C>>::accept_stream(self, arg, codec)
+    }
+
+    fn accept_upload<'a>(
+        &'a self,
+        reqs: BoxStream<'static, Result<Bytes>>,
+        codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<Vec<u8>>>> {
+        <H as BorrowHandler<C>>::accept_upload(self, reqs, codec)
+    }
+
+    fn accept_bidi<'a>(
+        &'a self,
+        reqs: BoxStream<'static, Result<Bytes>>,
+        rsps: mpsc::Sender<Vec<u8>>,
+        codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<()>>> {
+        <H as BorrowHandler<C>>::accept_bidi(self, reqs, rsps, codec)
+    }
+}
 
-// It's safe
-unsafe impl<Req: DeserializeOwned, Resp: Serialize> Send for HandleFn<Req, Resp> {}
+pub struct HandleFn<Req, Resp> {
+    pub f: Box<dyn Fn(Req) -> BoxFuture<'static, Result<Resp>> + Send + Sync>,
+    pub fingerprint: Option<u64>,
+}
 
-impl<C: Codec + 'static, Req: DeserializeOwned + Send, Resp: Serialize> Handler<C>
+impl<C: Format + 'static, Req: Decode<C> + Send, Resp: Encode<C>> Handler<C>
     for HandleFn<Req, Resp>
 {
     type Req = Req;
     type Resp = Resp;
 
-    fn handle(&self, req: Self::Req) -> BoxFuture<dark_std::errors::Result<Self::Resp>> {
+    fn handle(&self, req: Self::Req) -> BoxFuture<'_, Result<Self::Resp>> {
         (self.f)(req)
     }
+
+    fn fingerprint(&self) -> Option<u64> {
+        self.fingerprint
+    }
+}
+
+/// Runs the extractors of a `ContextFn` and calls it.
+pub type ExtractFn<Req, Resp> =
+    Box<dyn Fn(&Context, Req) -> Result<BoxFuture<'static, Result<Resp>>> + Send + Sync>;
+
+/// A method taking extractors(`FromContext`) before its request, see `Server::register_fn`.
+pub struct ContextFn<Req, Resp> {
+    pub f: ExtractFn<Req, Resp>,
+}
+
+impl<C: Format + 'static, Req: Decode<C> + Send, Resp: Encode<C>> Handler<C>
+    for ContextFn<Req, Resp>
+{
+    type Req = Req;
+    type Resp = Resp;
+
+    fn accept_with<'a>(
+        &'a self,
+        ctx: &Context,
+        arg: &'a [u8],
+        codec: &'a C,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        // the extractors run before the call, `ctx` is not kept
+        let f = Req::decode(codec, arg).and_then(|req| (self.f)(ctx, req));
+        let codec = codec.clone();
+        Box::pin(async move {
+            let data = f?.await?;
+            data.encode(&codec)
+        })
+    }
+
+    fn handle(&self, req: Self::Req) -> BoxFuture<'_, Result<Self::Resp>> {
+        match (self.f)(&Context::default(), req) {
+            Ok(f) => f,
+            Err(e) => Box::pin(async move { Err(e) }),
+        }
+    }
+}
+
+/// A function `Server::register_fn` takes: `Fn(Req)`, or `Fn(E1, .., Req)`
+/// taking up to 3 extractors(`FromContext`) before the request.
+pub trait HandlerFn<C: Format, T> {
+    fn into_stub(self) -> Box<dyn Stub<C>>;
+
+    /// Check the extractors against the state of the server, see `FromContext::check`.
+    fn check(_state: Option<&(dyn Any + Send + Sync)>) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<C, Req, Resp, Out, F> HandlerFn<C, (Req,)> for F
+where
+    C: Format + 'static,
+    Req: Decode<C> + Send + 'static,
+    Resp: Encode<C> + 'static,
+    Out: Future<Output = Result<Resp>> + Send + 'static,
+    F: Fn(Req) -> Out + Send + Sync + 'static,
+{
+    fn into_stub(self) -> Box<dyn Stub<C>> {
+        Box::new(HandleFn::new(
+            move |req: Req| -> BoxFuture<'static, Result<Resp>> { Box::pin((self)(req)) },
+        ))
+    }
+}
+
+macro_rules! handler_fn {
+    ($($e:ident),+) => {
+        impl<C, $($e,)+ Req, Resp, Out, F> HandlerFn<C, ($($e,)+ Req)> for F
+        where
+            C: Format + 'static,
+            $($e: FromContext + 'static,)+
+            Req: Decode<C> + Send + 'static,
+            Resp: Encode<C> + 'static,
+            Out: Future<Output = Result<Resp>> + Send + 'static,
+            F: Fn($($e,)+ Req) -> Out + Send + Sync + 'static,
+        {
+            fn into_stub(self) -> Box<dyn Stub<C>> {
+                Box::new(ContextFn {
+                    f: Box::new(
+                        move |ctx: &Context, req: Req| -> Result<BoxFuture<'static, Result<Resp>>> {
+                            Ok(Box::pin((self)($($e::from_context(ctx)?,)+ req)))
+                        },
+                    ),
+                })
+            }
+
+            fn check(state: Option<&(dyn Any + Send + Sync)>) -> Result<()> {
+                $($e::check(state)?;)+
+                Ok(())
+            }
+        }
+    };
+}
+
+handler_fn!(E1);
+handler_fn!(E1, E2);
+handler_fn!(E1, E2, E3);
+
+/// A method answering a stream of responses, see `Server::register_stream_fn`.
+pub struct StreamFn<Req, Resp> {
+    pub f: Box<dyn Fn(Req) -> BoxStream<'static, Result<Resp>> + Send + Sync>,
+}
+
+impl<Req, Resp> StreamFn<Req, Resp> {
+    pub fn new<F: 'static>(f: F) -> Self
+    where
+        F: Fn(Req) -> BoxStream<'static, Result<Resp>> + Send + Sync,
+    {
+        Self { f: Box::new(f) }
+    }
+}
+
+impl<C: Format + 'static, Req: Decode<C> + Send, Resp: Encode<C>> Handler<C>
+    for StreamFn<Req, Resp>
+{
+    type Req = Req;
+    type Resp = Resp;
+
+    fn handle(&self, _req: Self::Req) -> BoxFuture<'_, Result<Self::Resp>> {
+        Box::pin(async {
+            Err(Error::from(
+                "method answers a stream, call it with call_stream!",
+            ))
+        })
+    }
+
+    fn accept_stream<'a>(
+        &'a self,
+        arg: &'a [u8],
+        codec: &'a C,
+    ) -> Option<BoxStream<'a, Result<Vec<u8>>>> {
+        let items = match Req::decode(codec, arg) {
+            Ok(req) => (self.f)(req),
+            Err(e) => futures::stream::once(async { Err(e) }).boxed(),
+        };
+        Some(
+            items
+                .map(move |item| item.and_then(|v| v.encode(codec)))
+                .boxed(),
+        )
+    }
+}
+
+/// A method reading a stream of requests, see `Server::register_upload_fn`.
+pub struct UploadFn<Req, Resp> {
+    pub f: Box<dyn Fn(RequestStream<Req>) -> BoxFuture<'static, Result<Resp>> + Send + Sync>,
 }
 
-impl<Req: DeserializeOwned, Resp: Serialize> HandleFn<Req, Resp> {
+impl<Req, Resp> UploadFn<Req, Resp> {
     pub fn new<F: 'static>(f: F) -> Self
     where
-        F: Fn(Req) -> BoxFuture<'static, Result<Resp>>,
+        F: Fn(RequestStream<Req>) -> BoxFuture<'static, Result<Resp>> + Send + Sync,
     {
         Self { f: Box::new(f) }
     }
 }
 
-impl<C: Codec + 'static> Server<C> {
+impl<C: Format + 'static, Req: Decode<C> + Send + 'static, Resp: Encode<C>> Handler<C>
+    for UploadFn<Req, Resp>
+{
+    type Req = Req;
+    type Resp = Resp;
+
+    fn handle(&self, _req: Self::Req) -> BoxFuture<'_, Result<Self::Resp>> {
+        Box::pin(async {
+            Err(Error::from(
+                "method reads a stream of requests, call it with call_upload!",
+            ))
+        })
+    }
+
+    fn accept_upload<'a>(
+        &'a self,
+        reqs: BoxStream<'static, Result<Bytes>>,
+        codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<Vec<u8>>>> {
+        let decoder = codec.clone();
+        let reqs = reqs
+            .map(move |req| req.and_then(|data| Req::decode(&decoder, &data)))
+            .boxed();
+        let f = (self.f)(reqs);
+        Some(Box::pin(async move { f.await?.encode(codec) }))
+    }
+}
+
+type BidiBox<Req, Resp> = Box<
+    dyn Fn(RequestStream<Req>, ResponseSender<Resp>) -> BoxFuture<'static, Result<()>>
+        + Send
+        + Sync,
+>;
+
+/// A bidi streaming method, see `Server::register_bidi_fn`.
+pub struct BidiFn<Req, Resp> {
+    pub f: BidiBox<Req, Resp>,
+}
+
+impl<Req, Resp> BidiFn<Req, Resp> {
+    pub fn new<F: 'static>(f: F) -> Self
+    where
+        F: Fn(RequestStream<Req>, ResponseSender<Resp>) -> BoxFuture<'static, Result<()>>
+            + Send
+            + Sync,
+    {
+        Self { f: Box::new(f) }
+    }
+}
+
+impl<C: Format + 'static, Req: Decode<C> + Send + 'static, Resp: Encode<C> + 'static> Handler<C>
+    for BidiFn<Req, Resp>
+{
+    type Req = Req;
+    type Resp = Resp;
+
+    fn handle(&self, _req: Self::Req) -> BoxFuture<'_, Result<Self::Resp>> {
+        Box::pin(async {
+            Err(Error::from(
+                "method is bidi streaming, call it with call_bidi!",
+            ))
+        })
+    }
+
+    fn accept_bidi<'a>(
+        &'a self,
+        reqs: BoxStream<'static, Result<Bytes>>,
+        rsps: mpsc::Sender<Vec<u8>>,
+        codec: &'a C,
+    ) -> Option<BoxFuture<'a, Result<()>>> {
+        let decoder = codec.clone();
+        let reqs = reqs
+            .map(move |req| req.and_then(|data| Req::decode(&decoder, &data)))
+            .boxed();
+        let encoder = codec.clone();
+        let rsps = ResponseSender::new(rsps, move |resp: Resp| resp.encode(&encoder));
+        Some((self.f)(reqs, rsps))
+    }
+}
+
+impl<Req, Resp> HandleFn<Req, Resp> {
+    pub fn new<F: 'static>(f: F) -> Self
+    where
+        F: Fn(Req) -> BoxFuture<'static, Result<Resp>> + Send + Sync,
+    {
+        Self {
+            f: Box::new(f),
+            fingerprint: None,
+        }
+    }
+}
+
+impl<C: Format + 'static> Server<C> {
     /// Register a handle into the server.
-    pub async fn register<H: 'static>(&mut self, name: &str, handle: H)
+    /// Fails when its `method_id` is taken, see `insert_handle`.
+    pub async fn register<H: 'static>(&mut self, name: &str, handle: H) -> Result<()>
     where
         H: Stub<C>,
     {
-        self.handles.insert(name.to_owned(), Box::new(handle));
+        self.insert_handle(name, Box::new(handle))
     }
 
     /// Register a `register_box_future` into the server.
     pub fn register_box_future<
-        Req: DeserializeOwned + Send + 'static,
-        Resp: Serialize + 'static,
+        Req: Decode<C> + Send + 'static,
+        Resp: Encode<C> + 'static,
         F: 'static,
     >(
         &mut self,
         name: &str,
         f: F,
-    ) where
-        F: Fn(Req) -> BoxFuture<'static, Result<Resp>>,
+    ) -> Result<()>
+    where
+        F: Fn(Req) -> BoxFuture<'static, Result<Resp>> + Send + Sync,
     {
-        self.handles
-            .insert_mut(name.to_owned(), Box::new(HandleFn::new(f)));
+        self.insert_handle(name, Box::new(HandleFn::new(f)))
     }
 
-    /// Register a callback into the server.
+    /// Register a callback into the server, it may take extractors(`FromContext`)
+    /// before its request, e.g. the `Context` of the call or the `State` of the server.
     /// For example:
     /// ```
+    /// use drpc::context::Context;
     /// use drpc::server::{Server};
     /// use dark_std::errors::Result;
     /// let mut s = Server::default();
     ///
     /// async fn handle(req: i32) -> dark_std::errors::Result<i32> { return Ok(req + 1); }
-    ///     s.register_fn("handle", handle);
+    ///     s.register_fn("handle", handle).unwrap();
     ///     // way 2
     ///     s.register_fn("handle_fn2", |arg:i32| async move {
     ///         Ok(1)
-    ///     });
+    ///     }).unwrap();
+    ///     // way 3
+    ///     s.register_fn("handle_fn3", |ctx: Context, arg: i32| async move {
+    ///         Ok(ctx.id)
+    ///     }).unwrap();
+    /// ```
+    /// Breaking change: it took the generics `<Req, Resp, Out, F>` and returned `()`,
+    /// now `T` is the tuple of the arguments of `f` and a taken name fails.
+    /// A turbofish `register_fn::<Req, Resp, _, _>(..)` becomes `register_fn::<(Req,), _>(..)`,
+    /// or `register_method` to name the types:
     /// ```
-    pub fn register_fn<
-        Req: DeserializeOwned + Send + 'static,
-        Resp: Serialize + 'static,
+    /// use drpc::server::Server;
+    /// let mut s = Server::default();
+    /// s.register_fn::<(i32,), _>("add_one", |arg| async move { Ok(arg + 1) }).unwrap();
+    /// ```
+    pub fn register_fn<T, F: HandlerFn<C, T>>(&mut self, name: &str, f: F) -> Result<()> {
+        F::check(self.stub.state.as_deref())
+            .map_err(|e| Error::from(format!("method '{}': {}", name, e)))?;
+        self.insert_handle(name, f.into_stub())
+    }
+
+    /// Register a method answering a stream of responses, read by `Client::call_stream`.
+    /// The items are sent while the caller grants them, the stream is dropped
+    /// when the caller cancels it.
+    pub fn register_stream_fn<
+        Req: Decode<C> + Send + 'static,
+        Resp: Encode<C> + Send + 'static,
+        St: 'static,
+        F: 'static,
+    >(
+        &mut self,
+        name: &str,
+        f: F,
+    ) -> Result<()>
+    where
+        St: Stream<Item = Result<Resp>> + Send,
+        F: Fn(Req) -> St + Send + Sync,
+    {
+        self.insert_handle(
+            name,
+            Box::new(StreamFn::new(
+                move |req: Req| -> BoxStream<'static, Result<Resp>> { (f)(req).boxed() },
+            )),
+        )
+    }
+
+    /// Register a method reading a stream of requests, sent by `Client::call_upload`.
+    /// The caller sends the requests while the server grants them, see `set_window`.
+    pub fn register_upload_fn<
+        Req: Decode<C> + Send + 'static,
+        Resp: Encode<C> + 'static,
         Out: 'static,
         F: 'static,
     >(
         &mut self,
         name: &str,
         f: F,
-    ) where
+    ) -> Result<()>
+    where
         Out: Future<Output = Result<Resp>> + Send,
-        F: Fn(Req) -> Out,
+        F: Fn(RequestStream<Req>) -> Out + Send + Sync,
     {
-        self.handles.insert_mut(
-            name.to_owned(),
-            Box::new(HandleFn::new(
-                move |req: Req| -> BoxFuture<'static, Result<Resp>> { Box::pin((f)(req)) },
+        self.insert_handle(
+            name,
+            Box::new(UploadFn::new(
+                move |reqs: RequestStream<Req>| -> BoxFuture<'static, Result<Resp>> {
+                    Box::pin((f)(reqs))
+                },
             )),
-        );
+        )
+    }
+
+    /// Register a bidi streaming method, called by `Client::call_bidi`.
+    /// The method reads the requests and sends the responses until it returns,
+    /// dropping the sender ends the responses first.
+    pub fn register_bidi_fn<
+        Req: Decode<C> + Send + 'static,
+        Resp: Encode<C> + 'static,
+        Out: 'static,
+        F: 'static,
+    >(
+        &mut self,
+        name: &str,
+        f: F,
+    ) -> Result<()>
+    where
+        Out: Future<Output = Result<()>> + Send,
+        F: Fn(RequestStream<Req>, ResponseSender<Resp>) -> Out + Send + Sync,
+    {
+        self.insert_handle(
+            name,
+            Box::new(BidiFn::new(
+                move |reqs: RequestStream<Req>,
+                      rsps: ResponseSender<Resp>|
+                      -> BoxFuture<'static, Result<()>> {
+                    Box::pin((f)(reqs, rsps))
+                },
+            )),
+        )
+    }
+
+    /// Same as `register_fn`, with the name and types of `method`.
+    /// With the `schema` feature the schemas of `Req`/`Resp` are recorded for the reflection.
+    pub fn register_method<
+        Req: Decode<C> + Schema + Send + 'static,
+        Resp: Encode<C> + Schema + 'static,
+        Out: 'static,
+        F: 'static,
+    >(
+        &mut self,
+        method: &Method<Req, Resp>,
+        f: F,
+    ) -> Result<()>
+    where
+        Out: Future<Output = Result<Resp>> + Send,
+        F: Fn(Req) -> Out + Send + Sync,
+    {
+        self.register_fn(method.name, f)?;
+        #[cfg(feature = "schema")]
+        self.register_schema::<Req, Resp>(method.name);
+        Ok(())
+    }
+
+    /// Same as `register_fn`, with the fingerprint of `Req`/`Resp` checked against the one
+    /// a caller sends(`Client::call_checked`) when the server checks fingerprints(`set_fingerprint`).
+    /// `Resp` must be decodable too, to be fingerprinted.
+    #[cfg(feature = "fingerprint")]
+    pub fn register_fn_checked<
+        Req: Decode<C> + Send + 'static,
+        Resp: Encode<C> + Decode<C> + 'static,
+        Out: 'static,
+        F: 'static,
+    >(
+        &mut self,
+        name: &str,
+        f: F,
+    ) -> Result<()>
+    where
+        Out: Future<Output = Result<Resp>> + Send,
+        F: Fn(Req) -> Out + Send + Sync,
+    {
+        let mut handle = HandleFn::new(move |req: Req| -> BoxFuture<'static, Result<Resp>> {
+            Box::pin((f)(req))
+        });
+        handle.fingerprint = fingerprint::method::<C, Req, Resp>();
+        self.insert_handle(name, Box::new(handle))
+    }
+
+    /// Insert the handle and its `method_id`, a handle of the same name is replaced.
+    /// Fails when the id is taken by another method or by a built-in one(`batch::METHOD`,
+    /// `reflect::METHOD`, `handshake::METHOD`), rename the method.
+    fn insert_handle(&mut self, name: &str, handle: Box<dyn Stub<C>>) -> Result<()> {
+        let id = method_id(name);
+        for reserved in [batch::METHOD, reflect::METHOD, handshake::METHOD] {
+            if id == method_id(reserved) {
+                return Err(Error::from(format!(
+                    "method '{}' has the method id={} of the built-in '{}'!",
+                    name, id, reserved
+                )));
+            }
+        }
+        if let Some((old, _)) = self.ids.get(&id) {
+            if old != name {
+                return Err(Error::from(format!(
+                    "method '{}' and '{}' have the same method id={}!",
+                    old, name, id
+                )));
+            }
+        }
+        self.insert_stub(name, handle);
+        Ok(())
+    }
+
+    /// Insert the handle and its `method_id` unchecked, for the built-in methods.
+    fn insert_stub(&mut self, name: &str, handle: Box<dyn Stub<C>>) {
+        let id = method_id(name);
+        let handle: Arc<dyn Stub<C>> = Arc::from(handle);
+        self.ids.insert_mut(id, (name.to_owned(), handle.clone()));
+        self.handles.insert(name.to_owned(), handle);
+        if self.methods.get(name).is_none() {
+            self.methods.insert(
+                name.to_owned(),
+                MethodInfo {
+                    name: name.to_owned(),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    /// Answer the `ServiceInfo` of the server at `reflect::METHOD`, see `Client::reflect`.
+    /// It lists the methods with their descriptions(`describe`) and, with the `schema` feature,
+    /// their JSON Schemas(`register_method`, `register_schema`).
+    pub fn set_reflection(mut self, reflection: bool) -> Self
+    where
+        (): Decode<C>,
+        ServiceInfo: Encode<C>,
+    {
+        if reflection {
+            let methods = self.methods.clone();
+            let handle = HandlerFn::<C, _>::into_stub(move |_: ()| {
+                let info = ServiceInfo::of(&methods);
+                async move { Ok(info) }
+            });
+            // the methods of the users can not take its id
+            self.insert_stub(reflect::METHOD, handle);
+            self.describe(reflect::METHOD, "list the methods of the server");
+        } else if self.handles.remove(&reflect::METHOD.to_owned()).is_some() {
+            self.ids.remove_mut(&method_id(reflect::METHOD));
+            self.methods.remove(&reflect::METHOD.to_owned());
+        }
+        self
+    }
+
+    /// Set the description of the method `name` in the reflection.
+    pub fn describe(&mut self, name: &str, description: &str) {
+        self.method_info(name, |info| info.description = Some(description.to_owned()));
+    }
+
+    /// Set the request and response JSON Schemas of the method `name` in the reflection,
+    /// for the methods not registered by `register_method`.
+    #[cfg(feature = "schema")]
+    pub fn register_schema<Req: schemars::JsonSchema, Resp: schemars::JsonSchema>(
+        &mut self,
+        name: &str,
+    ) {
+        self.method_info(name, |info| {
+            info.request = Some(reflect::schema::<Req>());
+            info.response = Some(reflect::schema::<Resp>());
+        });
+    }
+
+    fn method_info<F: FnOnce(&mut MethodInfo)>(&mut self, name: &str, f: F) {
+        let mut info = self
+            .methods
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| MethodInfo {
+                name: name.to_owned(),
+                ..Default::default()
+            });
+        f(&mut info);
+        self.methods.insert(name.to_owned(), info);
     }
 
     pub async fn serve<A>(self, addr: A)
@@ -180,10 +1030,12 @@ impl<C: Codec + 'static> Server<C> {
         );
         let server = Arc::new(self);
         loop {
-            if let Ok((stream, _)) = listener.accept().await {
+            if let Ok((stream, addr)) = listener.accept().await {
+                // the small frames of the streams are not held back for the acks
+                let _ = stream.set_nodelay(true);
                 let server = server.clone();
                 tokio::spawn(async move {
-                    server.call(stream).await;
+                    server.call_from(stream, addr).await;
                 });
             }
         }
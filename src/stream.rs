@@ -0,0 +1,253 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use dark_std::errors::{Error, Result};
+use futures::stream::BoxStream;
+use futures::Stream;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::codec::{Encode, Format};
+use crate::mux::{Mux, Open};
+use crate::stub::ClientStub;
+
+use crate::frame::{
+    get_varint, put_varint, Frame, FrameVersion, CONTROL_CANCEL, CONTROL_WINDOW, FLAG_CONTROL,
+    FLAG_STREAM,
+};
+
+/// the items a stream sends ahead when the caller does not say, see `metadata::WINDOW`
+pub const DEFAULT_WINDOW: u32 = 16;
+
+/// A control frame of the stream with the frame id, sent by the reader of the stream.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Control {
+    /// the reader can take `n` more items
+    Window(u64),
+    /// the caller dropped the stream
+    Cancel,
+}
+
+impl Control {
+    pub fn into_frame(self, id: u64) -> Frame {
+        let mut buf = BytesMut::new();
+        match self {
+            Control::Window(n) => {
+                buf.put_u8(CONTROL_WINDOW);
+                put_varint(&mut buf, n);
+            }
+            Control::Cancel => buf.put_u8(CONTROL_CANCEL),
+        }
+        let mut f = Frame::new();
+        f.id = id;
+        f.ok = 1;
+        f.flags = FLAG_CONTROL;
+        f.version = FrameVersion::V2;
+        f.data = buf.freeze();
+        f
+    }
+
+    /// The stream control of a frame, `None` for any other frame.
+    pub fn decode(frame: &Frame) -> Option<Self> {
+        if !frame.has_flag(FLAG_CONTROL) || frame.has_flag(FLAG_STREAM) {
+            return None;
+        }
+        match frame.data.first() {
+            Some(&CONTROL_WINDOW) => {
+                let mut pos = 1;
+                get_varint(&frame.data, &mut pos)
+                    .ok()
+                    .flatten()
+                    .map(Control::Window)
+            }
+            Some(&CONTROL_CANCEL) => Some(Control::Cancel),
+            _ => None,
+        }
+    }
+}
+
+/// The frame ending the stream with id `id`, an error is sent to the reader.
+pub fn end_frame(id: u64, result: Result<()>) -> Frame {
+    let mut f = Frame::new();
+    f.id = id;
+    f.flags = FLAG_STREAM | FLAG_CONTROL;
+    f.version = FrameVersion::V2;
+    match result {
+        Ok(_) => f.ok = 1,
+        Err(e) => f.data = Bytes::from(e.to_string()),
+    }
+    f
+}
+
+/// The requests an upload method reads, see `Server::register_upload_fn`.
+/// It ends with an error when the caller aborts the upload.
+pub type RequestStream<Req> = BoxStream<'static, Result<Req>>;
+
+/// The sending half of a bidi method's responses, see `Server::register_bidi_fn`.
+/// Dropping it ends the responses, the method may go on reading the requests.
+pub struct ResponseSender<Resp> {
+    tx: mpsc::Sender<Vec<u8>>,
+    encode: Box<dyn Fn(Resp) -> Result<Vec<u8>> + Send + Sync>,
+}
+
+impl<Resp> ResponseSender<Resp> {
+    pub fn new<F>(tx: mpsc::Sender<Vec<u8>>, encode: F) -> Self
+    where
+        F: Fn(Resp) -> Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        Self {
+            tx,
+            encode: Box::new(encode),
+        }
+    }
+
+    /// Send a response, waiting while the caller does not grant it.
+    /// Fails once the caller cancelled the responses.
+    pub async fn send(&self, resp: Resp) -> Result<()> {
+        let data = (self.encode)(resp)?;
+        self.tx
+            .send(data)
+            .await
+            .map_err(|_| Error::from("stream cancelled!"))
+    }
+}
+
+/// The sending half of a bidi call, see `ClientStub::call_bidi`.
+/// `close` or dropping it ends the requests, the responses go on.
+pub struct RequestSender<'a, C: Format, Arg> {
+    id: u64,
+    stub: &'a ClientStub,
+    codec: &'a C,
+    mux: &'a Mux,
+    /// the requests the server granted
+    credits: Arc<Semaphore>,
+    _route: Option<Arc<Open<'a>>>,
+    closed: bool,
+    _arg: PhantomData<fn(Arg)>,
+}
+
+impl<'a, C: Format, Arg> RequestSender<'a, C, Arg> {
+    pub fn new(
+        id: u64,
+        stub: &'a ClientStub,
+        codec: &'a C,
+        mux: &'a Mux,
+        credits: Arc<Semaphore>,
+        route: Option<Arc<Open<'a>>>,
+    ) -> Self {
+        Self {
+            id,
+            stub,
+            codec,
+            mux,
+            credits,
+            _route: route,
+            closed: false,
+            _arg: PhantomData,
+        }
+    }
+
+    /// The id of the call.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// End the requests.
+    pub fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            let _ = self.mux.send(end_frame(self.id, Ok(())));
+        }
+    }
+}
+
+impl<C: Format, Arg: Encode<C>> RequestSender<'_, C, Arg> {
+    /// Send a request, waiting while the server does not grant it.
+    pub async fn send(&mut self, arg: Arg) -> Result<()> {
+        if self.closed {
+            return Err(Error::from("requests are closed!"));
+        }
+        let permit = self
+            .credits
+            .acquire()
+            .await
+            .map_err(|_| Error::from("connection closed!"))?;
+        permit.forget();
+        let f = self.stub.item_frame(self.id, arg, self.codec)?;
+        self.mux.send(f)?;
+        Ok(())
+    }
+}
+
+impl<C: Format, Arg> Drop for RequestSender<'_, C, Arg> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// The responses of a streaming call, see `ClientStub::call_stream`.
+/// It ends after an error. Dropped before its end, the stream is cancelled at once
+/// over a shared connection(`Mux`), else with the next call over the connection.
+pub struct ResponseStream<'a, Resp> {
+    id: u64,
+    items: BoxStream<'a, Result<Resp>>,
+    cancels: &'a Mutex<Vec<u64>>,
+    /// writes the cancel when the stream is dropped, see `Transport::sender`
+    out: Option<mpsc::UnboundedSender<Frame>>,
+    done: bool,
+}
+
+impl<'a, Resp> ResponseStream<'a, Resp> {
+    pub fn new(
+        id: u64,
+        items: BoxStream<'a, Result<Resp>>,
+        cancels: &'a Mutex<Vec<u64>>,
+        out: Option<mpsc::UnboundedSender<Frame>>,
+    ) -> Self {
+        Self {
+            id,
+            items,
+            cancels,
+            out,
+            done: false,
+        }
+    }
+
+    /// The id of the call.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl<Resp> Stream for ResponseStream<'_, Resp> {
+    type Item = Result<Resp>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let v = self.items.as_mut().poll_next(cx);
+        if matches!(v, Poll::Ready(None) | Poll::Ready(Some(Err(_)))) {
+            self.done = true;
+        }
+        v
+    }
+}
+
+impl<Resp> Drop for ResponseStream<'_, Resp> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let sent = match &self.out {
+            Some(out) => out.send(Control::Cancel.into_frame(self.id)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            if let Ok(mut cancels) = self.cancels.lock() {
+                cancels.push(self.id);
+            }
+        }
+    }
+}
@@ -1,17 +1,113 @@
+use bytes::{BufMut, Bytes, BytesMut};
 use dark_std::errors::Error;
 use dark_std::errors::Result;
 use dark_std::sync::map_hash::SyncHashMap;
+use futures::future::{BoxFuture, Either};
+use futures::stream::{BoxStream, FuturesUnordered};
+use futures::{Stream, StreamExt};
 use log::{debug, error};
-use serde::de::DeserializeOwned;
-use serde::Serialize;
+use std::any::Any;
+use std::collections::HashMap;
 use std::future::Future;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_util::codec::Framed;
 
-use crate::codec::Codec;
-use crate::frame::Frame;
+use crate::batch::{self, BatchRequest, BatchResponse};
+use crate::codec::{Decode, Encode, Format};
+use crate::compress::Compression;
+use crate::context::Context;
+use crate::frame::{
+    ChecksumError, Frame, FrameCodec, FrameVersion, DEFAULT_MAX_FRAME_LEN, FLAG_COMPRESSED,
+    FLAG_CONTROL, FLAG_ONEWAY, FLAG_STREAM,
+};
+use crate::handshake::Handshake;
+use crate::interceptor::{self, Interceptors};
+use crate::metadata::{self, Metadata};
+use crate::middleware::{Middleware, Next, Request};
+use crate::mux::{Mux, Routes};
+use crate::peer::Peer;
 use crate::server::Stub;
+use crate::stream::{self, Control, RequestSender, ResponseStream, DEFAULT_WINDOW};
+
+/// the response frames a connection buffers before its calls wait for the writes
+const WRITE_BUFFER: usize = 64;
+
+/// The stable numeric id of a method, the 64 bits FNV-1a hash of its name.
+/// A client sends it in the frame header instead of the method name.
+pub const fn method_id(name: &str) -> u64 {
+    let bytes = name.as_bytes();
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Is `id` the id of a call the accepting side makes back to the one that dialed,
+/// see `ClientStub::id_bit`.
+pub const fn is_callback(id: u64) -> bool {
+    id & 1 == 0
+}
+
+/// The frames of the calls over a connection: a `Framed` read and written by one call
+/// at a time, or a `MuxCall` sharing the connection with other calls.
+pub trait Transport: Send {
+    /// Read the frames with id `id`, called before the request of a call is sent.
+    fn open(&mut self, _id: u64) {}
+    fn send(&mut self, frame: Frame) -> impl Future<Output = std::io::Result<()>> + Send;
+    /// The next frame, `None` once the connection is closed.
+    fn recv(&mut self) -> impl Future<Output = Option<std::io::Result<Frame>>> + Send;
+    /// Writes a frame without the transport, so a dropped stream is cancelled at once.
+    /// `None` when the frames only go by `send`, the cancel then waits for the next call.
+    fn sender(&self) -> Option<mpsc::UnboundedSender<Frame>> {
+        None
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Transport for Framed<S, FrameCodec> {
+    async fn send(&mut self, frame: Frame) -> std::io::Result<()> {
+        let checksum = self.codec().checksum;
+        frame.write_to(self.get_mut(), checksum).await
+    }
+
+    async fn recv(&mut self) -> Option<std::io::Result<Frame>> {
+        let frame = self.next().await;
+        if let Some(Err(e)) = &frame {
+            if ChecksumError::from_io(e).is_some() {
+                // the stream can not be trusted any more
+                let _ = self.get_mut().shutdown().await;
+            }
+        }
+        frame
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn open(&mut self, id: u64) {
+        (**self).open(id)
+    }
+
+    fn send(&mut self, frame: Frame) -> impl Future<Output = std::io::Result<()>> + Send {
+        (**self).send(frame)
+    }
+
+    fn recv(&mut self) -> impl Future<Output = Option<std::io::Result<Frame>>> + Send {
+        (**self).recv()
+    }
+
+    fn sender(&self) -> Option<mpsc::UnboundedSender<Frame>> {
+        (**self).sender()
+    }
+}
 
 /// Pack the client request parameters into a network message,
 /// which is then sent to the server remotely over the network.
@@ -19,6 +115,32 @@ use crate::server::Stub;
 pub struct ClientStub {
     pub timeout: Option<Duration>,
     pub tag: AtomicU64,
+    /// the compression negotiated by `handshake`
+    pub compression: Option<Compression>,
+    /// compress the request payloads that are at least this long
+    pub compress_threshold: Option<usize>,
+    /// address methods by `method_id` instead of by name, true by default
+    pub method_id: bool,
+    /// the codec id negotiated by `handshake`, a call in another codec names it in its metadata
+    pub codec: Option<u8>,
+    /// the items a stream sends ahead of the ones read, see `call_stream`
+    pub window: u32,
+    /// the streams dropped before their end, cancelled with the next call
+    pub cancels: std::sync::Mutex<Vec<u64>>,
+    /// the low bit of the call ids, 1 for the side that dials and 0 for the side
+    /// calling it back, so the calls of both directions share the connection
+    pub id_bit: u64,
+    /// run around the unary calls and notifications
+    pub interceptors: Interceptors,
+    /// the version of the request frames, v1 until the server agrees on a `handshake`.
+    /// v1 requests name their method, metadata, streams and notifications need v2
+    pub version: FrameVersion,
+}
+
+impl Default for ClientStub {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ClientStub {
@@ -26,10 +148,56 @@ impl ClientStub {
         Self {
             timeout: None,
             tag: AtomicU64::new(0),
+            compression: None,
+            compress_threshold: None,
+            method_id: true,
+            codec: None,
+            window: DEFAULT_WINDOW,
+            cancels: std::sync::Mutex::new(vec![]),
+            id_bit: 1,
+            interceptors: Interceptors::default(),
+            version: FrameVersion::V1,
         }
     }
 
-    pub async fn call_frame<C: Codec, Arg: Serialize, Resp: DeserializeOwned, F, Transport>(
+    /// Negotiate the connection options with the server, must be the first frame of the stream.
+    /// The negotiated checksum is switched on in the stream's `FrameCodec`, and the
+    /// requests after it go in v2 frames.
+    /// A server that does not speak the handshake agrees on none of the options,
+    /// it is called with v1 frames.
+    pub async fn handshake<S>(
+        &mut self,
+        handshake: &Handshake,
+        stream: &mut Framed<S, FrameCodec>,
+    ) -> Result<Handshake>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        handshake
+            .clone()
+            .into_frame()
+            .write_to(stream.get_mut(), false)
+            .await?;
+        let rsp_frame = tokio::time::timeout(self.get_timeout(), stream.next())
+            .await
+            .map_err(|_| Error::from("rpc handshake timeout!"))?
+            .ok_or_else(|| Error::from("rpc handshake fail: connection closed!"))??;
+        let agreed = match Handshake::from_answer(&rsp_frame) {
+            Some(agreed) => agreed,
+            None => {
+                // a server from before the handshake answers it as a failed call
+                debug!("server does not speak the handshake, use the defaults");
+                return Ok(Handshake::none());
+            }
+        };
+        self.version = FrameVersion::V2;
+        self.compression = agreed.compression();
+        self.codec = agreed.codec;
+        stream.codec_mut().checksum = agreed.checksum;
+        Ok(agreed)
+    }
+
+    pub async fn call_frame<C: Format, Arg: Encode<C>, Resp: Decode<C>, F, Transport>(
         &self,
         method: &str,
         arg: Arg,
@@ -40,189 +208,1563 @@ impl ClientStub {
         F: Future<Output = Frame>,
         Transport: FnOnce(Frame) -> F,
     {
-        let mut arg_data = method.to_string().into_bytes();
-        arg_data.push('\n' as u8);
-        arg_data.extend(codec.encode(arg)?);
-        let mut req_buf = Frame::new();
-        req_buf.write_all(&arg_data).await?;
-        let id = {
-            let mut id = self.tag.load(Ordering::SeqCst);
-            if id == u64::MAX {
-                id = 0;
-            } else {
-                id += 1;
-            }
-            self.tag.store(id, Ordering::SeqCst);
-            id
-        };
-        debug!("request id = {}", id);
+        self.call_frame_with(method, arg, codec, false, transport)
+            .await
+    }
+
+    /// Same as `call_frame`, `compress` forces the request to be compressed
+    /// when the connection negotiated a compression.
+    pub async fn call_frame_with<C: Format, Arg: Encode<C>, Resp: Decode<C>, F, Transport>(
+        &self,
+        method: &str,
+        arg: Arg,
+        codec: &C,
+        compress: bool,
+        transport: Transport,
+    ) -> Result<Resp>
+    where
+        F: Future<Output = Frame>,
+        Transport: FnOnce(Frame) -> F,
+    {
+        self.call_frame_with_metadata(method, arg, codec, compress, Metadata::new(), transport)
+            .await
+    }
+
+    /// Same as `call_frame_with`, `metadata` goes in the metadata block of the request.
+    pub async fn call_frame_with_metadata<
+        C: Format,
+        Arg: Encode<C>,
+        Resp: Decode<C>,
+        F,
+        Transport,
+    >(
+        &self,
+        method: &str,
+        arg: Arg,
+        codec: &C,
+        compress: bool,
+        metadata: Metadata,
+        transport: Transport,
+    ) -> Result<Resp>
+    where
+        F: Future<Output = Frame>,
+        Transport: FnOnce(Frame) -> F,
+    {
+        let req_buf = self.request_frame(method, arg, codec, compress, metadata)?;
+        let id = req_buf.id;
         let rsp_frame = transport(req_buf).await;
         // discard the rsp that is is not belong to us
         debug!("get response id = {}", id);
+        self.response(rsp_frame, codec)
+    }
+
+    /// Build the request frame of a call, with the next id.
+    fn request_frame<C: Format, Arg: Encode<C>>(
+        &self,
+        method: &str,
+        arg: Arg,
+        codec: &C,
+        compress: bool,
+        metadata: Metadata,
+    ) -> Result<Frame> {
+        self.request_frame_with(method, codec, compress, metadata, |buf| {
+            arg.encode_into(codec, buf)
+        })
+    }
+
+    /// Same as `request_frame`, `body` writes the payload after the method.
+    fn request_frame_with<C: Format, B>(
+        &self,
+        method: &str,
+        codec: &C,
+        compress: bool,
+        mut metadata: Metadata,
+        body: B,
+    ) -> Result<Frame>
+    where
+        B: FnOnce(&mut BytesMut) -> Result<()>,
+    {
+        let mut req_buf = Frame::new();
+        req_buf.version = self.version;
+        let v2 = self.version == FrameVersion::V2;
+        let mut arg_data = BytesMut::new();
+        if self.method_id && v2 {
+            req_buf.method_id = Some(method_id(method));
+        } else {
+            arg_data.put_slice(method.as_bytes());
+            arg_data.put_u8(b'\n');
+        }
+        if v2 {
+            let codec_id = codec.id();
+            if codec_id != 0 && self.codec != Some(codec_id) {
+                metadata.insert(metadata::CODEC, vec![codec_id]);
+            }
+            req_buf.metadata = metadata.encode();
+        } else if !metadata.is_empty() {
+            self.require_v2("metadata")?;
+        }
+        body(&mut arg_data)?;
+        let mut arg_data = arg_data.freeze();
+        if let Some(compression) = self.compression {
+            let over_threshold = self
+                .compress_threshold
+                .map(|threshold| arg_data.len() >= threshold)
+                .unwrap_or(false);
+            if compress || over_threshold {
+                arg_data = Bytes::from(compression.compress(&arg_data)?);
+                req_buf.flags |= FLAG_COMPRESSED;
+            }
+        }
+        req_buf.data = arg_data;
+        let id = self.tag.fetch_add(1, Ordering::SeqCst).wrapping_mul(2) | self.id_bit;
+        debug!("request id = {}", id);
+        req_buf.id = id;
+        Ok(req_buf)
+    }
+
+    /// Decode the response frame of a call.
+    fn response<C: Format, Resp: Decode<C>>(&self, rsp_frame: Frame, codec: &C) -> Result<Resp> {
+        let rsp_data = self.response_data(rsp_frame)?;
+        let resp = Resp::decode(codec, &rsp_data)?;
+        Ok(resp)
+    }
+
+    /// The payload of a response frame, decompressed.
+    fn response_data(&self, rsp_frame: Frame) -> Result<Bytes> {
         if rsp_frame.ok == 0 {
             let rsp_data = rsp_frame.get_payload();
             //it's safety.rsp_data when ok = 0 must be string(utf8) data
             let resp: String = unsafe { String::from_utf8_unchecked(rsp_data.to_vec()) };
-            return Err(Error { inner: resp });
+            Err(Error { inner: resp })
         } else {
-            let rsp_data = rsp_frame.get_payload();
-            let resp: Resp = codec.decode(rsp_data)?;
-            return Ok(resp);
+            let rsp_data = if rsp_frame.has_flag(FLAG_COMPRESSED) {
+                let compression = self.compression.ok_or_else(|| {
+                    Error::from("rsp is compressed, but no compression negotiated!")
+                })?;
+                Bytes::from(compression.decompress(rsp_frame.get_payload(), DEFAULT_MAX_FRAME_LEN)?)
+            } else {
+                rsp_frame.data
+            };
+            Ok(rsp_data)
         }
     }
 
-    pub async fn call<C: Codec, Arg: Serialize, Resp: DeserializeOwned, S>(
+    pub async fn call<C: Format, Arg: Encode<C>, Resp: Decode<C>, T: Transport>(
         &self,
         method: &str,
         arg: Arg,
         codec: &C,
-        mut stream: S,
-    ) -> Result<Resp>
-    where
-        S: AsyncRead + AsyncWrite + Unpin,
-    {
-        self.call_frame(method, arg, codec, move |req_buf: Frame| async move {
-            let id = req_buf.id;
-            let data = req_buf.finish(id);
-            if let Err(e) = stream.write_all(&data).await {
-                return Frame {
-                    id,
-                    ok: 0,
-                    data: e.to_string().into_bytes(),
-                };
-            }
-            let timeout = self.get_timeout();
-            let v = tokio::time::timeout(timeout, async {
-                loop {
-                    // deserialize the rsp
-                    let rsp_frame = Frame::decode_from(&mut stream)
-                        .await
-                        .map_err(|e| Error::from(e));
-                    if rsp_frame.is_err() {
+        transport: T,
+    ) -> Result<Resp> {
+        self.call_with(method, arg, codec, false, transport).await
+    }
+
+    /// Same as `call`, `compress` forces the request to be compressed
+    /// when the connection negotiated a compression.
+    pub async fn call_with<C: Format, Arg: Encode<C>, Resp: Decode<C>, T: Transport>(
+        &self,
+        method: &str,
+        arg: Arg,
+        codec: &C,
+        compress: bool,
+        transport: T,
+    ) -> Result<Resp> {
+        self.call_with_metadata(method, arg, codec, compress, Metadata::new(), transport)
+            .await
+    }
+
+    /// Same as `call_with`, `metadata` goes in the metadata block of the request.
+    pub async fn call_with_metadata<C: Format, Arg: Encode<C>, Resp: Decode<C>, T: Transport>(
+        &self,
+        method: &str,
+        arg: Arg,
+        codec: &C,
+        compress: bool,
+        metadata: Metadata,
+        transport: T,
+    ) -> Result<Resp> {
+        if self.interceptors.is_empty() {
+            return self
+                .call_frame_with_metadata(method, arg, codec, compress, metadata, |req_buf| {
+                    self.round_trip(req_buf, transport)
+                })
+                .await;
+        }
+        let mut body = BytesMut::new();
+        arg.encode_into(codec, &mut body)?;
+        let rsp_data = self
+            .call_encoded(method, body.freeze(), codec, compress, metadata, transport)
+            .await?;
+        Resp::decode(codec, &rsp_data)
+    }
+
+    /// Same as `call_with_metadata`, the request is encoded already and so is the response.
+    pub async fn call_encoded<C: Format, T: Transport>(
+        &self,
+        method: &str,
+        body: Bytes,
+        codec: &C,
+        compress: bool,
+        metadata: Metadata,
+        transport: T,
+    ) -> Result<Bytes> {
+        let send: interceptor::SendFn = Box::new(move |req| {
+            Box::pin(async move {
+                let req_buf = self.intercepted_frame(req, codec, compress)?;
+                let rsp_frame = self.round_trip(req_buf, transport).await;
+                self.response_data(rsp_frame)
+            })
+        });
+        let req = interceptor::Request {
+            method,
+            metadata,
+            body,
+        };
+        interceptor::Next::new(&self.interceptors, send)
+            .run(req)
+            .await
+    }
+
+    /// Run the interceptors around a call, `send` writes the request they let through.
+    async fn intercept<'a, C: Format, Arg: Encode<C>>(
+        &'a self,
+        method: &'a str,
+        arg: Arg,
+        codec: &C,
+        metadata: Metadata,
+        send: interceptor::SendFn<'a>,
+    ) -> Result<Bytes> {
+        let mut body = BytesMut::new();
+        arg.encode_into(codec, &mut body)?;
+        let req = interceptor::Request {
+            method,
+            metadata,
+            body: body.freeze(),
+        };
+        interceptor::Next::new(&self.interceptors, send)
+            .run(req)
+            .await
+    }
+
+    /// The request frame of a call the interceptors let through.
+    fn intercepted_frame<C: Format>(
+        &self,
+        req: interceptor::Request,
+        codec: &C,
+        compress: bool,
+    ) -> Result<Frame> {
+        self.request_frame_with(req.method, codec, compress, req.metadata, |buf| {
+            buf.put_slice(&req.body);
+            Ok(())
+        })
+    }
+
+    /// Send a request over `transport` and read its response,
+    /// a failure is answered as an error frame.
+    async fn round_trip<T: Transport>(&self, req_buf: Frame, mut transport: T) -> Frame {
+        let id = req_buf.id;
+        transport.open(id);
+        let sent = match self.flush_cancels(&mut transport).await {
+            Ok(_) => transport.send(req_buf).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            return Frame {
+                id,
+                ok: 0,
+                data: Bytes::from(e.to_string()),
+                ..Frame::new()
+            };
+        }
+        let timeout = self.get_timeout();
+        let v = tokio::time::timeout(timeout, async {
+            loop {
+                // deserialize the rsp
+                let rsp_frame = match transport.recv().await {
+                    Some(Ok(v)) => v,
+                    Some(Err(e)) => {
                         return Frame {
                             id,
                             ok: 0,
-                            data: rsp_frame.err().unwrap().to_string().into_bytes(),
+                            data: Bytes::from(e.to_string()),
+                            ..Frame::new()
                         };
                     }
-                    let rsp_frame = rsp_frame.unwrap();
-                    // discard the rsp that is is not belong to us
-                    if rsp_frame.id == id {
-                        debug!("get response id = {}", id);
-                        return rsp_frame;
+                    None => {
+                        return Frame {
+                            id,
+                            ok: 0,
+                            data: Bytes::from("connection closed!"),
+                            ..Frame::new()
+                        };
                     }
+                };
+                // discard the rsp that is is not belong to us
+                if rsp_frame.id == id {
+                    debug!("get response id = {}", id);
+                    return rsp_frame;
                 }
+            }
+        })
+        .await;
+        match v {
+            Ok(v) => v,
+            Err(_e) => Frame {
+                id,
+                ok: 0,
+                data: Bytes::from("rpc call timeout!"),
+                ..Frame::new()
+            },
+        }
+    }
+
+    /// Send the calls of `batch` in one request frame(`batch::METHOD`),
+    /// each call is answered in the `BatchResponse`.
+    pub async fn call_batch<C: Format, T: Transport>(
+        &self,
+        batch: &BatchRequest,
+        codec: &C,
+        transport: T,
+    ) -> Result<BatchResponse<C>> {
+        let req =
+            self.request_frame_with(batch::METHOD, codec, false, batch.metadata(), |buf| {
+                batch.encode_into(buf);
+                Ok(())
+            })?;
+        let rsp = self.round_trip(req, transport).await;
+        let data = self.response_data(rsp)?;
+        BatchResponse::decode(&data, codec.clone())
+    }
+
+    /// Send a notification: the server serves the request and answers nothing,
+    /// so it returns once the request is written. A failure of the method is not seen here.
+    pub async fn notify<C: Format, Arg: Encode<C>, T: Transport>(
+        &self,
+        method: &str,
+        arg: Arg,
+        codec: &C,
+        mut transport: T,
+    ) -> Result<()> {
+        self.require_v2("notifications")?;
+        let send: interceptor::SendFn = Box::new(move |req| {
+            Box::pin(async move {
+                let mut req = self.intercepted_frame(req, codec, false)?;
+                req.flags |= FLAG_ONEWAY;
+                debug!("notify id = {}", req.id);
+                self.flush_cancels(&mut transport).await?;
+                transport.send(req).await?;
+                Ok(Bytes::new())
             })
-            .await;
-            match v {
-                Ok(v) => v,
-                Err(_e) => {
-                    return Frame {
-                        id,
-                        ok: 0,
-                        data: "rpc call timeout!".to_string().into_bytes(),
+        });
+        self.intercept(method, arg, codec, Metadata::new(), send)
+            .await
+            .map(|_| ())
+    }
+
+    /// Call a method answering a stream(`Server::register_stream_fn`),
+    /// `transport` is held until the stream is dropped.
+    /// The server sends up to `window` items ahead of the ones read.
+    pub async fn call_stream<'a, C, Arg, Resp, T>(
+        &'a self,
+        method: &str,
+        arg: Arg,
+        codec: &'a C,
+        mut transport: T,
+    ) -> Result<ResponseStream<'a, Resp>>
+    where
+        C: Format,
+        Arg: Encode<C>,
+        Resp: Decode<C> + Send + 'a,
+        T: Transport + 'a,
+    {
+        self.require_v2("streams")?;
+        let window = self.window.max(1);
+        let mut metadata = Metadata::new();
+        metadata.insert(metadata::WINDOW, window.to_be_bytes().to_vec());
+        let mut req_buf = self.request_frame(method, arg, codec, false, metadata)?;
+        req_buf.flags |= FLAG_STREAM;
+        let id = req_buf.id;
+        transport.open(id);
+        self.flush_cancels(&mut transport).await?;
+        transport.send(req_buf).await?;
+        let out = transport.sender();
+        let items = self.read_stream(id, window, Some(self.get_timeout()), codec, transport);
+        Ok(ResponseStream::new(id, items, &self.cancels, out))
+    }
+
+    /// The items of the stream with id `id`, granting more once half of `window` is read.
+    /// It fails when no frame comes in `timeout`.
+    fn read_stream<'a, C, Resp, T>(
+        &'a self,
+        id: u64,
+        window: u32,
+        timeout: Option<Duration>,
+        codec: &'a C,
+        transport: T,
+    ) -> BoxStream<'a, Result<Resp>>
+    where
+        C: Format,
+        Resp: Decode<C> + Send + 'a,
+        T: Transport + 'a,
+    {
+        let grant = (window as u64).div_ceil(2);
+        futures::stream::unfold(Some((transport, 0)), move |state| async move {
+            let (mut transport, mut read) = state?;
+            loop {
+                let rsp_frame = match timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, transport.recv())
+                        .await
+                        .unwrap_or_else(|_| {
+                            Some(Err(std::io::Error::new(
+                                std::io::ErrorKind::TimedOut,
+                                "rpc stream timeout!",
+                            )))
+                        }),
+                    None => transport.recv().await,
+                };
+                let rsp_frame = match rsp_frame {
+                    Some(Ok(v)) => v,
+                    Some(Err(e)) => return Some((Err(Error::from(e.to_string())), None)),
+                    None => return Some((Err(Error::from("connection closed!")), None)),
+                };
+                // discard the rsp that is is not belong to us
+                if rsp_frame.id != id {
+                    continue;
+                }
+                if !rsp_frame.has_flag(FLAG_STREAM) {
+                    // a unary method answers one item
+                    return Some((self.response(rsp_frame, codec), None));
+                }
+                if rsp_frame.has_flag(FLAG_CONTROL) {
+                    return match rsp_frame.ok {
+                        1 => None,
+                        _ => Some((self.response(rsp_frame, codec), None)),
                     };
                 }
+                read += 1;
+                if read >= grant {
+                    let r = transport.send(Control::Window(read).into_frame(id)).await;
+                    if let Err(e) = r {
+                        return Some((Err(Error::from(e.to_string())), None));
+                    }
+                    read = 0;
+                }
+                return Some((self.response(rsp_frame, codec), Some((transport, read))));
             }
         })
-        .await
+        .boxed()
+    }
+
+    /// Call a method reading a stream of requests(`Server::register_upload_fn`).
+    /// The requests are sent while the server grants them, the server answers one response.
+    /// An error of the server ends the upload early.
+    pub async fn call_upload<C, Arg, Resp, I, T>(
+        &self,
+        method: &str,
+        reqs: I,
+        codec: &C,
+        mut transport: T,
+    ) -> Result<Resp>
+    where
+        C: Format,
+        Arg: Encode<C>,
+        Resp: Decode<C>,
+        I: Stream<Item = Arg>,
+        T: Transport,
+    {
+        self.require_v2("uploads")?;
+        let mut metadata = Metadata::new();
+        metadata.insert(metadata::UPLOAD, Bytes::new());
+        let req_buf = self.request_frame_with(method, codec, false, metadata, |_| Ok(()))?;
+        let id = req_buf.id;
+        transport.open(id);
+        self.flush_cancels(&mut transport).await?;
+        transport.send(req_buf).await?;
+        let mut reqs = std::pin::pin!(reqs);
+        // the server grants the first window once the upload is open
+        let mut window = 0u64;
+        let mut sending = true;
+        loop {
+            let rsp_frame = if sending && window > 0 {
+                tokio::select! {
+                    biased;
+                    rsp_frame = transport.recv() => rsp_frame,
+                    req = reqs.next() => {
+                        let f = match req {
+                            Some(arg) => match self.item_frame(id, arg, codec) {
+                                Ok(f) => f,
+                                Err(e) => {
+                                    let end = stream::end_frame(id, Err(Error::from(e.to_string())));
+                                    transport.send(end).await?;
+                                    return Err(e);
+                                }
+                            },
+                            None => {
+                                sending = false;
+                                stream::end_frame(id, Ok(()))
+                            }
+                        };
+                        transport.send(f).await?;
+                        window = window.saturating_sub(1);
+                        continue;
+                    }
+                }
+            } else {
+                tokio::time::timeout(self.get_timeout(), transport.recv())
+                    .await
+                    .map_err(|_| Error::from("rpc call timeout!"))?
+            };
+            let rsp_frame = match rsp_frame {
+                Some(Ok(v)) => v,
+                Some(Err(e)) => return Err(Error::from(e.to_string())),
+                None => return Err(Error::from("connection closed!")),
+            };
+            // discard the rsp that is is not belong to us
+            if rsp_frame.id != id {
+                continue;
+            }
+            if let Some(Control::Window(n)) = Control::decode(&rsp_frame) {
+                window += n;
+                continue;
+            }
+            if sending {
+                // answered before the last request, the rest is not sent
+                let end = stream::end_frame(id, Err(Error::from("upload answered before its end")));
+                transport.send(end).await?;
+            }
+            debug!("get response id = {}", id);
+            return self.response(rsp_frame, codec);
+        }
+    }
+
+    /// Call a bidi streaming method(`Server::register_bidi_fn`) over a shared connection,
+    /// the requests go by the sender while the responses are read from the stream.
+    /// Either half closes on its own: closing the sender ends the requests,
+    /// the end of the stream is the end of the responses.
+    pub async fn call_bidi<'a, C, Arg, Resp>(
+        &'a self,
+        method: &str,
+        codec: &'a C,
+        mux: &'a Mux,
+    ) -> Result<(RequestSender<'a, C, Arg>, ResponseStream<'a, Resp>)>
+    where
+        C: Format,
+        Arg: Encode<C>,
+        Resp: Decode<C> + Send + 'a,
+    {
+        self.require_v2("bidi streams")?;
+        let window = self.window.max(1);
+        let mut metadata = Metadata::new();
+        metadata.insert(metadata::WINDOW, window.to_be_bytes().to_vec());
+        metadata.insert(metadata::UPLOAD, Bytes::new());
+        let mut req_buf = self.request_frame_with(method, codec, false, metadata, |_| Ok(()))?;
+        req_buf.flags |= FLAG_STREAM;
+        let id = req_buf.id;
+        let credits = Arc::new(Semaphore::new(0));
+        let mut transport = mux.call_with_credits(credits.clone());
+        transport.open(id);
+        self.flush_cancels(&mut transport).await?;
+        transport.send(req_buf).await?;
+        let sender = RequestSender::new(id, self, codec, mux, credits, transport.route());
+        // the responses may wait for the requests as long as the caller likes
+        let out = transport.sender();
+        let items = self.read_stream(id, window, None, codec, transport);
+        Ok((sender, ResponseStream::new(id, items, &self.cancels, out)))
+    }
+
+    /// A request of the stream with id `id`.
+    pub(crate) fn item_frame<C: Format, Arg: Encode<C>>(
+        &self,
+        id: u64,
+        arg: Arg,
+        codec: &C,
+    ) -> Result<Frame> {
+        let mut f = Frame::new();
+        f.id = id;
+        f.ok = 1;
+        f.flags = FLAG_STREAM;
+        f.version = FrameVersion::V2;
+        let mut data = Bytes::from(arg.encode(codec)?);
+        if let (Some(compression), Some(threshold)) = (self.compression, self.compress_threshold) {
+            if data.len() >= threshold {
+                data = Bytes::from(compression.compress(&data)?);
+                f.flags |= FLAG_COMPRESSED;
+            }
+        }
+        f.data = data;
+        Ok(f)
+    }
+
+    /// Fail unless the requests go in v2 frames, the only ones carrying `what`.
+    fn require_v2(&self, what: &str) -> Result<()> {
+        if self.version != FrameVersion::V2 {
+            return Err(Error::from(format!(
+                "{} need v2 frames, the server took no handshake!",
+                what
+            )));
+        }
+        Ok(())
+    }
+
+    /// Cancel the streams dropped before their end.
+    async fn flush_cancels<T: Transport>(&self, transport: &mut T) -> std::io::Result<()> {
+        let cancels = match self.cancels.lock() {
+            Ok(mut cancels) if !cancels.is_empty() => std::mem::take(&mut *cancels),
+            _ => return Ok(()),
+        };
+        for id in cancels {
+            debug!("cancel stream id = {}", id);
+            transport.send(Control::Cancel.into_frame(id)).await?;
+        }
+        Ok(())
     }
 
     pub fn get_timeout(&self) -> Duration {
         if let Some(t) = &self.timeout {
-            t.clone()
+            *t
         } else {
             Duration::from_secs(30)
         }
     }
 }
 
+/// The counters of a server, shared by its connections.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// the requests served, notifications included
+    pub calls: AtomicU64,
+    /// the requests that failed, a notification fails without answering
+    pub errors: AtomicU64,
+    /// the notifications served, see `ClientStub::notify`
+    pub notifies: AtomicU64,
+}
+
+impl Metrics {
+    fn call(&self) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Answers the unary calls in place of their handlers, by the call, its request
+/// and the `Format::id` of the codec it is encoded with.
+pub type Dispatcher =
+    Arc<dyn Fn(Context, Bytes, u8) -> BoxFuture<'static, Result<Bytes>> + Send + Sync>;
+
 /// Receives the message sent by the client, unpacks the message, and invokes the local method.
-pub struct ServerStub {}
+pub struct ServerStub {
+    /// the options the server supports, a client handshake picks from them
+    pub handshake: Handshake,
+    /// compress the response payloads that are at least this long
+    pub compress_threshold: Option<usize>,
+    /// reject the calls whose `metadata::FINGERPRINT` differs from the method's
+    pub fingerprint: bool,
+    /// the requests an upload sends ahead of the ones read
+    pub window: u32,
+    /// the longest frame a client may send, see `FrameCodec::max_frame_len`.
+    /// A compressed payload may not expand past it either
+    pub max_frame_len: usize,
+    pub metrics: Metrics,
+    /// run around the calls of every method, in the order added
+    pub middlewares: Vec<Box<dyn Middleware>>,
+    /// method name -> run around its calls, after `middlewares`
+    pub method_middlewares: HashMap<String, Vec<Box<dyn Middleware>>>,
+    /// answers the unary calls instead of the handlers, inside the middlewares
+    pub dispatcher: Option<Dispatcher>,
+    /// the state the handlers extract(`context::State`)
+    pub state: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl Default for ServerStub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ServerStub {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            handshake: Handshake::default().checksum(true),
+            compress_threshold: None,
+            fingerprint: false,
+            window: DEFAULT_WINDOW,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            metrics: Metrics::default(),
+            middlewares: vec![],
+            method_middlewares: HashMap::new(),
+            dispatcher: None,
+            state: None,
+        }
+    }
+
+    /// The future answering a unary call, by the dispatcher when one is set.
+    fn accept<'a, C: Format>(
+        &self,
+        ctx: Context,
+        stub: &'a dyn Stub<C>,
+        body: &'a Bytes,
+        codec: &'a C,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        match &self.dispatcher {
+            Some(dispatch) => {
+                let rsp = dispatch(ctx, body.clone(), codec.id());
+                Box::pin(async move { rsp.await.map(Vec::from) })
+            }
+            None => stub.accept_with(&ctx, body, codec),
+        }
+    }
+
+    /// Run `handler` behind the middlewares of `req.method`.
+    async fn intercept<'a>(
+        &'a self,
+        req: Request<'a>,
+        handler: BoxFuture<'a, Result<Vec<u8>>>,
+    ) -> Result<Vec<u8>> {
+        if self.middlewares.is_empty() && self.method_middlewares.is_empty() {
+            return handler.await;
+        }
+        let method = self
+            .method_middlewares
+            .get(req.method)
+            .map(|v| v.as_slice())
+            .unwrap_or_default();
+        Next::new(req, &self.middlewares, method, handler)
+            .run()
+            .await
+    }
+
+    /// Pass the middlewares before a streaming call opens.
+    async fn intercept_stream<C: Format>(&self, call: &Resolved<'_, C>) -> Result<()> {
+        self.intercept(call.request(), Box::pin(async { Ok(Vec::new()) }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Compare the fingerprint the caller sent with the one of the method,
+    /// either missing passes.
+    pub fn check_fingerprint(
+        method: &str,
+        expected: Option<u64>,
+        metadata: &Metadata,
+    ) -> Result<()> {
+        let got = match metadata.get(metadata::FINGERPRINT) {
+            Some(got) => <[u8; 8]>::try_from(&got[..])
+                .map(u64::from_be_bytes)
+                .map_err(|_| Error::from("fingerprint is not 8 bytes!"))?,
+            None => return Ok(()),
+        };
+        Self::compare_fingerprint(method, expected, Some(got))
+    }
+
+    /// Same as `check_fingerprint`, with the fingerprint the caller sent.
+    fn compare_fingerprint(method: &str, expected: Option<u64>, got: Option<u64>) -> Result<()> {
+        let (expected, got) = match (expected, got) {
+            (Some(expected), Some(got)) => (expected, got),
+            _ => return Ok(()),
+        };
+        if got != expected {
+            return Err(Error::from(format!(
+                "incompatible schema: method '{}' fingerprint={:016x}, caller fingerprint={:016x}",
+                method, expected, got
+            )));
+        }
+        Ok(())
+    }
+
+    /// Find the method of a request and its codec, decompress its payload.
+    fn resolve<'a, C: Format>(
+        &self,
+        stubs: &'a SyncHashMap<String, Arc<dyn Stub<C>>>,
+        ids: &'a SyncHashMap<u64, (String, Arc<dyn Stub<C>>)>,
+        codec: &C,
+        req: Frame,
+        compression: Option<Compression>,
+        peer_addr: Option<SocketAddr>,
+    ) -> Result<Resolved<'a, C>> {
+        let compressed = req.has_flag(FLAG_COMPRESSED);
+        let (payload, metadata, codec) = self.request_parts(codec, &req, compression)?;
+        let (method, stub, body) = match req.method_id {
+            Some(method_id) => match ids.get(&method_id) {
+                Some((method, stub)) => (method.as_str(), stub, payload),
+                None => {
+                    return Err(Error::from(format!("method id={} not find!", method_id)));
+                }
+            },
+            None => {
+                let end = payload
+                    .iter()
+                    .position(|x| *x == b'\n')
+                    .ok_or_else(|| Error::from("not find '\n' end of method!"))?;
+                let method = std::str::from_utf8(&payload[..end])
+                    .map_err(|_| Error::from("method is not utf8!"))?;
+                match stubs.dirty_ref().get_key_value(method) {
+                    Some((method, stub)) => (method.as_str(), stub, payload.slice(end + 1..)),
+                    None => return Err(Error::from(format!("method='{}' not find!", method))),
+                }
+            }
+        };
+        if self.fingerprint {
+            Self::check_fingerprint(method, stub.fingerprint(), &metadata)?;
+        }
+        Ok(Resolved {
+            id: req.id,
+            peer_addr,
+            method,
+            stub: stub.as_ref(),
+            codec,
+            body,
+            compressed,
+            stream: req.flags & FLAG_STREAM != 0,
+            upload: metadata.get(metadata::UPLOAD).is_some(),
+            metadata,
+        })
     }
 
-    pub async fn call_frame<C: Codec>(
+    /// The payload of a request decompressed, its metadata and the codec it is encoded with.
+    /// The payload expands to `max_frame_len` at most.
+    fn request_parts<C: Format>(
         &self,
-        stubs: &SyncHashMap<String, Box<dyn Stub<C>>>,
         codec: &C,
+        req: &Frame,
+        compression: Option<Compression>,
+    ) -> Result<(Bytes, Metadata, C)> {
+        let (payload, metadata) = self.request_payload(req, compression)?;
+        let codec = Self::codec_of(codec, &metadata)?;
+        Ok((payload, metadata, codec))
+    }
+
+    /// Same as `request_parts`, without the codec.
+    fn request_payload(
+        &self,
+        req: &Frame,
+        compression: Option<Compression>,
+    ) -> Result<(Bytes, Metadata)> {
+        let payload = if req.has_flag(FLAG_COMPRESSED) {
+            let data = compression
+                .ok_or_else(|| Error::from("req is compressed, but no compression negotiated!"))
+                .and_then(|c| c.decompress(req.get_payload(), self.max_frame_len))?;
+            Bytes::from(data)
+        } else {
+            req.data.clone()
+        };
+        let metadata = Metadata::decode(&req.metadata)?;
+        Ok((payload, metadata))
+    }
+
+    /// The codec of a call, it may be another than the connection's.
+    pub(crate) fn codec_of<C: Format>(codec: &C, metadata: &Metadata) -> Result<C> {
+        match metadata.get(metadata::CODEC).and_then(|v| v.first()) {
+            Some(id) => codec
+                .select(*id)
+                .ok_or_else(|| Error::from(format!("codec id={} not support!", id))),
+            None => Ok(codec.clone()),
+        }
+    }
+
+    /// Answer a batch request(`batch::METHOD`), each call is counted and checked
+    /// on its own, a batch that does not decode counts as one failed call.
+    async fn batch<C: Format>(
+        &self,
+        stubs: &SyncHashMap<String, Arc<dyn Stub<C>>>,
+        codec: C,
         req: Frame,
+        compression: Option<Compression>,
+        peer_addr: Option<SocketAddr>,
     ) -> Frame {
         let mut rsp = Frame::new();
-        let payload = req.get_payload();
-        let method = {
-            let mut find_end = false;
-            let mut method = String::with_capacity(20);
-            for x in payload {
-                if x.eq(&('\n' as u8)) {
-                    find_end = true;
-                    break;
-                }
-                method.push(*x as char);
-            }
-            if !find_end {
-                let _ = rsp
-                    .write_all("not find '\n' end of method!".as_bytes())
-                    .await;
-                rsp.ok = 0;
-                return rsp;
-            }
-            method
-        };
-        let stub = stubs.get(&method);
-        if stub.is_none() {
-            let _ = rsp
-                .write_all(format!("method='{}' not find!", method).as_bytes())
+        rsp.id = req.id;
+        rsp.version = req.version;
+        match self
+            .run_batch(stubs, &codec, &req, compression, peer_addr)
+            .await
+        {
+            Ok(data) => {
+                self.compress(&mut rsp, data, req.has_flag(FLAG_COMPRESSED), compression);
+                rsp.ok = 1;
+            }
+            Err(e) => {
+                self.metrics.call();
+                self.metrics.error();
+                rsp.data = Bytes::from(e.to_string());
+            }
+        }
+        rsp
+    }
+
+    async fn run_batch<C: Format>(
+        &self,
+        stubs: &SyncHashMap<String, Arc<dyn Stub<C>>>,
+        codec: &C,
+        req: &Frame,
+        compression: Option<Compression>,
+        peer_addr: Option<SocketAddr>,
+    ) -> Result<Vec<u8>> {
+        let (mut payload, metadata) = self.request_payload(req, compression)?;
+        if req.method_id.is_none() {
+            // the method name sent before the batch
+            payload = payload.slice(batch::METHOD.len() + 1..);
+        }
+        let batch = BatchRequest::decode(&payload)?;
+        // from here on a failure of the whole batch fails each of its calls
+        let codec = Self::codec_of(codec, &metadata);
+        let fingerprints = batch::fingerprints(&metadata, batch.items.len());
+        let id = req.id;
+        let run = |(index, (method, arg)): (usize, (String, Bytes))| {
+            let codec = codec.as_ref();
+            let metadata = &metadata;
+            let fingerprint = fingerprints.as_ref().map(|v| v[index]);
+            async move {
+                self.metrics.call();
+                let r = async {
+                    let codec = codec.map_err(|e| Error::from(e.to_string()))?;
+                    let got = fingerprint.map_err(|e| Error::from(e.to_string()))?;
+                    let stub = stubs
+                        .get(&method)
+                        .ok_or_else(|| Error::from(format!("method='{}' not find!", method)))?;
+                    if self.fingerprint {
+                        Self::compare_fingerprint(&method, stub.fingerprint(), got)?;
+                    }
+                    let req = Request {
+                        method: &method,
+                        metadata,
+                        body: &arg,
+                    };
+                    let ctx =
+                        Context::new(id, &method, metadata.clone(), peer_addr, self.state.clone());
+                    let handler = self.accept(ctx, stub.as_ref(), &arg, codec);
+                    self.intercept(req, handler).await
+                }
+                .await;
+                if r.is_err() {
+                    self.metrics.error();
+                }
+                r
+            }
+        };
+        let results = if batch.concurrent {
+            futures::future::join_all(batch.items.into_iter().enumerate().map(run)).await
+        } else {
+            let mut results = Vec::with_capacity(batch.items.len());
+            for item in batch.items.into_iter().enumerate() {
+                results.push(run(item).await);
+            }
+            results
+        };
+        Ok(batch::encode_response(results))
+    }
+
+    /// Compress a response payload when the request was, or when it is over the threshold.
+    fn compress(
+        &self,
+        rsp: &mut Frame,
+        data: Vec<u8>,
+        compressed: bool,
+        compression: Option<Compression>,
+    ) {
+        let mut r = Bytes::from(data);
+        if let Some(compression) = compression {
+            let over_threshold = self
+                .compress_threshold
+                .map(|threshold| r.len() >= threshold)
+                .unwrap_or(false);
+            if compressed || over_threshold {
+                match compression.compress(&r) {
+                    Ok(data) => {
+                        r = Bytes::from(data);
+                        rsp.flags |= FLAG_COMPRESSED;
+                    }
+                    Err(e) => {
+                        error!("compress rsp fail: {}", e);
+                    }
+                }
+            }
+        }
+        rsp.data = r;
+    }
+
+    /// `compression` is what the connection negotiated, compressed requests are
+    /// answered compressed.
+    pub async fn call_frame<C: Format>(
+        &self,
+        stubs: &SyncHashMap<String, Arc<dyn Stub<C>>>,
+        ids: &SyncHashMap<u64, (String, Arc<dyn Stub<C>>)>,
+        codec: &C,
+        req: Frame,
+        compression: Option<Compression>,
+    ) -> Frame {
+        if batch::is_batch(&req) {
+            return self
+                .batch(stubs, codec.clone(), req, compression, None)
                 .await;
-            rsp.ok = 0;
-            return rsp;
         }
-        let stub = stub.unwrap();
-        let body = &payload[(method.len() + 1)..];
-        let r = stub.accept(body, codec).await;
+        let mut rsp = Frame::new();
+        rsp.id = req.id;
+        // answer with the version the client speaks
+        rsp.version = req.version;
+        let r = match self.resolve(stubs, ids, codec, req, compression, None) {
+            Ok(call) => self.unary(call, &mut rsp, compression).await,
+            Err(e) => Err(e),
+        };
+        self.metrics.call();
         if let Err(e) = r {
+            self.metrics.error();
             let _ = rsp.write_all(e.to_string().as_bytes()).await;
             rsp.ok = 0;
-            return rsp;
         }
-        let r = r.unwrap();
-        let _ = rsp.write_all(&r).await;
-        rsp.ok = 1;
         rsp
     }
 
-    pub async fn call<S, C: Codec>(
+    async fn unary<C: Format>(
         &self,
-        stubs: &SyncHashMap<String, Box<dyn Stub<C>>>,
+        call: Resolved<'_, C>,
+        rsp: &mut Frame,
+        compression: Option<Compression>,
+    ) -> Result<()> {
+        let r = self.intercept(call.request(), call.accept(self)).await?;
+        self.compress(rsp, r, call.compressed, compression);
+        rsp.ok = 1;
+        Ok(())
+    }
+
+    /// Answer one request of a connection, the response frames go to `conn.out`.
+    /// `reqs` are the requests of an upload.
+    async fn serve<C: Format>(
+        &self,
+        conn: &Conn,
+        call: Result<Resolved<'_, C>>,
+        id: u64,
+        version: FrameVersion,
+        reqs: Option<Requests>,
+        compression: Option<Compression>,
+    ) {
+        self.metrics.call();
+        let mut rsp = Frame::new();
+        rsp.id = id;
+        // answer with the version the client speaks
+        rsp.version = version;
+        let r = match call {
+            Ok(call) => match reqs {
+                Some(reqs) if call.stream => {
+                    match self.bidi(conn, call, id, reqs, compression).await {
+                        Ok(_) => return,
+                        Err(e) => Err(e),
+                    }
+                }
+                Some(reqs) => self.upload(conn, call, &mut rsp, reqs, compression).await,
+                None => {
+                    if call.stream {
+                        if let Some(items) = call.stub.accept_stream(&call.body, &call.codec) {
+                            if let Err(e) = self.intercept_stream(&call).await {
+                                return self.reply_error(conn, rsp, e).await;
+                            }
+                            self.stream(
+                                conn,
+                                rsp.id,
+                                items,
+                                Self::window_of(&call.metadata),
+                                call.compressed,
+                                compression,
+                            )
+                            .await;
+                            return;
+                        }
+                    }
+                    // a unary method answers a stream call with one frame
+                    self.unary(call, &mut rsp, compression).await
+                }
+            },
+            Err(e) => Err(e),
+        };
+        match r {
+            Ok(_) => {
+                let _ = conn.out.send(rsp).await;
+            }
+            Err(e) => self.reply_error(conn, rsp, e).await,
+        }
+    }
+
+    async fn reply_error(&self, conn: &Conn, mut rsp: Frame, e: Error) {
+        self.metrics.error();
+        let _ = rsp.write_all(e.to_string().as_bytes()).await;
+        rsp.ok = 0;
+        let _ = conn.out.send(rsp).await;
+    }
+
+    /// Serve a notification, nothing is written back.
+    async fn notify<C: Format>(&self, call: Result<Resolved<'_, C>>, id: u64) {
+        self.metrics.call();
+        self.metrics.notifies.fetch_add(1, Ordering::Relaxed);
+        let r = match call {
+            Ok(call) => self
+                .intercept(call.request(), call.accept(self))
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = r {
+            self.metrics.error();
+            error!("notify id={} fail: {}", id, e);
+        }
+    }
+
+    /// The items a stream sends ahead, the caller's `metadata::WINDOW` or `DEFAULT_WINDOW`.
+    fn window_of(metadata: &Metadata) -> u64 {
+        metadata
+            .get(metadata::WINDOW)
+            .and_then(|v| <[u8; 4]>::try_from(&v[..]).ok())
+            .map(u32::from_be_bytes)
+            .unwrap_or(DEFAULT_WINDOW) as u64
+    }
+
+    /// The requests the caller sends for the call `id`, granting more of them
+    /// once half of the window is read.
+    fn requests(
+        &self,
+        conn: &Conn,
+        id: u64,
+        reqs: Requests,
+        compression: Option<Compression>,
+    ) -> BoxStream<'static, Result<Bytes>> {
+        let grant = (self.window.max(1) as u64).div_ceil(2);
+        let max = self.max_frame_len;
+        futures::stream::unfold(
+            (reqs, conn.out.clone(), 0),
+            move |(mut reqs, out, mut read)| async move {
+                let req = match reqs.rx.recv().await {
+                    Some(req) if !req.has_flag(FLAG_COMPRESSED) => Ok(req.data),
+                    Some(req) => compression
+                        .ok_or_else(|| {
+                            Error::from("req is compressed, but no compression negotiated!")
+                        })
+                        .and_then(|c| c.decompress(req.get_payload(), max))
+                        .map(Bytes::from),
+                    // the requests sent before a failure are read first
+                    None => Err(reqs.error.lock().ok()?.take()?),
+                };
+                read += 1;
+                if read >= grant {
+                    let _ = out.send(Control::Window(read).into_frame(id)).await;
+                    read = 0;
+                }
+                Some((req, (reqs, out, read)))
+            },
+        )
+        .boxed()
+    }
+
+    /// Read the requests of an upload while granting the caller a window of them,
+    /// then answer the response of the method.
+    async fn upload<C: Format>(
+        &self,
+        conn: &Conn,
+        call: Resolved<'_, C>,
+        rsp: &mut Frame,
+        reqs: Requests,
+        compression: Option<Compression>,
+    ) -> Result<()> {
+        self.intercept_stream(&call).await?;
+        let id = rsp.id;
+        let items = self.requests(conn, id, reqs, compression);
+        let f = call
+            .stub
+            .accept_upload(items, &call.codec)
+            .ok_or_else(|| Error::from("method does not read a stream of requests!"))?;
+        let window = Control::Window(self.window.max(1) as u64);
+        let _ = conn.out.send(window.into_frame(id)).await;
+        let r = f.await?;
+        self.compress(rsp, r, call.compressed, compression);
+        rsp.ok = 1;
+        Ok(())
+    }
+
+    /// Run a bidi method: its requests are read as for an upload, its responses are
+    /// sent as for a stream. The responses end when the method drops their sender,
+    /// or with the error of the method.
+    async fn bidi<C: Format>(
+        &self,
+        conn: &Conn,
+        call: Resolved<'_, C>,
+        id: u64,
+        reqs: Requests,
+        compression: Option<Compression>,
+    ) -> Result<()> {
+        self.intercept_stream(&call).await?;
+        let items = self.requests(conn, id, reqs, compression);
+        let (tx, mut rsps) = mpsc::channel(1);
+        let f = call
+            .stub
+            .accept_bidi(items, tx, &call.codec)
+            .ok_or_else(|| Error::from("method is not bidi streaming!"))?;
+        let window = Control::Window(self.window.max(1) as u64);
+        let _ = conn.out.send(window.into_frame(id)).await;
+        let failed = std::sync::Mutex::new(None);
+        let handler = async {
+            if let Err(e) = f.await {
+                *failed.lock().unwrap() = Some(e);
+            }
+        };
+        // the sender is dropped by the end of the method, its error comes last
+        let items = futures::stream::poll_fn(|cx| rsps.poll_recv(cx))
+            .map(Ok)
+            .chain(
+                futures::stream::once(async { failed.lock().unwrap().take() })
+                    .filter_map(|e| async move { e.map(Err) }),
+            );
+        let responses = self.stream(
+            conn,
+            id,
+            items.boxed(),
+            Self::window_of(&call.metadata),
+            call.compressed,
+            compression,
+        );
+        futures::join!(handler, responses);
+        if let Some(e) = failed.lock().unwrap().take() {
+            error!("bidi id={} failed after its responses ended: {}", id, e);
+        }
+        Ok(())
+    }
+
+    /// Send the items while the caller grants them, until the end or a cancel.
+    async fn stream(
+        &self,
+        conn: &Conn,
+        id: u64,
+        mut items: BoxStream<'_, Result<Vec<u8>>>,
+        mut window: u64,
+        compressed: bool,
+        compression: Option<Compression>,
+    ) {
+        let (tx, mut controls) = mpsc::unbounded_channel();
+        if let Ok(mut v) = conn.controls.lock() {
+            // once the client is gone, no control ever comes
+            if !conn.closed.load(Ordering::Acquire) {
+                v.insert(id, tx);
+            }
+        }
+        let end = loop {
+            if window == 0 {
+                match controls.recv().await {
+                    Some(Control::Window(n)) => window += n,
+                    _ => break None,
+                }
+                continue;
+            }
+            tokio::select! {
+                biased;
+                control = controls.recv() => match control {
+                    Some(Control::Window(n)) => window += n,
+                    _ => break None,
+                },
+                item = items.next() => match item {
+                    Some(Ok(data)) => {
+                        let mut f = Frame::new();
+                        f.id = id;
+                        f.ok = 1;
+                        f.flags = FLAG_STREAM;
+                        f.version = FrameVersion::V2;
+                        self.compress(&mut f, data, compressed, compression);
+                        if conn.out.send(f).await.is_err() {
+                            break None;
+                        }
+                        window -= 1;
+                    }
+                    Some(Err(e)) => {
+                        self.metrics.error();
+                        break Some(stream::end_frame(id, Err(e)));
+                    }
+                    None => break Some(stream::end_frame(id, Ok(()))),
+                },
+            }
+        };
+        if let Ok(mut v) = conn.controls.lock() {
+            v.remove(&id);
+        }
+        match end {
+            Some(end) => {
+                let _ = conn.out.send(end).await;
+            }
+            None => debug!("stream id={} cancelled", id),
+        }
+    }
+
+    /// Serve a connection, its calls are answered concurrently.
+    pub async fn call<S, C: Format>(
+        &self,
+        stubs: &SyncHashMap<String, Arc<dyn Stub<C>>>,
+        ids: &SyncHashMap<u64, (String, Arc<dyn Stub<C>>)>,
         codec: &C,
-        mut stream: S,
+        stream: S,
     ) where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        // the read half of the stream
+        self.call_with_peer(stubs, ids, codec, stream, None, |_| {})
+            .await
+    }
+
+    /// Same as `call`, `on_connect` takes the `Peer` calling the client back
+    /// over the connection. `peer_addr` is the address of the client, see `Context`.
+    pub async fn call_with_peer<S, C: Format, F>(
+        &self,
+        stubs: &SyncHashMap<String, Arc<dyn Stub<C>>>,
+        ids: &SyncHashMap<u64, (String, Arc<dyn Stub<C>>)>,
+        codec: &C,
+        stream: S,
+        peer_addr: Option<SocketAddr>,
+        on_connect: F,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin,
+        F: FnOnce(Peer<C>),
+    {
+        let frames = FrameCodec::default().set_max_frame_len(self.max_frame_len);
+        let mut stream = Framed::new(stream, frames);
+        // the compression and codec negotiated by the client handshake
+        let mut compression = None;
+        let mut conn_codec = codec.clone();
+        let (out, mut rsps) = mpsc::channel(WRITE_BUFFER);
+        // the calls back to the client in flight by id
+        let callbacks = Arc::new(Routes::default());
+        on_connect(Peer::new(codec.clone(), out.clone(), callbacks.clone()));
+        let conn = Conn {
+            controls: std::sync::Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+            out,
+        };
+        let mut calls = FuturesUnordered::new();
+        // the uploads in flight by id, only the read loop sends their requests
+        let mut uploads: HashMap<u64, Upload> = HashMap::new();
+        let mut reading = true;
+        // the handshake is taken before the first call only, it would change the
+        // compression and codec under the calls in flight
+        let mut handshake = true;
         loop {
-            let req = match Frame::decode_from(&mut stream).await {
-                Ok(r) => r,
-                Err(ref e) => {
-                    if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                        debug!("tcp server decode req: connection closed");
+            if !reading && calls.is_empty() {
+                // the client closed its write half, flush the last responses
+                while let Ok(rsp) = rsps.try_recv() {
+                    let checksum = stream.codec().checksum;
+                    let _ = rsp.write_to(stream.get_mut(), checksum).await;
+                }
+                break;
+            }
+            tokio::select! {
+                biased;
+                Some(rsp) = rsps.recv() => {
+                    debug!("rsp: id={}", rsp.id);
+                    // send the result back to client
+                    let checksum = stream.codec().checksum;
+                    let _ = rsp.write_to(stream.get_mut(), checksum).await;
+                }
+                Some(_) = calls.next(), if !calls.is_empty() => {}
+                req = stream.next(), if reading => {
+                    let req = match req {
+                        Some(Ok(r)) => r,
+                        Some(Err(e)) => {
+                            error!("tcp server decode req: err = {:?}", e);
+                            break;
+                        }
+                        None => {
+                            debug!("tcp server decode req: connection closed");
+                            // the streams waiting for a window end
+                            if let Ok(mut v) = conn.controls.lock() {
+                                conn.closed.store(true, Ordering::Release);
+                                v.clear();
+                            }
+                            for (_, mut upload) in uploads.drain() {
+                                upload.fail(Error::from("connection closed!"));
+                            }
+                            reading = false;
+                            continue;
+                        }
+                    };
+                    let id = req.id;
+                    debug!("req: id={:?}", id);
+                    if Handshake::is_handshake(&req) && !handshake {
+                        error!("handshake id={} after the first call", id);
+                        let rsp = Frame {
+                            id,
+                            ok: 0,
+                            data: Bytes::from("handshake after the first call!"),
+                            ..Frame::new()
+                        };
+                        let checksum = stream.codec().checksum;
+                        let _ = rsp.write_to(stream.get_mut(), checksum).await;
+                        continue;
+                    }
+                    handshake = false;
+                    if Handshake::is_handshake(&req) {
+                        let (mut agreed, codec_id) = match Handshake::from_request(&req) {
+                            Ok(handshake) => (self.handshake.accept(&handshake), handshake.codec),
+                            Err(e) => {
+                                error!("tcp server decode handshake: err = {}", e);
+                                break;
+                            }
+                        };
+                        if let Some(c) = codec_id.and_then(|id| codec.select(id)) {
+                            agreed.codec = codec_id;
+                            conn_codec = c;
+                        }
+                        debug!("handshake: {:?}", agreed);
+                        compression = agreed.compression();
+                        // the handshake answer itself goes without checksum
+                        let checksum = agreed.checksum;
+                        let rsp = agreed.into_answer(id);
+                        let _ = rsp.write_to(stream.get_mut(), false).await;
+                        stream.codec_mut().checksum = checksum;
+                        continue;
+                    }
+                    let req = if is_callback(id) {
+                        match callbacks.route(req) {
+                            // the answer of a call back
+                            Ok(_) => continue,
+                            Err(req) => req,
+                        }
                     } else {
-                        error!("tcp server decode req: err = {:?}", e);
+                        req
+                    };
+                    if let Some(control) = Control::decode(&req) {
+                        let tx = conn.controls.lock().ok().and_then(|v| v.get(&id).cloned());
+                        if let Some(tx) = tx {
+                            let _ = tx.send(control);
+                        }
+                        continue;
+                    }
+                    if req.has_flag(FLAG_STREAM) {
+                        if let Some(upload) = uploads.get_mut(&id) {
+                            if req.has_flag(FLAG_CONTROL) {
+                                // the end of the upload, an error when the caller aborts it
+                                if req.ok != 1 {
+                                    let e = String::from_utf8_lossy(req.get_payload()).to_string();
+                                    upload.fail(Error::from(e));
+                                }
+                                uploads.remove(&id);
+                            } else if let Some(Err(TrySendError::Full(_))) =
+                                upload.tx.as_ref().map(|tx| tx.try_send(req))
+                            {
+                                error!("upload id={} sent over its window", id);
+                                // kept until its end, the requests left are discarded
+                                upload.fail(Error::from("upload window exceeded!"));
+                            }
+                            continue;
+                        }
+                        if req.has_flag(FLAG_CONTROL) {
+                            // the end of an upload already answered
+                            continue;
+                        }
+                    }
+                    let version = req.version;
+                    let oneway = req.has_flag(FLAG_ONEWAY);
+                    if batch::is_batch(&req) {
+                        let rsp = self.batch(stubs, conn_codec.clone(), req, compression, peer_addr);
+                        let conn = &conn;
+                        calls.push(Either::Right(Either::Right(async move {
+                            let rsp = rsp.await;
+                            if !oneway {
+                                let _ = conn.out.send(rsp).await;
+                            }
+                        })));
+                        continue;
+                    }
+                    let call = self.resolve(stubs, ids, &conn_codec, req, compression, peer_addr);
+                    if oneway {
+                        calls.push(Either::Right(Either::Left(self.notify(call, id))));
+                        continue;
                     }
-                    break;
+                    let mut reqs = None;
+                    if matches!(&call, Ok(call) if call.upload) {
+                        let (tx, rx) = mpsc::channel(self.window.max(1) as usize);
+                        let error = Arc::new(std::sync::Mutex::new(None));
+                        uploads.insert(
+                            id,
+                            Upload {
+                                tx: Some(tx),
+                                error: error.clone(),
+                            },
+                        );
+                        reqs = Some(Requests { rx, error });
+                    }
+                    calls.push(Either::Left(
+                        self.serve(&conn, call, id, version, reqs, compression),
+                    ));
                 }
-            };
-            let id = req.id;
-            debug!("req: id={:?}", id);
-            let rsp = self.call_frame(stubs, codec, req).await;
-            // let ret = server.service(req.decode_req(), &mut rsp);
-            let data = rsp.finish(id);
-            debug!("rsp: id={}", id);
-            // send the result back to client
-            let _ = stream.write(&data).await;
+            }
+        }
+        callbacks.clear();
+    }
+}
+
+/// A request whose method is found.
+struct Resolved<'a, C: Format> {
+    id: u64,
+    /// the address of the caller, when the server knows it
+    peer_addr: Option<SocketAddr>,
+    method: &'a str,
+    stub: &'a dyn Stub<C>,
+    codec: C,
+    body: Bytes,
+    compressed: bool,
+    /// the caller reads a stream
+    stream: bool,
+    /// the caller sends a stream of requests
+    upload: bool,
+    metadata: Metadata,
+}
+
+impl<C: Format> Resolved<'_, C> {
+    fn accept<'a>(&'a self, server: &ServerStub) -> BoxFuture<'a, Result<Vec<u8>>> {
+        server.accept(self.context(server), self.stub, &self.body, &self.codec)
+    }
+
+    fn context(&self, server: &ServerStub) -> Context {
+        Context::new(
+            self.id,
+            self.method,
+            self.metadata.clone(),
+            self.peer_addr,
+            server.state.clone(),
+        )
+    }
+
+    fn request(&self) -> Request<'_> {
+        Request {
+            method: self.method,
+            metadata: &self.metadata,
+            body: &self.body,
+        }
+    }
+}
+
+/// An upload in flight, its requests are sent by the read loop.
+struct Upload {
+    /// `None` once the upload failed
+    tx: Option<mpsc::Sender<Frame>>,
+    error: Arc<std::sync::Mutex<Option<Error>>>,
+}
+
+impl Upload {
+    /// End the requests with `e`, it cannot be lost to a full window.
+    fn fail(&mut self, e: Error) {
+        if let Ok(mut v) = self.error.lock() {
+            v.get_or_insert(e);
         }
+        self.tx = None;
     }
 }
+
+/// The requests of an upload as its method reads them, see `Upload`.
+struct Requests {
+    rx: mpsc::Receiver<Frame>,
+    /// read once the requests end, the upload failed when it is set
+    error: Arc<std::sync::Mutex<Option<Error>>>,
+}
+
+/// The state of a connection its calls share.
+struct Conn {
+    /// the streams in flight by id
+    controls: std::sync::Mutex<HashMap<u64, mpsc::UnboundedSender<Control>>>,
+    /// the client closed its write half, set under the lock of `controls`
+    closed: AtomicBool,
+    out: mpsc::Sender<Frame>,
+}
@@ -0,0 +1,148 @@
+use bytes::{Bytes, BytesMut};
+use dark_std::errors::{Error, Result};
+use dark_std::sync::SyncHashMap;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use std::task::{self, Poll};
+use tower_service::Service;
+
+use crate::client::Client;
+use crate::codec::{Encode, Format};
+use crate::context::Context;
+use crate::metadata::Metadata;
+use crate::server::{Server, Stub};
+use crate::stub::ServerStub;
+
+/// The error of a tower service, see `Server::set_layer`.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A unary call as the tower services see it, encoded by the codec of the client or the server.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub metadata: Metadata,
+    pub body: Bytes,
+    /// the `Format::id` of the codec `body` is encoded with, on a server the one the caller
+    /// negotiated or named. `None` is the codec of the client, or of the server unless
+    /// `metadata` names one
+    pub codec: Option<u8>,
+    /// the call on a server, its id, peer address and state. `None` on a client
+    pub context: Option<Context>,
+}
+
+impl Request {
+    pub fn new(method: &str, body: Bytes) -> Self {
+        Self {
+            method: method.to_string(),
+            metadata: Metadata::new(),
+            body,
+            codec: None,
+            context: None,
+        }
+    }
+
+    /// A request of `arg` encoded by `codec`.
+    pub fn encode<C: Format, Arg: Encode<C>>(method: &str, arg: Arg, codec: &C) -> Result<Self> {
+        let mut body = BytesMut::new();
+        arg.encode_into(codec, &mut body)?;
+        let mut req = Self::new(method, body.freeze());
+        req.codec = Some(codec.id());
+        Ok(req)
+    }
+}
+
+/// The calls of a shared client, the response is decoded by `Decode::decode(&client.codec, ..)`:
+/// ```rust
+/// use drpc::client::Client;
+/// use drpc::codec::{BinCodec, Decode};
+/// use drpc::tower::Request;
+/// use std::sync::Arc;
+/// use tower::ServiceExt;
+///
+/// async fn test() -> drpc::Result<i32> {
+///     let c = Arc::new(Client::<BinCodec>::dial("127.0.0.1:10000").await?);
+///     let req = Request::encode("add", 1, &c.codec)?;
+///     let rsp = c.clone().oneshot(req).await?;
+///     i32::decode(&c.codec, &rsp)
+/// }
+/// ```
+impl<C: Format + 'static> Service<Request> for Arc<Client<C>> {
+    type Response = Bytes;
+    type Error = Error;
+    type Future = BoxFuture<'static, Result<Bytes>>;
+
+    fn poll_ready(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<()>> {
+        match self.stream {
+            Some(_) => Poll::Ready(Ok(())),
+            None => Poll::Ready(Err(Error::from("stream is shutdown!"))),
+        }
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let client = self.clone();
+        Box::pin(async move {
+            client
+                .call_encoded(&req.method, req.body, req.metadata)
+                .await
+        })
+    }
+}
+
+/// The service calling the methods registered on a server, the innermost one of `Server::set_layer`.
+pub struct Dispatch<C: Format> {
+    handles: Arc<SyncHashMap<String, Arc<dyn Stub<C>>>>,
+    codec: C,
+}
+
+impl<C: Format> Dispatch<C> {
+    pub fn new(server: &Server<C>) -> Self {
+        Self {
+            handles: server.handles.clone(),
+            codec: server.codec.clone(),
+        }
+    }
+}
+
+impl<C: Format> Clone for Dispatch<C> {
+    fn clone(&self) -> Self {
+        Self {
+            handles: self.handles.clone(),
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+impl<C: Format + 'static> Service<Request> for Dispatch<C> {
+    type Response = Bytes;
+    type Error = Error;
+    type Future = BoxFuture<'static, Result<Bytes>>;
+
+    fn poll_ready(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let handles = self.handles.clone();
+        let codec = match req.codec {
+            Some(id) => self
+                .codec
+                .select(id)
+                .ok_or_else(|| Error::from(format!("codec id={} not support!", id))),
+            None => ServerStub::codec_of(&self.codec, &req.metadata),
+        };
+        // the layers may have changed the method or the metadata
+        let mut ctx = req.context.unwrap_or_default();
+        ctx.method = req.method;
+        ctx.metadata = req.metadata;
+        Box::pin(async move {
+            let codec = codec?;
+            match handles.get(&ctx.method) {
+                Some(stub) => stub
+                    .accept_with(&ctx, &req.body, &codec)
+                    .await
+                    .map(Bytes::from),
+                None => Err(Error::from(format!("method='{}' not find!", ctx.method))),
+            }
+        })
+    }
+}
@@ -0,0 +1,145 @@
+#[cfg(test)]
+mod test {
+    use drpc::batch::BatchRequest;
+    use drpc::client::Client;
+    use drpc::codec::BinCodec;
+    use drpc::server::Server;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+
+    fn server() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s.register_fn("hello", |name: String| async move {
+            Ok(format!("hello {}", name))
+        })
+        .unwrap();
+        s.register_fn("fail", |_: i32| async move {
+            Err::<i32, _>(drpc::Error::from("boom"))
+        })
+        .unwrap();
+        s.register_fn("slow", |arg: i32| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(arg)
+        })
+        .unwrap();
+        s
+    }
+
+    async fn serve(s: Arc<Server<BinCodec>>) -> Client<BinCodec> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                stream.set_nodelay(true).unwrap();
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        Client::<BinCodec>::dial(&addr).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let s = Arc::new(server());
+        let c = serve(s.clone()).await;
+        let mut batch = c.batch();
+        let add = batch.push("add", 1).unwrap();
+        let hello = batch.push("hello", "drpc".to_string()).unwrap();
+        let fail = batch.push("fail", 1).unwrap();
+        let missing = batch.push("missing", 1).unwrap();
+        let rsps = batch.send().await.unwrap();
+        assert_eq!(rsps.len(), 4);
+        assert_eq!(rsps.get::<i32>(add).unwrap(), 2);
+        assert_eq!(rsps.get::<String>(hello).unwrap(), "hello drpc");
+        assert_eq!(rsps.get::<i32>(fail).unwrap_err().to_string(), "boom");
+        assert_eq!(
+            rsps.get::<i32>(missing).unwrap_err().to_string(),
+            "method='missing' not find!"
+        );
+        assert!(rsps.get::<i32>(4).is_err());
+        assert_eq!(s.metrics().calls.load(Ordering::SeqCst), 4);
+        assert_eq!(s.metrics().errors.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_batch_concurrent() {
+        let c = serve(Arc::new(server())).await;
+        let mut batch = c.batch().set_concurrent(true);
+        for i in 0..5 {
+            batch.push("slow", i).unwrap();
+        }
+        let start = Instant::now();
+        let rsps = batch.send().await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(200));
+        for i in 0..5 {
+            assert_eq!(rsps.get::<i32>(i).unwrap(), i as i32);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_by_name() {
+        let c = serve(Arc::new(server())).await.set_method_id(false);
+        let mut batch = c.batch();
+        batch.push("add", 41).unwrap();
+        let rsps = batch.send().await.unwrap();
+        assert_eq!(rsps.get::<i32>(0).unwrap(), 42);
+        // the connection goes on after the batch
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[cfg(feature = "fingerprint")]
+    #[tokio::test]
+    async fn test_batch_fingerprint() {
+        let mut s = Server::default().set_fingerprint(true);
+        s.register_fn_checked("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        let s = Arc::new(s);
+        let c = serve(s.clone()).await;
+        let mut batch = c.batch();
+        let checked = batch.push_checked::<i32, i32>("add", 1).unwrap();
+        let wrong = batch.push_checked::<i32, String>("add", 1).unwrap();
+        let unchecked = batch.push("add", 2).unwrap();
+        let rsps = batch.send().await.unwrap();
+        assert_eq!(rsps.get::<i32>(checked).unwrap(), 2);
+        assert!(rsps
+            .get::<String>(wrong)
+            .unwrap_err()
+            .to_string()
+            .starts_with("incompatible schema: method 'add'"));
+        assert_eq!(rsps.get::<i32>(unchecked).unwrap(), 3);
+        assert_eq!(s.metrics().calls.load(Ordering::SeqCst), 3);
+        assert_eq!(s.metrics().errors.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_bad_fingerprints() {
+        let s = Arc::new(server());
+        let c = serve(s.clone()).await;
+        let mut req = BatchRequest::new();
+        req.push("add", 1, &BinCodec {}).unwrap();
+        req.push("add", 2, &BinCodec {}).unwrap();
+        req.fingerprints.push((5, 1));
+        let rsps = c.call_batch(&req).await.unwrap();
+        for i in 0..2 {
+            assert_eq!(
+                rsps.get::<i32>(i).unwrap_err().to_string(),
+                "batch has no call 5!"
+            );
+        }
+        // each call of the batch is counted
+        assert_eq!(s.metrics().calls.load(Ordering::SeqCst), 2);
+        assert_eq!(s.metrics().errors.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_empty_batch() {
+        let c = serve(Arc::new(server())).await;
+        let rsps = c.batch().send().await.unwrap();
+        assert!(rsps.is_empty());
+    }
+}
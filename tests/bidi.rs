@@ -0,0 +1,141 @@
+#[cfg(test)]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::BinCodec;
+    use drpc::server::Server;
+    use drpc::stream::{RequestStream, ResponseSender, ResponseStream};
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    async fn serve(s: Server<BinCodec>) -> Client<BinCodec> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                stream.set_nodelay(true).unwrap();
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        Client::<BinCodec>::dial(&addr).await.unwrap()
+    }
+
+    fn server() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_bidi_fn(
+            "double",
+            |mut reqs: RequestStream<i32>, rsps: ResponseSender<i32>| async move {
+                while let Some(v) = reqs.next().await {
+                    rsps.send(v? * 2).await?;
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+        s.register_bidi_fn(
+            "fail",
+            |mut reqs: RequestStream<i32>, rsps: ResponseSender<i32>| async move {
+                let v = reqs.next().await.unwrap()?;
+                rsps.send(v).await?;
+                Err(drpc::Error::from("boom"))
+            },
+        )
+        .unwrap();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s
+    }
+
+    #[tokio::test]
+    async fn test_bidi() {
+        let c = serve(server()).await;
+        let (mut tx, rx): (_, ResponseStream<i32>) = c.call_bidi::<i32, _>("double").await.unwrap();
+        for v in 1..=5 {
+            tx.send(v).await.unwrap();
+        }
+        tx.close();
+        let rsps: Vec<i32> = rx.map(|v| v.unwrap()).collect().await;
+        assert_eq!(rsps, vec![2, 4, 6, 8, 10]);
+    }
+
+    #[tokio::test]
+    async fn test_bidi_with_unary() {
+        let c = serve(server()).await;
+        let (mut tx, mut rx): (_, ResponseStream<i32>) =
+            c.call_bidi::<i32, _>("double").await.unwrap();
+        tx.send(1).await.unwrap();
+        assert_eq!(rx.next().await.unwrap().unwrap(), 2);
+        // the unary calls and the streams share the connection
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        let mut items: ResponseStream<i32> = c.call_bidi::<i32, _>("double").await.unwrap().1;
+        tx.send(2).await.unwrap();
+        assert_eq!(rx.next().await.unwrap().unwrap(), 4);
+        drop(tx);
+        assert!(rx.next().await.is_none());
+        // the sender of the second call is dropped, its responses end
+        assert!(items.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_bidi_half_close() {
+        let sum = Arc::new(AtomicI64::new(0));
+        let mut s = server();
+        let total = sum.clone();
+        s.register_bidi_fn(
+            "sum",
+            move |mut reqs: RequestStream<i64>, rsps: ResponseSender<i64>| {
+                let total = total.clone();
+                async move {
+                    rsps.send(0).await?;
+                    // the responses end, the requests go on
+                    drop(rsps);
+                    while let Some(v) = reqs.next().await {
+                        total.fetch_add(v?, Ordering::SeqCst);
+                    }
+                    Ok(())
+                }
+            },
+        )
+        .unwrap();
+        let c = serve(s).await;
+        let (mut tx, rx): (_, ResponseStream<i64>) = c.call_bidi::<i64, _>("sum").await.unwrap();
+        let rsps: Vec<i64> = rx.map(|v| v.unwrap()).collect().await;
+        assert_eq!(rsps, vec![0]);
+        for v in 1..=100 {
+            tx.send(v).await.unwrap();
+        }
+        tx.close();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(sum.load(Ordering::SeqCst), 5050);
+    }
+
+    #[tokio::test]
+    async fn test_bidi_error() {
+        let c = serve(server()).await;
+        let (mut tx, rx): (_, ResponseStream<i32>) = c.call_bidi::<i32, _>("fail").await.unwrap();
+        tx.send(7).await.unwrap();
+        let rsps: Vec<drpc::Result<i32>> = rx.collect().await;
+        assert_eq!(rsps.len(), 2);
+        assert_eq!(rsps[0].as_ref().unwrap(), &7);
+        assert_eq!(rsps[1].as_ref().unwrap_err().to_string(), "boom");
+    }
+
+    #[tokio::test]
+    async fn test_stream_with_unary() {
+        let mut s = server();
+        s.register_stream_fn("count", |n: i32| futures::stream::iter((0..n).map(Ok)))
+            .unwrap();
+        let c = serve(s).await;
+        let items: ResponseStream<i32> = c.call_stream("count", 3).await.unwrap();
+        // a stream no longer holds the connection
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        let items: Vec<i32> = items.map(|v| v.unwrap()).collect().await;
+        assert_eq!(items, vec![0, 1, 2]);
+    }
+}
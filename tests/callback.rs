@@ -0,0 +1,138 @@
+#[cfg(test)]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::BinCodec;
+    use drpc::peer::Peer;
+    use drpc::server::Server;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Serve `s`, the peers of its connections come out of the receiver.
+    async fn serve(s: Server<BinCodec>) -> (String, mpsc::UnboundedReceiver<Peer<BinCodec>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (tx, peers) = mpsc::unbounded_channel();
+        let s = Arc::new(s.set_on_connect(move |peer| {
+            let _ = tx.send(peer);
+        }));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                stream.set_nodelay(true).unwrap();
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        (addr, peers)
+    }
+
+    fn server() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s.register_fn("slow", |arg: i32| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(arg)
+        })
+        .unwrap();
+        s
+    }
+
+    fn callbacks() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_fn("hello", |name: String| async move {
+            Ok(format!("hello {}", name))
+        })
+        .unwrap();
+        s
+    }
+
+    #[tokio::test]
+    async fn test_callback() {
+        let (addr, mut peers) = serve(server()).await;
+        let c = Client::<BinCodec>::dial(&addr)
+            .await
+            .unwrap()
+            .set_callbacks(callbacks());
+        let peer = peers.recv().await.unwrap();
+        let resp: String = peer.call("hello", "drpc".to_string()).await.unwrap();
+        assert_eq!(resp, "hello drpc");
+        // the client still calls the server over the same connection
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        let resp: drpc::Result<i32> = peer.call("add", 1).await;
+        assert!(resp.unwrap_err().to_string().ends_with("not find!"));
+    }
+
+    #[tokio::test]
+    async fn test_callback_during_call() {
+        let (addr, mut peers) = serve(server()).await;
+        let c = Client::<BinCodec>::dial(&addr)
+            .await
+            .unwrap()
+            .set_callbacks(callbacks());
+        let peer = peers.recv().await.unwrap();
+        // the calls of both directions are in flight at once, their ids do not collide
+        let (slow, hellos) = tokio::join!(c.call::<i32, i32>("slow", 7), async {
+            let mut hellos = vec![];
+            for i in 0..10 {
+                let v: String = peer.call("hello", i.to_string()).await.unwrap();
+                hellos.push(v);
+            }
+            hellos
+        });
+        assert_eq!(slow.unwrap(), 7);
+        assert_eq!(hellos.len(), 10);
+        assert_eq!(hellos[9], "hello 9");
+    }
+
+    #[tokio::test]
+    async fn test_callback_notify() {
+        let (addr, mut peers) = serve(server()).await;
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let mut callbacks = callbacks();
+        callbacks
+            .register_fn("event", move |v: i32| {
+                let tx = tx.clone();
+                async move {
+                    tx.send(v).unwrap();
+                    Ok(())
+                }
+            })
+            .unwrap();
+        let _c = Client::<BinCodec>::dial(&addr)
+            .await
+            .unwrap()
+            .set_callbacks(callbacks);
+        let peer = peers.recv().await.unwrap();
+        peer.notify("event", 7).await.unwrap();
+        assert_eq!(events.recv().await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_no_callbacks() {
+        let (addr, mut peers) = serve(server()).await;
+        let c = Client::<BinCodec>::dial(&addr).await.unwrap();
+        let peer = peers.recv().await.unwrap();
+        let resp: drpc::Result<String> = peer.call("hello", "drpc".to_string()).await;
+        assert_eq!(resp.unwrap_err().to_string(), "client serves no callbacks!");
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_callback_after_shutdown() {
+        let (addr, mut peers) = serve(server()).await;
+        let mut c = Client::<BinCodec>::dial(&addr)
+            .await
+            .unwrap()
+            .set_callbacks(callbacks());
+        let peer = peers.recv().await.unwrap();
+        c.shutdown().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(peer.is_closed());
+        let resp: drpc::Result<String> = peer.call("hello", "drpc".to_string()).await;
+        assert!(resp.is_err());
+    }
+}
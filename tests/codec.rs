@@ -0,0 +1,292 @@
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+    use drpc::codec::{BinCodec, Codec, JsonCodec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Borrowed<'a> {
+        name: &'a str,
+        age: i32,
+    }
+
+    #[cfg(feature = "cbor")]
+    #[derive(Deserialize)]
+    struct Owned {
+        name: String,
+        age: i32,
+    }
+
+    #[derive(Clone, Default)]
+    struct OwnedCodec {}
+
+    impl Codec for OwnedCodec {
+        fn encode<T: Serialize>(&self, arg: T) -> drpc::Result<Vec<u8>> {
+            BinCodec {}.encode(arg)
+        }
+
+        fn decode<T: serde::de::DeserializeOwned>(&self, arg: &[u8]) -> drpc::Result<T> {
+            BinCodec {}.decode(arg)
+        }
+
+        fn decode_borrowed<'de, T: Deserialize<'de>>(&self, arg: &'de [u8]) -> drpc::Result<T> {
+            BinCodec {}.decode_borrowed(arg)
+        }
+    }
+
+    fn encode_into<C: Codec>(codec: C) {
+        let arg = Borrowed {
+            name: "joe",
+            age: 18,
+        };
+        let mut buf = BytesMut::from(&b"head"[..]);
+        codec.encode_into(&arg, &mut buf).unwrap();
+        assert_eq!(&buf[..4], b"head");
+        assert_eq!(&buf[4..], codec.encode(&arg).unwrap());
+    }
+
+    fn decode_borrowed<C: Codec>(codec: C) {
+        let data = codec
+            .encode(Borrowed {
+                name: "joe",
+                age: 18,
+            })
+            .unwrap();
+        let v: Borrowed = codec.decode_borrowed(&data).unwrap();
+        assert_eq!(v.name, "joe");
+        assert_eq!(v.age, 18);
+        // points into the buffer, nothing was copied
+        assert!(data.as_ptr_range().contains(&v.name.as_ptr()));
+    }
+
+    #[test]
+    fn test_encode_into() {
+        encode_into(BinCodec {});
+        encode_into(JsonCodec {});
+        encode_into(OwnedCodec {});
+        #[cfg(feature = "msgpack")]
+        {
+            encode_into(drpc::codec::MsgPackCodec::map());
+            encode_into(drpc::codec::MsgPackCodec::array());
+        }
+        #[cfg(feature = "cbor")]
+        {
+            encode_into(drpc::codec::CborCodec::default());
+            encode_into(drpc::codec::CborCodec::default().self_describe(true));
+        }
+    }
+
+    #[test]
+    fn test_decode_borrowed() {
+        decode_borrowed(BinCodec {});
+        decode_borrowed(JsonCodec {});
+        decode_borrowed(OwnedCodec {});
+        #[cfg(feature = "msgpack")]
+        {
+            decode_borrowed(drpc::codec::MsgPackCodec::map());
+            decode_borrowed(drpc::codec::MsgPackCodec::array());
+        }
+        #[cfg(feature = "cbor")]
+        {
+            decode_borrowed(drpc::codec::CborCodec::default());
+            decode_borrowed(drpc::codec::CborCodec::default().self_describe(true));
+        }
+    }
+
+    #[test]
+    fn test_decode_borrowed_bytes() {
+        let data = BinCodec {}.encode(&[1u8, 2, 3][..]).unwrap();
+        let v: &[u8] = BinCodec {}.decode_borrowed(&data).unwrap();
+        assert_eq!(v, &[1, 2, 3]);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_modes() {
+        use drpc::codec::MsgPackCodec;
+        let arg = Borrowed {
+            name: "joe",
+            age: 18,
+        };
+        let map = MsgPackCodec::map().encode(&arg).unwrap();
+        let array = MsgPackCodec::array().encode(&arg).unwrap();
+        // fixmap of 2 / fixarray of 2
+        assert_eq!(map[0], 0x82);
+        assert_eq!(array[0], 0x92);
+        assert!(array.len() < map.len());
+        assert!(map.windows(4).any(|x| x == b"name"));
+        // decode reads both layouts
+        for data in [&map, &array] {
+            let v: Borrowed = MsgPackCodec::map().decode_borrowed(data).unwrap();
+            assert_eq!(v, arg);
+            let v: Borrowed = MsgPackCodec::array().decode_borrowed(data).unwrap();
+            assert_eq!(v, arg);
+        }
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_decode_err() {
+        use drpc::codec::MsgPackCodec;
+        let data = MsgPackCodec::default().encode("joe").unwrap();
+        assert!(MsgPackCodec::default().decode::<i32>(&data).is_err());
+        assert!(MsgPackCodec::default()
+            .decode::<String>(&data[..2])
+            .is_err());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor() {
+        use drpc::codec::{CborCodec, CBOR_SELF_DESCRIBE};
+        let arg = Borrowed {
+            name: "joe",
+            age: 18,
+        };
+        let plain = CborCodec::default().encode(&arg).unwrap();
+        let tagged = CborCodec::default()
+            .self_describe(true)
+            .encode(&arg)
+            .unwrap();
+        assert_eq!(tagged[..3], CBOR_SELF_DESCRIBE);
+        assert_eq!(tagged[3..], plain[..]);
+        // decode accepts both, whatever the codec's own setting
+        for codec in [
+            CborCodec::default(),
+            CborCodec::default().self_describe(true),
+        ] {
+            for data in [&plain, &tagged] {
+                let v: Owned = codec.decode(data).unwrap();
+                assert_eq!(v.name, "joe");
+                assert_eq!(v.age, 18);
+            }
+        }
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor_decode_err() {
+        use drpc::codec::CborCodec;
+        let codec = CborCodec::default().self_describe(true);
+        let data = codec.encode("joe").unwrap();
+        assert!(codec.decode::<i32>(&data).is_err());
+        assert!(codec.decode::<String>(&data[..5]).is_err());
+        // only the tag
+        assert!(codec.decode::<String>(&data[..3]).is_err());
+        assert!(codec.decode::<String>(&[]).is_err());
+        assert_eq!(codec.decode_borrowed::<&str>(&data).unwrap(), "joe");
+        assert!(codec.decode_borrowed::<&str>(&data[..5]).is_err());
+        assert!(codec.decode_borrowed::<i32>(&data).is_err());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape<'a> {
+        Empty,
+        Named(&'a str),
+        Pair(i8, u64),
+        Rect { w: f32, h: f64 },
+    }
+
+    #[cfg(feature = "cbor")]
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct All<'a> {
+        shapes: Vec<Shape<'a>>,
+        none: Option<i32>,
+        some: Option<&'a str>,
+        big: i128,
+        small: i64,
+        map: std::collections::BTreeMap<u8, bool>,
+        unit: (),
+        c: char,
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor_decode_borrowed() {
+        use drpc::codec::CborCodec;
+        let codec = CborCodec::default();
+        let arg = All {
+            shapes: vec![
+                Shape::Empty,
+                Shape::Named("joe"),
+                Shape::Pair(-3, u64::MAX),
+                Shape::Rect { w: 1.5, h: 1e300 },
+            ],
+            none: None,
+            some: Some("x"),
+            big: i128::MIN,
+            small: i64::MIN,
+            map: [(1, true), (2, false)].into_iter().collect(),
+            unit: (),
+            c: '字',
+        };
+        let data = codec.encode(&arg).unwrap();
+        let v: All = codec.decode_borrowed(&data).unwrap();
+        assert_eq!(v, arg);
+        // serde writes `&[u8]` as an array, a byte string is borrowed
+        let data = [0x42, 1, 2];
+        let v: &[u8] = codec.decode_borrowed(&data).unwrap();
+        assert_eq!(v, &[1, 2]);
+        assert!(data.as_ptr_range().contains(&v.as_ptr()));
+        // indefinite lengths and chunked strings are read too
+        let data = [
+            0x9f, 0x7f, 0x62, b'j', b'o', 0x61, b'e', 0xff, 0xf9, 0x3c, 0x00, 0xff,
+        ];
+        let v: (String, f32) = codec.decode_borrowed(&data).unwrap();
+        assert_eq!(v, ("joe".to_string(), 1.0));
+        // an array with items left over
+        let data = codec.encode((1, 2, 3)).unwrap();
+        assert!(codec.decode_borrowed::<(i32, i32)>(&data).is_err());
+    }
+
+    #[cfg(feature = "prost")]
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Hello {
+        #[prost(string, tag = "1")]
+        name: String,
+        #[prost(int32, tag = "2")]
+        age: i32,
+    }
+
+    #[cfg(feature = "prost")]
+    #[test]
+    fn test_prost() {
+        use drpc::codec::{Decode, DecodeBorrowed, Encode, ProstCodec};
+        let codec = ProstCodec {};
+        let arg = Hello {
+            name: "joe".to_string(),
+            age: 18,
+        };
+        let data = arg.clone().encode(&codec).unwrap();
+        assert_eq!(data, prost::Message::encode_to_vec(&arg));
+        let mut buf = BytesMut::from(&b"head"[..]);
+        arg.clone().encode_into(&codec, &mut buf).unwrap();
+        assert_eq!(&buf[4..], &data[..]);
+        assert_eq!(Hello::decode(&codec, &data).unwrap(), arg);
+        assert_eq!(Hello::decode_borrowed(&codec, &data).unwrap(), arg);
+        // truncated
+        assert!(Hello::decode(&codec, &data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_any_codec() {
+        use drpc::codec::{AnyCodec, Format, CODEC_BIN, CODEC_JSON, CODEC_PROST};
+        let codec = AnyCodec::default();
+        assert_eq!(codec.id(), CODEC_BIN);
+        let json = codec.select(CODEC_JSON).unwrap();
+        assert_eq!(json.id(), CODEC_JSON);
+        assert!(codec.select(CODEC_PROST).is_none());
+        assert!(codec.select(0).is_none());
+        assert_eq!(
+            json.encode("joe").unwrap(),
+            JsonCodec {}.encode("joe").unwrap()
+        );
+        encode_into(json);
+        decode_borrowed(json);
+        assert!(AnyCodec::new(0).encode("joe").is_err());
+        // a custom codec only selects itself
+        assert!(OwnedCodec {}.select(0).is_some());
+        assert!(OwnedCodec {}.select(CODEC_BIN).is_none());
+    }
+}
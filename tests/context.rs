@@ -0,0 +1,145 @@
+#[cfg(test)]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::BinCodec;
+    use drpc::context::{Context, State};
+    use drpc::frame::FrameCodec;
+    use drpc::interceptor::from_fn;
+    use drpc::metadata::Metadata;
+    use drpc::server::Server;
+    use drpc::stub::{method_id, ClientStub};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_util::codec::Framed;
+
+    async fn serve(s: Server<BinCodec>) -> Client<BinCodec> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            while let Ok((stream, addr)) = listener.accept().await {
+                stream.set_nodelay(true).unwrap();
+                let s = s.clone();
+                tokio::spawn(async move { s.call_from(stream, addr).await });
+            }
+        });
+        Client::<BinCodec>::dial(&addr).await.unwrap()
+    }
+
+    async fn who(ctx: Context, req: i32) -> drpc::Result<String> {
+        let token = ctx
+            .metadata
+            .get("token")
+            .map(|v| String::from_utf8_lossy(v).to_string());
+        Ok(format!(
+            "{} {} odd={} peer={:?} token={:?}",
+            ctx.method,
+            req,
+            ctx.id % 2 == 1,
+            ctx.peer_addr.map(|v| v.ip().to_string()),
+            token
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_context() {
+        let mut s = Server::default();
+        s.register_fn("who", who).unwrap();
+        let c = serve(s).await.add_interceptor(from_fn(|mut req, next| {
+            Box::pin(async move {
+                req.metadata.insert("token", "open");
+                next.run(req).await
+            })
+        }));
+        let resp: String = c.call("who", 1).await.unwrap();
+        assert_eq!(
+            resp,
+            "who 1 odd=true peer=Some(\"127.0.0.1\") token=Some(\"open\")"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_context_without_addr() {
+        let mut s = Server::default();
+        s.register_fn("who", who).unwrap();
+        let (client, server_io) = tokio::io::duplex(1024);
+        tokio::spawn(async move { s.call(server_io).await });
+        let mut stream = Framed::new(client, FrameCodec::default());
+        let stub = ClientStub::new();
+        let resp: String = stub
+            .call("who", 2, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "who 2 odd=true peer=None token=None");
+    }
+
+    #[tokio::test]
+    async fn test_state() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let mut s = Server::default().set_state(counter.clone());
+        s.register_fn(
+            "count",
+            |State(n): State<Arc<AtomicUsize>>, req: usize| async move {
+                Ok(n.fetch_add(req, Ordering::SeqCst) + req)
+            },
+        )
+        .unwrap();
+        s.register_fn(
+            "event",
+            move |ctx: Context, State(n): State<Arc<AtomicUsize>>, _: ()| {
+                let tx = tx.clone();
+                async move {
+                    tx.send((ctx.method, n.load(Ordering::SeqCst))).unwrap();
+                    Ok(())
+                }
+            },
+        )
+        .unwrap();
+        // a state of another type fails the registration, not the calls
+        let e = s
+            .register_fn(
+                "missing",
+                |State(v): State<String>, _: ()| async move { Ok(v) },
+            )
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "method 'missing': state alloc::string::String is not set!"
+        );
+        assert!(s.ids.get(&method_id("missing")).is_none());
+        s.register_fn("metadata", |m: Metadata, _: ()| async move {
+            Ok(m.get("absent").is_none())
+        })
+        .unwrap();
+        let c = serve(s).await;
+        let resp: usize = c.call("count", 2usize).await.unwrap();
+        assert_eq!(resp, 2);
+        let resp: usize = c.call("count", 3usize).await.unwrap();
+        assert_eq!(resp, 5);
+        assert_eq!(counter.load(Ordering::SeqCst), 5);
+        // the notifications and the calls of a batch extract too
+        c.notify("event", ()).await.unwrap();
+        assert_eq!(events.recv().await.unwrap(), ("event".to_string(), 5));
+        let mut batch = c.batch();
+        batch.push("count", 1usize).unwrap();
+        let rsps = batch.send().await.unwrap();
+        assert_eq!(rsps.get::<usize>(0).unwrap(), 6);
+        let resp: bool = c.call("metadata", ()).await.unwrap();
+        assert!(resp);
+    }
+
+    #[test]
+    fn test_state_unset() {
+        // the state is set before the handlers taking it
+        let mut s = Server::<BinCodec>::default();
+        assert!(s
+            .register_fn("n", |State(n): State<usize>, _: ()| async move { Ok(n) })
+            .is_err());
+        let mut s = Server::<BinCodec>::default().set_state(1usize);
+        s.register_fn("n", |State(n): State<usize>, _: ()| async move { Ok(n) })
+            .unwrap();
+    }
+}
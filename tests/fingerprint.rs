@@ -0,0 +1,83 @@
+#[cfg(all(test, feature = "fingerprint"))]
+mod test {
+    use drpc::codec::{BinCodec, JsonCodec};
+    use drpc::fingerprint;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: i32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct UserV2 {
+        name: String,
+        age: i64,
+    }
+
+    /// `User` renamed, the same on the wire
+    #[derive(Serialize, Deserialize)]
+    struct Person {
+        name: String,
+        age: i32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Team {
+        lead: User,
+        members: Vec<User>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Group {
+        lead: Person,
+        members: Vec<Person>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Node {
+        value: i32,
+        next: Option<Box<Node>>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    enum Shape {
+        Circle(f64),
+        Rect { w: f64, h: f64 },
+    }
+
+    #[derive(Serialize, Deserialize)]
+    enum ShapeV2 {
+        Circle(f64),
+        Rect { w: f64, h: f64 },
+        Point,
+    }
+
+    #[test]
+    fn test_fingerprint_of() {
+        let user = fingerprint::of::<User>().unwrap();
+        assert_eq!(Some(user), fingerprint::of::<User>());
+        assert_ne!(Some(user), fingerprint::of::<UserV2>());
+        assert_ne!(fingerprint::of::<i32>(), fingerprint::of::<i64>());
+        assert_ne!(fingerprint::of::<Shape>(), fingerprint::of::<ShapeV2>());
+    }
+
+    #[test]
+    fn test_fingerprint_renamed() {
+        // the names of the types are not part of the shape
+        assert_eq!(fingerprint::of::<User>(), fingerprint::of::<Person>());
+        assert_eq!(fingerprint::of::<Team>(), fingerprint::of::<Group>());
+        assert_ne!(fingerprint::of::<Team>(), fingerprint::of::<User>());
+        assert!(fingerprint::of::<Node>().is_some());
+    }
+
+    #[test]
+    fn test_fingerprint_method() {
+        let m = fingerprint::method::<BinCodec, User, i32>().unwrap();
+        assert_eq!(Some(m), fingerprint::method::<JsonCodec, User, i32>());
+        assert_eq!(Some(m), fingerprint::method_cached::<BinCodec, User, i32>());
+        assert_ne!(Some(m), fingerprint::method::<BinCodec, i32, User>());
+        assert_ne!(Some(m), fingerprint::method::<BinCodec, UserV2, i32>());
+    }
+}
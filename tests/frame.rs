@@ -1,10 +1,15 @@
 #[cfg(test)]
 mod test {
-    use drpc::frame::Frame;
+    use bytes::{Bytes, BytesMut};
+    use drpc::frame::{
+        get_varint, put_varint, ChecksumError, Frame, FrameCodec, FrameVersion, FLAG_COMPRESSED,
+        FLAG_METADATA, FLAG_STREAM, FLAG_V2,
+    };
     use std::io::Error;
     use std::pin::Pin;
     use std::task::{Context, Poll};
     use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+    use tokio_util::codec::{Decoder, Encoder};
 
     pub struct Mock {
         pub inner: Vec<u8>,
@@ -57,4 +62,237 @@ mod test {
         let f = Frame::decode_from(&mut mock).await.unwrap();
         println!("id={},ok={},data={:?}", f.id, f.ok, f.data);
     }
+
+    #[tokio::test]
+    async fn test_frame_v2() {
+        let mut req = Frame::new();
+        req.version = FrameVersion::V2;
+        req.ok = 1;
+        req.flags = FLAG_STREAM;
+        req.metadata = Bytes::from_static(b"trace=1");
+        let _ = req.write_all("hello".as_bytes()).await;
+        let data = req.finish(300);
+        // flags(1) + id(2) + meta_len(1) + meta(7) + len(1) + payload(5)
+        assert_eq!(data.len(), 17);
+        let f = Frame::decode_from(&mut data.as_slice()).await.unwrap();
+        assert_eq!(f.id, 300);
+        assert_eq!(f.ok, 1);
+        assert!(f.has_flag(FLAG_STREAM));
+        assert!(!f.has_flag(FLAG_COMPRESSED));
+        assert_eq!(f.metadata, "trace=1".as_bytes());
+        assert_eq!(f.data, "hello".as_bytes());
+        assert_eq!(f.version, FrameVersion::V2);
+    }
+
+    #[tokio::test]
+    async fn test_frame_v1() {
+        let mut req = Frame::new();
+        req.version = FrameVersion::V1;
+        req.ok = 1;
+        let _ = req.write_all("hello".as_bytes()).await;
+        let data = req.finish(u32::MAX as u64 + 1);
+        assert_eq!(data.len(), 17 + 5);
+        let f = Frame::decode_from(&mut data.as_slice()).await.unwrap();
+        assert_eq!(f.id, u32::MAX as u64 + 1);
+        assert_eq!(f.ok, 1);
+        assert_eq!(f.data, "hello".as_bytes());
+        assert_eq!(f.version, FrameVersion::V1);
+    }
+
+    #[tokio::test]
+    async fn test_frame_v2_max_id() {
+        let mut req = Frame::new();
+        req.version = FrameVersion::V2;
+        let data = req.finish(u64::MAX);
+        let f = Frame::decode_from(&mut data.as_slice()).await.unwrap();
+        assert_eq!(f.id, u64::MAX);
+        assert_eq!(f.ok, 0);
+        assert!(f.data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_frame_checksum() {
+        let mut req = Frame::new();
+        req.version = FrameVersion::V2;
+        req.ok = 1;
+        req.metadata = Bytes::from_static(b"trace=1");
+        let _ = req.write_all("hello".as_bytes()).await;
+        let data = req.clone().finish_with(7, true);
+        assert_eq!(data.len(), req.clone().finish(7).len() + 4);
+        let f = Frame::decode_with(&mut data.as_slice(), true)
+            .await
+            .unwrap();
+        assert_eq!(f.id, 7);
+        assert_eq!(f.data, "hello".as_bytes());
+
+        // flip one payload bit
+        let mut corrupted = data.clone();
+        let idx = corrupted.len() - 5;
+        corrupted[idx] ^= 0x01;
+        let e = Frame::decode_with(&mut corrupted.as_slice(), true)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        let e = ChecksumError::from_io(&e).unwrap();
+        assert_ne!(e.expected, e.actual);
+    }
+
+    #[test]
+    fn test_frame_checksum_v1() {
+        let mut codec = FrameCodec::default().set_checksum(true);
+        let mut req = Frame::new();
+        req.version = FrameVersion::V2;
+        req.id = 7;
+        req.data = Bytes::from_static(b"hello");
+        let mut buf = BytesMut::new();
+        codec.encode(req, &mut buf).unwrap();
+        // flipping the v2 bit must not skip the check
+        buf[0] ^= FLAG_V2;
+        let e = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        // nor can a v1 frame be sent as it is
+        let mut buf = BytesMut::new();
+        let mut req = Frame::new();
+        req.version = FrameVersion::V1;
+        FrameCodec::default().encode(req, &mut buf).unwrap();
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_frame_codec() {
+        let mut codec = FrameCodec::default().set_checksum(true);
+        let mut buf = BytesMut::new();
+        for id in 1..=2 {
+            let mut req = Frame::new();
+            req.version = FrameVersion::V2;
+            req.id = id;
+            req.metadata = Bytes::from_static(b"trace=1");
+            req.data = Bytes::from_static(b"hello");
+            codec.encode(req, &mut buf).unwrap();
+        }
+        let all = buf.split().freeze();
+        // a partial frame waits for more bytes
+        buf.extend_from_slice(&all[..3]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&all[3..]);
+        let f = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(f.id, 1);
+        assert_eq!(f.metadata, "trace=1".as_bytes());
+        assert_eq!(f.data, "hello".as_bytes());
+        let f = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(f.id, 2);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_frame_max_len() {
+        let mut codec = FrameCodec::default().set_max_frame_len(64);
+        // a small header declaring a huge payload fails before any allocation
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[FLAG_V2, 1]);
+        put_varint(&mut buf, u64::MAX >> 1);
+        let e = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert!(buf.capacity() < 64);
+        // so does a huge metadata block
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[FLAG_V2 | FLAG_METADATA, 1]);
+        put_varint(&mut buf, 1 << 40);
+        let e = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        // and a v1 frame
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&1u64.to_be_bytes());
+        buf.extend_from_slice(&[1]);
+        buf.extend_from_slice(&(1u64 << 40).to_be_bytes());
+        let e = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        // a frame up to the max is read
+        let mut buf = BytesMut::new();
+        let mut req = Frame::new();
+        req.data = Bytes::from(vec![0u8; 32]);
+        codec.encode(req, &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().data.len(), 32);
+    }
+
+    #[tokio::test]
+    async fn test_frame_write_to() {
+        let mut req = Frame::new();
+        req.id = 9;
+        req.data = Bytes::from_static(b"hello");
+        let mut out = vec![];
+        req.clone().write_to(&mut out, true).await.unwrap();
+        assert_eq!(out, req.finish_with(9, true));
+    }
+
+    #[tokio::test]
+    async fn test_frame_write_to_metadata() {
+        let mut req = Frame::new();
+        req.version = FrameVersion::V2;
+        req.id = 300;
+        req.method_id = Some(u64::MAX);
+        req.metadata = Bytes::from_static(b"trace=1");
+        req.data = Bytes::from(vec![7u8; 200]);
+        let mut out = vec![];
+        req.clone().write_to(&mut out, true).await.unwrap();
+        assert_eq!(out, req.clone().finish_with(300, true));
+        let frame = Frame::decode_with(&mut out.as_slice(), true).await.unwrap();
+        assert_eq!(frame.id, 300);
+        assert_eq!(frame.method_id, req.method_id);
+        assert_eq!(frame.metadata, req.metadata);
+        assert_eq!(frame.data, req.data);
+    }
+
+    #[tokio::test]
+    async fn test_frame_decode_with_stops_at_frame() {
+        // each frame is read alone, the next one is left in the reader
+        let mut a = Frame::new();
+        a.version = FrameVersion::V2;
+        a.method_id = Some(1);
+        a.metadata = Bytes::from_static(b"m");
+        a.data = Bytes::from(vec![1u8; 300]);
+        let mut b = Frame::new();
+        b.data = Bytes::from_static(b"b");
+        let mut buf = a.clone().finish(1);
+        buf.extend(b.clone().finish(2));
+        let mut r = buf.as_slice();
+        assert_eq!(Frame::decode_from(&mut r).await.unwrap().data, a.data);
+        assert_eq!(Frame::decode_from(&mut r).await.unwrap().data, b.data);
+        assert!(r.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_frame_write_many() {
+        let mut req = Frame::new();
+        for i in 0..1000u32 {
+            req.write_all(&i.to_be_bytes()).await.unwrap();
+        }
+        assert_eq!(req.data.len(), 4000);
+        assert_eq!(&req.data[3996..], &999u32.to_be_bytes());
+        // a shared payload is copied, not changed under its other holder
+        let shared = req.data.clone();
+        req.write_all(b"x").await.unwrap();
+        assert_eq!(shared.len(), 4000);
+        assert_eq!(req.data.len(), 4001);
+    }
+
+    #[test]
+    fn test_frame_default_v1() {
+        // every server reads v1, v2 waits for the handshake
+        assert_eq!(Frame::new().version, FrameVersion::V1);
+        assert_eq!(Frame::new().finish(1).len(), 17);
+    }
+
+    #[test]
+    fn test_varint_overflow() {
+        let mut buf = BytesMut::new();
+        put_varint(&mut buf, u64::MAX);
+        assert_eq!(buf.len(), 10);
+        assert_eq!(get_varint(&buf, &mut 0).unwrap(), Some(u64::MAX));
+        // a 10th byte over 1 sets bits past the 64th
+        buf[9] = 0x02;
+        let e = get_varint(&buf, &mut 0).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
 }
@@ -0,0 +1,255 @@
+#[cfg(test)]
+mod test {
+    use drpc::codec::{BinCodec, CODEC_JSON};
+    use drpc::compress::Compression;
+    use drpc::frame::{Frame, FrameCodec, FrameVersion};
+    use drpc::handshake::Handshake;
+    use drpc::server::Server;
+    use drpc::stub::ClientStub;
+    use std::sync::Arc;
+    use tokio::io::DuplexStream;
+    use tokio_util::codec::Framed;
+
+    async fn serve(s: Server<BinCodec>) -> Framed<DuplexStream, FrameCodec> {
+        let (client, server) = tokio::io::duplex(1024);
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            s.call(server).await;
+        });
+        Framed::new(client, FrameCodec::default())
+    }
+
+    fn server() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_fn("echo", |arg: String| async move { Ok(arg) })
+            .unwrap();
+        s
+    }
+
+    #[test]
+    fn test_handshake_accept() {
+        let server = Handshake::none().compressions(vec![Compression::Zstd, Compression::Lz4]);
+        let agreed = server
+            .accept(&Handshake::none().compressions(vec![Compression::Lz4, Compression::Zstd]));
+        assert_eq!(agreed.compression(), Some(Compression::Lz4));
+        let agreed = server.accept(&Handshake::none());
+        assert_eq!(agreed.compression(), None);
+    }
+
+    #[test]
+    fn test_handshake_decode() {
+        let h = Handshake::none().compressions(vec![Compression::Zstd]);
+        assert_eq!(Handshake::decode(&h.encode()).unwrap(), h);
+        // unknown algorithms are skipped
+        assert_eq!(
+            Handshake::decode(&[1, 2, 9, 2]).unwrap(),
+            Handshake::none().compressions(vec![Compression::Zstd])
+        );
+        assert!(Handshake::decode(&[1, 3, 1]).is_err());
+    }
+
+    #[test]
+    fn test_handshake_codec() {
+        let h = Handshake::none().codec(Some(CODEC_JSON));
+        assert_eq!(Handshake::decode(&h.encode()).unwrap(), h);
+        // 0 or no codec byte at all, from an older peer
+        assert_eq!(Handshake::decode(&[1, 0, 0, 0]).unwrap().codec, None);
+        assert_eq!(Handshake::decode(&[1, 0, 0]).unwrap().codec, None);
+    }
+
+    #[tokio::test]
+    async fn test_no_compression() {
+        let mut stream = serve(server()).await;
+        let mut stub = ClientStub::new();
+        let agreed = stub
+            .handshake(&Handshake::none(), &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(agreed.compression(), None);
+        // an agreed handshake switches to v2 frames
+        assert_eq!(stub.version, FrameVersion::V2);
+        let resp: String = stub
+            .call_with("echo", "hello".to_string(), &BinCodec {}, true, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "hello");
+    }
+
+    #[cfg(feature = "lz4")]
+    #[tokio::test]
+    async fn test_lz4() {
+        let mut stream = serve(server()).await;
+        let mut stub = ClientStub::new();
+        let handshake = Handshake::none().compressions(vec![Compression::Lz4]);
+        stub.handshake(&handshake, &mut stream).await.unwrap();
+        assert_eq!(stub.compression, Some(Compression::Lz4));
+        stub.compress_threshold = Some(1024);
+        let msg = "drpc".repeat(10000);
+        let resp: String = stub
+            .call("echo", msg.clone(), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, msg);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn test_zstd() {
+        let mut stream = serve(server().set_compress_threshold(Some(16))).await;
+        let mut stub = ClientStub::new();
+        let handshake = Handshake::none().compressions(vec![Compression::Zstd]);
+        stub.handshake(&handshake, &mut stream).await.unwrap();
+        assert_eq!(stub.compression, Some(Compression::Zstd));
+        let msg = "drpc".repeat(10000);
+        let resp: String = stub
+            .call_with("echo", msg.clone(), &BinCodec {}, true, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, msg);
+    }
+
+    #[tokio::test]
+    async fn test_checksum() {
+        let mut stream = serve(server()).await;
+        let mut stub = ClientStub::new();
+        let agreed = stub
+            .handshake(&Handshake::none().checksum(true), &mut stream)
+            .await
+            .unwrap();
+        assert!(agreed.checksum);
+        assert!(stream.codec().checksum);
+        let resp: String = stub
+            .call("echo", "hello".to_string(), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_checksum_refused() {
+        let s = server().set_handshake(Handshake::default());
+        let mut stream = serve(s).await;
+        let mut stub = ClientStub::new();
+        let agreed = stub
+            .handshake(&Handshake::none().checksum(true), &mut stream)
+            .await
+            .unwrap();
+        assert!(!agreed.checksum);
+        let resp: String = stub
+            .call("echo", "hello".to_string(), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "hello");
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4_max() {
+        let data = Compression::Lz4.compress(&[0u8; 4096]).unwrap();
+        assert_eq!(
+            Compression::Lz4.decompress(&data, 4096).unwrap().len(),
+            4096
+        );
+        assert!(Compression::Lz4.decompress(&data, 4095).is_err());
+        // a huge prepended size is refused before it is allocated
+        let bomb = [0xff, 0xff, 0xff, 0xff, 0x00];
+        assert_eq!(
+            Compression::Lz4
+                .decompress(&bomb, 4096)
+                .unwrap_err()
+                .to_string(),
+            "decompressed length 4294967295 is over the max 4096"
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_max() {
+        let data = Compression::Zstd.compress(&vec![0u8; 1 << 20]).unwrap();
+        assert!(data.len() < 1024);
+        assert_eq!(
+            Compression::Zstd.decompress(&data, 1 << 20).unwrap().len(),
+            1 << 20
+        );
+        assert!(Compression::Zstd.decompress(&data, 4096).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn test_decompress_max() {
+        let mut stream = serve(server().set_max_frame_len(4096)).await;
+        let mut stub = ClientStub::new();
+        let handshake = Handshake::none().compressions(vec![Compression::Zstd]);
+        stub.handshake(&handshake, &mut stream).await.unwrap();
+        // small on the wire, over the max once decompressed
+        let msg = "drpc".repeat(10000);
+        let resp: drpc::Result<String> = stub
+            .call_with("echo", msg, &BinCodec {}, true, &mut stream)
+            .await;
+        assert_eq!(
+            resp.unwrap_err().to_string(),
+            "decompressed length is over the max 4096"
+        );
+        let resp: String = stub
+            .call_with("echo", "hello".to_string(), &BinCodec {}, true, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_after_call() {
+        let mut stream = serve(server()).await;
+        let mut stub = ClientStub::new();
+        let resp: String = stub
+            .call("echo", "hello".to_string(), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "hello");
+        // refused once a call was made, the connection keeps its v1 frames
+        let agreed = stub
+            .handshake(&Handshake::default().checksum(true), &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(agreed, Handshake::none());
+        assert!(!stream.codec().checksum);
+        assert_eq!(stub.version, FrameVersion::V1);
+        let resp: String = stub
+            .call("echo", "world".to_string(), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "world");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_unknown() {
+        let (client, mut server_io) = tokio::io::duplex(1024);
+        let s = server();
+        tokio::spawn(async move {
+            // a server from before the handshake answers it as a failed call
+            // it reads v1 frames only
+            let req = Frame::decode_from(&mut server_io).await.unwrap();
+            assert_eq!(req.version, FrameVersion::V1);
+            let mut rsp = Frame::new();
+            rsp.id = req.id;
+            rsp.data = "method drpc.handshake not find!".into();
+            rsp.write_to(&mut server_io, false).await.unwrap();
+            s.call(server_io).await;
+        });
+        let mut stream = Framed::new(client, FrameCodec::default());
+        let mut stub = ClientStub::new();
+        let agreed = stub
+            .handshake(&Handshake::default().checksum(true), &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(agreed, Handshake::none());
+        assert!(!stream.codec().checksum);
+        // the calls stay on v1 frames
+        assert_eq!(stub.version, FrameVersion::V1);
+        let resp: String = stub
+            .call("echo", "hello".to_string(), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "hello");
+    }
+}
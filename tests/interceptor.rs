@@ -0,0 +1,169 @@
+#[cfg(test)]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::{BinCodec, Encode};
+    use drpc::interceptor::{self, from_fn};
+    use drpc::middleware::{self, Next, Request};
+    use drpc::server::Server;
+    use drpc::{BalanceManger, ManagerConfig, RegistryCenter};
+    use futures::future::BoxFuture;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Rejects the calls without the token the clients inject.
+    fn auth<'a>(req: Request<'a>, next: Next<'a>) -> BoxFuture<'a, drpc::Result<Vec<u8>>> {
+        Box::pin(async move {
+            match req.metadata.get("token") {
+                Some(v) if &v[..] == b"open" => next.run().await,
+                _ => Err(drpc::Error::from("unauthorized")),
+            }
+        })
+    }
+
+    async fn serve() -> (String, mpsc::UnboundedReceiver<i32>) {
+        let (tx, events) = mpsc::unbounded_channel();
+        let mut s = Server::default();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s.register_fn("fail", |_: i32| async move {
+            Err::<i32, _>(drpc::Error::from("boom"))
+        })
+        .unwrap();
+        s.register_fn("event", move |v: i32| {
+            let tx = tx.clone();
+            async move {
+                tx.send(v).unwrap();
+                Ok(())
+            }
+        })
+        .unwrap();
+        s.add_middleware(middleware::from_fn(auth));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                stream.set_nodelay(true).unwrap();
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        (addr, events)
+    }
+
+    fn token<'a>(
+        mut req: interceptor::Request<'a>,
+        next: interceptor::Next<'a>,
+    ) -> BoxFuture<'a, drpc::Result<bytes::Bytes>> {
+        Box::pin(async move {
+            req.metadata.insert("token", "open");
+            next.run(req).await
+        })
+    }
+
+    #[tokio::test]
+    async fn test_interceptor() {
+        let (addr, mut events) = serve().await;
+        let log = Arc::new(Mutex::new(vec![]));
+        let l = log.clone();
+        let c = Client::<BinCodec>::dial(&addr)
+            .await
+            .unwrap()
+            .add_interceptor(from_fn(move |req, next| {
+                let l = l.clone();
+                Box::pin(async move {
+                    l.lock()
+                        .unwrap()
+                        .push(format!("{} {}", req.method, req.body.len()));
+                    let r = next.run(req).await;
+                    l.lock().unwrap().push(format!("ok {}", r.is_ok()));
+                    r
+                })
+            }))
+            .add_interceptor(from_fn(token));
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        // the notifications pass the interceptors too
+        c.notify("event", 7).await.unwrap();
+        assert_eq!(events.recv().await.unwrap(), 7);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["add 4", "ok true", "event 4", "ok true"]
+        );
+        // without the token the server rejects the call
+        let c = Client::<BinCodec>::dial(&addr).await.unwrap();
+        let resp: drpc::Result<i32> = c.call("add", 1).await;
+        assert_eq!(resp.unwrap_err().to_string(), "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn test_interceptor_rewrite() {
+        let (addr, _events) = serve().await;
+        let c = Client::<BinCodec>::dial(&addr)
+            .await
+            .unwrap()
+            .add_interceptor(from_fn(token))
+            .add_interceptor(from_fn(|req, next| {
+                Box::pin(async move {
+                    let method = req.method;
+                    next.run(req)
+                        .await
+                        .map_err(|e| drpc::Error::from(format!("{} failed: {}", method, e)))
+                })
+            }));
+        let resp: drpc::Result<i32> = c.call("fail", 1).await;
+        assert_eq!(resp.unwrap_err().to_string(), "fail failed: boom");
+    }
+
+    #[tokio::test]
+    async fn test_interceptor_answers() {
+        let (addr, _events) = serve().await;
+        let c = Client::<BinCodec>::dial(&addr)
+            .await
+            .unwrap()
+            .add_interceptor(from_fn(|_, _| {
+                Box::pin(async move { Ok(bytes::Bytes::from(5i32.encode(&BinCodec {})?)) })
+            }));
+        // answered without reaching the server, that would reject it
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 5);
+    }
+
+    struct Registry {
+        addr: String,
+    }
+
+    impl RegistryCenter for Registry {
+        async fn pull(&self) -> HashMap<String, Vec<String>> {
+            HashMap::from([("svc".to_string(), vec![self.addr.clone()])])
+        }
+
+        async fn push(&self, _: String, _: String, _: Duration) -> drpc::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_manager_interceptor() {
+        let (addr, _events) = serve().await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let cfg = ManagerConfig::new()
+            .interceptor(from_fn(token))
+            .interceptor(from_fn(move |req, next| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Box::pin(next.run(req))
+            }));
+        let m = BalanceManger::<BinCodec, _>::new(cfg, Registry { addr });
+        m.pull().await.unwrap();
+        let resp: i32 = m.call("svc", "add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        let resp: i32 = m.call("svc", "add", 2).await.unwrap();
+        assert_eq!(resp, 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
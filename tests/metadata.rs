@@ -0,0 +1,43 @@
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use drpc::metadata::Metadata;
+
+    #[test]
+    fn test_metadata() {
+        let mut m = Metadata::new();
+        assert!(m.encode().is_empty());
+        m.insert("trace", "1");
+        m.insert("token", vec![0xff, 0x00]);
+        m.insert("trace", "2");
+        assert_eq!(m.len(), 2);
+        assert_eq!(m.get_str("trace"), Some("2"));
+        assert_eq!(m.get_str("token"), None);
+        assert_eq!(m.get("token").unwrap(), &[0xff, 0x00][..]);
+        assert_eq!(m.get("none"), None);
+
+        let data = m.encode();
+        let decoded = Metadata::decode(&data).unwrap();
+        assert_eq!(decoded, m);
+        assert_eq!(
+            decoded.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            vec!["trace", "token"]
+        );
+
+        let mut m = decoded;
+        assert_eq!(m.remove("trace"), Some(Bytes::from("2")));
+        assert_eq!(m.remove("trace"), None);
+        assert_eq!(m.len(), 1);
+    }
+
+    #[test]
+    fn test_metadata_decode_err() {
+        assert!(Metadata::decode(&Bytes::new()).unwrap().is_empty());
+        // the value is missing
+        assert!(Metadata::decode(&Bytes::from_static(&[1, b'a'])).is_err());
+        // the key is longer than the block
+        assert!(Metadata::decode(&Bytes::from_static(&[5, b'a'])).is_err());
+        // the key is not utf8
+        assert!(Metadata::decode(&Bytes::from_static(&[1, 0xff, 0])).is_err());
+    }
+}
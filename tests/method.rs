@@ -0,0 +1,51 @@
+#[cfg(test)]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::BinCodec;
+    use drpc::method::Method;
+    use drpc::server::Server;
+    use drpc::stub::method_id;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    const ADD: Method<(i32, i32), i32> = Method::new("add");
+    const HELLO: Method<String, String> = Method::new("hello");
+
+    async fn serve(s: Server<BinCodec>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_method() {
+        const ID: u64 = ADD.id();
+        assert_eq!(ID, method_id("add"));
+        let copy = ADD;
+        assert_eq!(copy.name, ADD.name);
+        assert_eq!(format!("{:?}", HELLO), "Method { name: \"hello\" }");
+    }
+
+    #[tokio::test]
+    async fn test_invoke() {
+        let mut s = Server::default();
+        s.register_method(&ADD, |(a, b)| async move { Ok(a + b) })
+            .unwrap();
+        s.register_method(&HELLO, |name| async move { Ok(format!("hello {}", name)) })
+            .unwrap();
+        let addr = serve(s).await;
+        let c = Client::<BinCodec>::dial(&addr).await.unwrap();
+        assert_eq!(c.invoke(&ADD, (1, 2)).await.unwrap(), 3);
+        assert_eq!(
+            c.invoke(&HELLO, "drpc".to_string()).await.unwrap(),
+            "hello drpc"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+#[cfg(test)]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::{BinCodec, Decode};
+    use drpc::frame::FrameCodec;
+    use drpc::handshake::Handshake;
+    use drpc::metadata::Metadata;
+    use drpc::middleware::{from_fn, Next, Request};
+    use drpc::server::Server;
+    use drpc::stream::ResponseStream;
+    use drpc::stub::ClientStub;
+    use futures::future::BoxFuture;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    fn server() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s.register_fn("secret", |_: ()| async move { Ok("42".to_string()) })
+            .unwrap();
+        s.register_stream_fn("count", |n: i32| futures::stream::iter((0..n).map(Ok)))
+            .unwrap();
+        s
+    }
+
+    async fn serve(s: Server<BinCodec>) -> Client<BinCodec> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                stream.set_nodelay(true).unwrap();
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        Client::<BinCodec>::dial(&addr).await.unwrap()
+    }
+
+    /// Rejects the calls without a `token` in their metadata.
+    fn auth<'a>(req: Request<'a>, next: Next<'a>) -> BoxFuture<'a, drpc::Result<Vec<u8>>> {
+        Box::pin(async move {
+            match req.metadata.get("token") {
+                Some(v) if &v[..] == b"open" => next.run().await,
+                _ => Err(drpc::Error::from("unauthorized")),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_middleware_order() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let mut s = server();
+        let l = log.clone();
+        s.add_middleware(from_fn(move |req, next| {
+            let l = l.clone();
+            Box::pin(async move {
+                l.lock().unwrap().push(format!("global {}", req.method));
+                let r = next.run().await;
+                l.lock()
+                    .unwrap()
+                    .push(format!("global {} done", req.method));
+                r
+            })
+        }));
+        let l = log.clone();
+        s.add_method_middleware(
+            "add",
+            from_fn(move |_, next| {
+                let l = l.clone();
+                Box::pin(async move {
+                    l.lock().unwrap().push("add".to_string());
+                    next.run().await
+                })
+            }),
+        );
+        let c = serve(s).await;
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        let resp: String = c.call("secret", ()).await.unwrap();
+        assert_eq!(resp, "42");
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "global add",
+                "add",
+                "global add done",
+                "global secret",
+                "global secret done"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_middleware_short_circuit() {
+        let mut s = server();
+        s.add_method_middleware("secret", from_fn(auth));
+        let (client, server_io) = tokio::io::duplex(1024);
+        tokio::spawn(async move { s.call(server_io).await });
+        let mut stream = Framed::new(client, FrameCodec::default());
+        let mut stub = ClientStub::new();
+        // metadata goes in v2 frames, agreed by the handshake
+        stub.handshake(&Handshake::none(), &mut stream)
+            .await
+            .unwrap();
+        let resp: drpc::Result<String> = stub.call("secret", (), &BinCodec {}, &mut stream).await;
+        assert_eq!(resp.unwrap_err().to_string(), "unauthorized");
+        let mut metadata = Metadata::new();
+        metadata.insert("token", b"open".to_vec());
+        let resp: String = stub
+            .call_with_metadata("secret", (), &BinCodec {}, false, metadata, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "42");
+        // the other methods are not checked
+        let resp: i32 = stub
+            .call("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_payload() {
+        let mut s = server();
+        s.add_method_middleware(
+            "add",
+            from_fn(|req, next| {
+                Box::pin(async move {
+                    let arg = i32::decode(&BinCodec {}, req.body)?;
+                    if arg < 0 {
+                        return Err(drpc::Error::from("arg must not be negative"));
+                    }
+                    next.run().await
+                })
+            }),
+        );
+        let c = serve(s).await;
+        let resp: drpc::Result<i32> = c.call("add", -1).await;
+        assert_eq!(resp.unwrap_err().to_string(), "arg must not be negative");
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        // the calls of a batch pass the middlewares too
+        let mut batch = c.batch();
+        batch.push("add", -1).unwrap();
+        batch.push("add", 2).unwrap();
+        let rsps = batch.send().await.unwrap();
+        assert!(rsps.get::<i32>(0).is_err());
+        assert_eq!(rsps.get::<i32>(1).unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_stream() {
+        let mut s = server();
+        s.add_method_middleware("count", from_fn(auth));
+        let c = serve(s).await;
+        let mut items: ResponseStream<i32> = c.call_stream("count", 3).await.unwrap();
+        let first = futures::StreamExt::next(&mut items).await.unwrap();
+        assert_eq!(first.unwrap_err().to_string(), "unauthorized");
+    }
+}
@@ -0,0 +1,103 @@
+#[cfg(test)]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::BinCodec;
+    use drpc::frame::FrameCodec;
+    use drpc::handshake::Handshake;
+    use drpc::server::Server;
+    use drpc::stub::ClientStub;
+    use futures::StreamExt;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_util::codec::Framed;
+
+    fn server(tx: mpsc::UnboundedSender<String>) -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_fn("log", move |line: String| {
+            let tx = tx.clone();
+            async move {
+                tx.send(line).unwrap();
+                Ok(())
+            }
+        })
+        .unwrap();
+        s.register_fn("fail", |_: i32| async move {
+            Err::<(), _>(drpc::Error::from("boom"))
+        })
+        .unwrap();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s
+    }
+
+    async fn serve(s: Arc<Server<BinCodec>>) -> Client<BinCodec> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                stream.set_nodelay(true).unwrap();
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        Client::<BinCodec>::dial(&addr).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        let (tx, mut lines) = mpsc::unbounded_channel();
+        let s = Arc::new(server(tx));
+        let c = serve(s.clone()).await;
+        c.notify("log", "started".to_string()).await.unwrap();
+        assert_eq!(lines.recv().await.unwrap(), "started");
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        assert_eq!(s.metrics().notifies.load(Ordering::SeqCst), 1);
+        assert_eq!(s.metrics().calls.load(Ordering::SeqCst), 2);
+        assert_eq!(s.metrics().errors.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_notify_error() {
+        let (tx, _lines) = mpsc::unbounded_channel();
+        let s = Arc::new(server(tx));
+        let c = serve(s.clone()).await;
+        // the failures are not answered, only counted
+        c.notify("fail", 1).await.unwrap();
+        c.notify("missing", 1).await.unwrap();
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(s.metrics().notifies.load(Ordering::SeqCst), 2);
+        assert_eq!(s.metrics().errors.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_notify_no_response() {
+        let (tx, mut lines) = mpsc::unbounded_channel();
+        let (client, server_io) = tokio::io::duplex(1024);
+        let s = Arc::new(server(tx));
+        tokio::spawn(async move { s.call(server_io).await });
+        let mut stream = Framed::new(client, FrameCodec::default());
+        let mut stub = ClientStub::new();
+        // a v1 frame has no room for the oneway flag
+        let e = stub
+            .notify("log", "a".to_string(), &BinCodec {}, &mut stream)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("v2"));
+        stub.handshake(&Handshake::none(), &mut stream)
+            .await
+            .unwrap();
+        stub.notify("log", "a".to_string(), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(lines.recv().await.unwrap(), "a");
+        // nothing is written back
+        let read = tokio::time::timeout(Duration::from_millis(50), stream.next()).await;
+        assert!(read.is_err());
+    }
+}
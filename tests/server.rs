@@ -0,0 +1,412 @@
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use drpc::batch;
+    #[cfg(feature = "fingerprint")]
+    use drpc::codec::Decode;
+    use drpc::codec::{AnyCodec, BinCodec, Format, JsonCodec, CODEC_BIN, CODEC_JSON};
+    #[cfg(feature = "fingerprint")]
+    use drpc::fingerprint;
+    use drpc::frame::{Frame, FrameCodec};
+    use drpc::handshake::Handshake;
+    #[cfg(feature = "fingerprint")]
+    use drpc::metadata::{self, Metadata};
+    use drpc::reflect::{self, ServiceInfo};
+    use drpc::server::{BorrowHandler, Server};
+    use drpc::stub::{method_id, ClientStub};
+    use futures::future::BoxFuture;
+    use futures::{SinkExt, StreamExt};
+    use std::sync::Arc;
+    use tokio::io::DuplexStream;
+    use tokio_util::codec::Framed;
+
+    async fn serve<C: Format + 'static>(s: Server<C>) -> Framed<DuplexStream, FrameCodec> {
+        let (client, server) = tokio::io::duplex(1024);
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            s.call(server).await;
+        });
+        Framed::new(client, FrameCodec::default())
+    }
+
+    /// A stub speaking v2 frames, the ones carrying method ids and metadata
+    async fn v2_stub(stream: &mut Framed<DuplexStream, FrameCodec>) -> ClientStub {
+        let mut stub = ClientStub::new();
+        stub.handshake(&Handshake::none(), stream).await.unwrap();
+        stub
+    }
+
+    fn server() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s.register_fn("名字\n", |arg: i32| async move { Ok(arg + 2) })
+            .unwrap();
+        s
+    }
+
+    #[test]
+    fn test_method_id() {
+        const ID: u64 = method_id("add");
+        assert_eq!(ID, method_id("add"));
+        assert_ne!(method_id("add"), method_id("add2"));
+        assert_eq!(server().ids.get(&ID).unwrap().0, "add");
+    }
+
+    #[tokio::test]
+    async fn test_call_by_id() {
+        let mut stream = serve(server()).await;
+        let stub = v2_stub(&mut stream).await;
+        let resp: i32 = stub
+            .call("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+        let resp: i32 = stub
+            .call("名字\n", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 3);
+        let resp: drpc::Result<i32> = stub.call("none", 1, &BinCodec {}, &mut stream).await;
+        assert_eq!(
+            resp.unwrap_err().to_string(),
+            format!("method id={} not find!", method_id("none"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_by_name() {
+        let mut stream = serve(server()).await;
+        let mut stub = ClientStub::new();
+        stub.method_id = false;
+        let resp: i32 = stub
+            .call("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+        let resp: drpc::Result<i32> = stub.call("none", 1, &BinCodec {}, &mut stream).await;
+        assert_eq!(resp.unwrap_err().to_string(), "method='none' not find!");
+    }
+
+    #[tokio::test]
+    async fn test_call_by_bad_name() {
+        let mut stream = serve(server()).await;
+        let mut req = Frame::new();
+        req.id = 1;
+        req.data = Bytes::from_static(&[0xff, 0xfe, b'\n', 1]);
+        stream.send(req).await.unwrap();
+        let rsp = stream.next().await.unwrap().unwrap();
+        assert_eq!(rsp.ok, 0);
+        assert_eq!(rsp.data, "method is not utf8!".as_bytes());
+    }
+
+    struct Greet {}
+
+    impl BorrowHandler<BinCodec> for Greet {
+        type Req<'a> = &'a str;
+        type Resp = String;
+
+        fn handle<'a>(&'a self, req: &'a str) -> BoxFuture<'a, drpc::Result<String>> {
+            Box::pin(async move { Ok(format!("hello {}", req)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_call_borrowed() {
+        let mut s = server();
+        s.register("greet", Greet {}).await.unwrap();
+        let mut stream = serve(s).await;
+        let stub = ClientStub::new();
+        let resp: String = stub
+            .call("greet", "joe", &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, "hello joe");
+    }
+
+    #[cfg(feature = "prost")]
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Hello {
+        #[prost(string, tag = "1")]
+        name: String,
+    }
+
+    #[cfg(feature = "prost")]
+    #[tokio::test]
+    async fn test_call_prost() {
+        use drpc::codec::ProstCodec;
+        let mut s = Server::<ProstCodec>::new();
+        s.register_fn("hello", |req: Hello| async move {
+            Ok(Hello {
+                name: format!("hello {}", req.name),
+            })
+        })
+        .unwrap();
+        let mut stream = serve(s).await;
+        let stub = ClientStub::new();
+        let resp: Hello = stub
+            .call(
+                "hello",
+                Hello {
+                    name: "joe".to_string(),
+                },
+                &ProstCodec {},
+                &mut stream,
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.name, "hello joe");
+    }
+
+    fn any_server() -> Server<AnyCodec> {
+        let mut s = Server::<AnyCodec>::new();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s
+    }
+
+    #[tokio::test]
+    async fn test_codec_by_handshake() {
+        let mut stream = serve(any_server()).await;
+        let mut stub = ClientStub::new();
+        let agreed = stub
+            .handshake(&Handshake::none().codec(Some(CODEC_JSON)), &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(agreed.codec, Some(CODEC_JSON));
+        let resp: i32 = stub
+            .call("add", 1, &JsonCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+        // one call in another codec
+        let resp: i32 = stub
+            .call("add", 2, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 3);
+    }
+
+    #[tokio::test]
+    async fn test_codec_by_call() {
+        let mut stream = serve(any_server()).await;
+        let stub = v2_stub(&mut stream).await;
+        let resp: i32 = stub
+            .call("add", 1, &JsonCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+        let resp: i32 = stub
+            .call("add", 1, &AnyCodec::new(CODEC_BIN), &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_codec_not_support() {
+        let mut stream = serve(server()).await;
+        let mut stub = ClientStub::new();
+        let agreed = stub
+            .handshake(&Handshake::none().codec(Some(CODEC_JSON)), &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(agreed.codec, None);
+        let resp: drpc::Result<i32> = stub.call("add", 1, &JsonCodec {}, &mut stream).await;
+        assert_eq!(resp.unwrap_err().to_string(), "codec id=2 not support!");
+    }
+
+    #[cfg(feature = "fingerprint")]
+    fn checked_server(check: bool) -> Server<BinCodec> {
+        let mut s = Server::default().set_fingerprint(check);
+        s.register_fn_checked("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s
+    }
+
+    #[cfg(feature = "fingerprint")]
+    fn fingerprint_of<Req: Decode<BinCodec>, Resp: Decode<BinCodec>>() -> Metadata {
+        let mut m = Metadata::new();
+        let v = fingerprint::method::<BinCodec, Req, Resp>().unwrap();
+        m.insert(metadata::FINGERPRINT, v.to_be_bytes().to_vec());
+        m
+    }
+
+    #[cfg(feature = "fingerprint")]
+    #[tokio::test]
+    async fn test_call_fingerprint() {
+        let mut stream = serve(checked_server(true)).await;
+        let stub = v2_stub(&mut stream).await;
+        let resp: i32 = stub
+            .call_with_metadata(
+                "add",
+                1,
+                &BinCodec {},
+                false,
+                fingerprint_of::<i32, i32>(),
+                &mut stream,
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+        // a caller without fingerprint is not checked
+        let resp: i32 = stub
+            .call("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+        let resp: drpc::Result<i32> = stub
+            .call_with_metadata(
+                "add",
+                1,
+                &BinCodec {},
+                false,
+                fingerprint_of::<i32, u32>(),
+                &mut stream,
+            )
+            .await;
+        let e = resp.unwrap_err().to_string();
+        assert!(
+            e.starts_with("incompatible schema: method 'add' fingerprint="),
+            "{}",
+            e
+        );
+    }
+
+    #[cfg(feature = "fingerprint")]
+    #[tokio::test]
+    async fn test_call_fingerprint_off() {
+        let mut stream = serve(checked_server(false)).await;
+        let stub = v2_stub(&mut stream).await;
+        let resp: i32 = stub
+            .call_with_metadata(
+                "add",
+                1,
+                &BinCodec {},
+                false,
+                fingerprint_of::<i32, u32>(),
+                &mut stream,
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_reflect() {
+        let mut s = server().set_reflection(true);
+        s.describe("add", "add one");
+        let mut stream = serve(s).await;
+        let stub = ClientStub::new();
+        let info: ServiceInfo = stub
+            .call(reflect::METHOD, (), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        let names: Vec<&str> = info.methods.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["add", reflect::METHOD, "名字\n"]);
+        let add = info.get("add").unwrap();
+        assert_eq!(add.description.as_deref(), Some("add one"));
+        assert_eq!(add.request, None);
+        assert_eq!(info.get("名字\n").unwrap().description, None);
+    }
+
+    #[tokio::test]
+    async fn test_reflect_off() {
+        let s = server().set_reflection(true).set_reflection(false);
+        assert!(s.methods.get(reflect::METHOD).is_none());
+        let mut stream = serve(s).await;
+        let stub = ClientStub::new();
+        let resp: drpc::Result<ServiceInfo> = stub
+            .call(reflect::METHOD, (), &BinCodec {}, &mut stream)
+            .await;
+        assert!(resp.is_err());
+    }
+
+    #[cfg(feature = "schema")]
+    #[tokio::test]
+    async fn test_reflect_schema() {
+        #[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+        struct User {
+            name: String,
+            age: i32,
+        }
+        let mut s = Server::<JsonCodec>::new().set_reflection(true);
+        s.register_fn(
+            "get",
+            |name: String| async move { Ok(User { name, age: 1 }) },
+        )
+        .unwrap();
+        s.register_schema::<String, User>("get");
+        const ADD: drpc::method::Method<(i32, i32), i32> = drpc::method::Method::new("add");
+        s.register_method(&ADD, |(a, b)| async move { Ok(a + b) })
+            .unwrap();
+        let mut stream = serve(s).await;
+        let stub = ClientStub::new();
+        let info: ServiceInfo = stub
+            .call(reflect::METHOD, (), &JsonCodec {}, &mut stream)
+            .await
+            .unwrap();
+        let get = info.get("get").unwrap();
+        let req: serde_json::Value = serde_json::from_str(get.request.as_ref().unwrap()).unwrap();
+        assert_eq!(req["type"], "string");
+        let resp: serde_json::Value = serde_json::from_str(get.response.as_ref().unwrap()).unwrap();
+        assert_eq!(resp["title"], "User");
+        assert_eq!(resp["properties"]["age"]["type"], "integer");
+        assert_eq!(resp["required"], serde_json::json!(["name", "age"]));
+        let add = info.get("add").unwrap();
+        let req: serde_json::Value = serde_json::from_str(add.request.as_ref().unwrap()).unwrap();
+        assert_eq!(req["type"], "array");
+        let resp: serde_json::Value = serde_json::from_str(add.response.as_ref().unwrap()).unwrap();
+        assert_eq!(resp["type"], "integer");
+    }
+
+    #[test]
+    fn test_method_id_64() {
+        let mut s = Server::default();
+        // FNV-1a 32 bits collisions, with each other and with `batch::METHOD`
+        s.register_fn("costarring", |arg: i32| async move { Ok(arg) })
+            .unwrap();
+        s.register_fn("liquid", |arg: i32| async move { Ok(arg) })
+            .unwrap();
+        s.register_fn("ojajnjk", |arg: i32| async move { Ok(arg) })
+            .unwrap();
+        assert_eq!(s.ids.get(&method_id("liquid")).unwrap().0, "liquid");
+    }
+
+    #[test]
+    fn test_method_id_collision() {
+        let mut s = Server::default();
+        s.register_fn("a", |arg: i32| async move { Ok(arg) })
+            .unwrap();
+        // as if "b" hashed to the id of "a"
+        let a = s.ids.get(&method_id("a")).unwrap().1.clone();
+        s.ids.insert_mut(method_id("b"), ("a".to_string(), a));
+        let r = s.register_fn("b", |arg: i32| async move { Ok(arg) });
+        assert_eq!(
+            r.unwrap_err().to_string(),
+            format!(
+                "method 'a' and 'b' have the same method id={}!",
+                method_id("b")
+            )
+        );
+        assert!(s.handles.get("b").is_none());
+        // registering a name again replaces its handle
+        s.register_fn("a", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_method_id_reserved() {
+        let mut s = Server::default();
+        for name in [batch::METHOD, reflect::METHOD] {
+            let r = s.register_fn(name, |arg: i32| async move { Ok(arg) });
+            assert!(r
+                .unwrap_err()
+                .to_string()
+                .ends_with(&format!("of the built-in '{}'!", name)));
+        }
+        // the reflection registers its own method
+        let s = s.set_reflection(true);
+        assert!(s.handles.get(reflect::METHOD).is_some());
+    }
+}
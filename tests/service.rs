@@ -0,0 +1,145 @@
+#[cfg(feature = "macros")]
+#[cfg(test)]
+mod test {
+    use drpc::client::Call;
+    use drpc::codec::{BinCodec, Decode, Encode};
+    use drpc::frame::FrameCodec;
+    use drpc::server::Server;
+    use drpc::stub::ClientStub;
+    use drpc::Result;
+    use serde::{Deserialize, Serialize};
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Arc;
+    use tokio::io::DuplexStream;
+    use tokio::sync::Mutex;
+    use tokio_util::codec::Framed;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub struct User {
+        name: String,
+        age: i32,
+    }
+
+    #[drpc::service]
+    pub trait UserService {
+        async fn add(&self, a: i32, b: i32) -> Result<i32>;
+        async fn hello(&self, user: User) -> Result<String>;
+        async fn count(&self) -> drpc::Result<i32>;
+        async fn twice(&self, a: i32) -> Result<i32> {
+            Ok(a * 2)
+        }
+    }
+
+    #[derive(Default)]
+    struct UserServiceImpl {
+        count: AtomicI32,
+    }
+
+    impl UserService for UserServiceImpl {
+        async fn add(&self, a: i32, b: i32) -> Result<i32> {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            Ok(a + b)
+        }
+
+        async fn hello(&self, user: User) -> Result<String> {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            Ok(format!("hello {}, {}", user.name, user.age))
+        }
+
+        async fn count(&self) -> Result<i32> {
+            Ok(self.count.load(Ordering::SeqCst))
+        }
+    }
+
+    /// A `Call` over an in memory stream.
+    struct Duplex {
+        stub: ClientStub,
+        stream: Mutex<Framed<DuplexStream, FrameCodec>>,
+    }
+
+    impl Call for Duplex {
+        type Codec = BinCodec;
+
+        async fn call<Arg, Resp>(&self, func: &str, arg: Arg) -> Result<Resp>
+        where
+            Arg: Encode<BinCodec> + Send,
+            Resp: Decode<BinCodec>,
+        {
+            let mut stream = self.stream.lock().await;
+            self.stub.call(func, arg, &BinCodec {}, &mut *stream).await
+        }
+    }
+
+    fn client() -> UserServiceClient<Duplex> {
+        let mut s = Server::default();
+        register_user_service(&mut s, UserServiceImpl::default()).unwrap();
+        let (client, server) = tokio::io::duplex(1024);
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            s.call(server).await;
+        });
+        UserServiceClient::new(Duplex {
+            stub: ClientStub::new(),
+            stream: Mutex::new(Framed::new(client, FrameCodec::default())),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_service() {
+        let c = client();
+        assert_eq!(c.add(1, 2).await.unwrap(), 3);
+        let user = User {
+            name: "drpc".to_string(),
+            age: 1,
+        };
+        assert_eq!(c.hello(user).await.unwrap(), "hello drpc, 1");
+        assert_eq!(c.twice(2).await.unwrap(), 4);
+        assert_eq!(c.count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_service_method_name() {
+        let c = client();
+        let resp: i32 = c.inner.call("UserService.add", (1, 2)).await.unwrap();
+        assert_eq!(resp, 3);
+        let resp: Result<i32> = c.inner.call("add", (1, 2)).await;
+        assert!(resp.is_err());
+    }
+
+    #[drpc::service]
+    pub trait Keywords {
+        async fn r#type(&self, r#in: i32) -> Result<i32>;
+    }
+
+    impl Keywords for UserServiceImpl {
+        async fn r#type(&self, r#in: i32) -> Result<i32> {
+            Ok(r#in)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_service_raw_name() {
+        let mut s = Server::default();
+        register_keywords(&mut s, UserServiceImpl::default()).unwrap();
+        let (client, server) = tokio::io::duplex(1024);
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            s.call(server).await;
+        });
+        let c = KeywordsClient::new(Duplex {
+            stub: ClientStub::new(),
+            stream: Mutex::new(Framed::new(client, FrameCodec::default())),
+        });
+        assert_eq!(c.r#type(1).await.unwrap(), 1);
+        // the name of the method has no `r#`, as in a `.drpc` file
+        let resp: i32 = c.inner.call("Keywords.type", 2).await.unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_service_spawn() {
+        let c = Arc::new(client());
+        let task = tokio::spawn(async move { c.add(1, 1).await });
+        assert_eq!(task.await.unwrap().unwrap(), 2);
+    }
+}
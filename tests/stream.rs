@@ -0,0 +1,237 @@
+#[cfg(test)]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::BinCodec;
+    use drpc::frame::FrameCodec;
+    use drpc::handshake::Handshake;
+    use drpc::server::Server;
+    use drpc::stream::ResponseStream;
+    use drpc::stub::ClientStub;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::DuplexStream;
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    async fn serve(s: Server<BinCodec>) -> Framed<DuplexStream, FrameCodec> {
+        let (client, server) = tokio::io::duplex(1024);
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            s.call(server).await;
+        });
+        Framed::new(client, FrameCodec::default())
+    }
+
+    /// A stub speaking v2 frames, the ones carrying streams
+    async fn v2_stub(stream: &mut Framed<DuplexStream, FrameCodec>) -> ClientStub {
+        let mut stub = ClientStub::new();
+        stub.handshake(&Handshake::none(), stream).await.unwrap();
+        stub
+    }
+
+    fn server() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_stream_fn("count", |n: i32| futures::stream::iter((0..n).map(Ok)))
+            .unwrap();
+        s.register_stream_fn("fail", |_: ()| {
+            futures::stream::iter(vec![Ok(1), Err(drpc::Error::from("boom")), Ok(2)])
+        })
+        .unwrap();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s
+    }
+
+    /// Sets the flag when the stream holding it is dropped.
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let mut stream = serve(server()).await;
+        let stub = v2_stub(&mut stream).await;
+        let items: ResponseStream<i32> = stub
+            .call_stream("count", 40, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        let items: Vec<i32> = items.map(|v| v.unwrap()).collect().await;
+        assert_eq!(items, (0..40).collect::<Vec<i32>>());
+        // the connection goes on after the stream
+        let resp: i32 = stub
+            .call("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_stream_error() {
+        let mut stream = serve(server()).await;
+        let stub = v2_stub(&mut stream).await;
+        let items: ResponseStream<i32> = stub
+            .call_stream("fail", (), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        let items: Vec<drpc::Result<i32>> = items.collect().await;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap(), &1);
+        assert_eq!(items[1].as_ref().unwrap_err().to_string(), "boom");
+    }
+
+    #[tokio::test]
+    async fn test_stream_unary() {
+        let mut stream = serve(server()).await;
+        let stub = v2_stub(&mut stream).await;
+        // a unary method answers one item
+        let items: ResponseStream<i32> = stub
+            .call_stream("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        let items: Vec<i32> = items.map(|v| v.unwrap()).collect().await;
+        assert_eq!(items, vec![2]);
+        let resp: drpc::Result<i32> = stub.call("count", 1, &BinCodec {}, &mut stream).await;
+        assert_eq!(
+            resp.unwrap_err().to_string(),
+            "method answers a stream, call it with call_stream!"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_window() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let mut s = Server::default();
+        let counter = sent.clone();
+        s.register_stream_fn("numbers", move |_: ()| {
+            let counter = counter.clone();
+            futures::stream::iter(0..).map(move |v: i32| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(v)
+            })
+        })
+        .unwrap();
+        let mut stream = serve(s).await;
+        let mut stub = v2_stub(&mut stream).await;
+        stub.window = 4;
+        let mut items: ResponseStream<i32> = stub
+            .call_stream("numbers", (), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(sent.load(Ordering::SeqCst), 4);
+        // reading half of the window grants 2 more
+        assert_eq!(items.next().await.unwrap().unwrap(), 0);
+        assert_eq!(items.next().await.unwrap().unwrap(), 1);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(sent.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_stream_cancel() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let mut s = server();
+        forever(&mut s, &dropped);
+        let mut stream = serve(s).await;
+        let stub = v2_stub(&mut stream).await;
+        let mut items: ResponseStream<i32> = stub
+            .call_stream("forever", (), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(items.next().await.unwrap().unwrap(), 0);
+        drop(items);
+        assert!(!dropped.load(Ordering::SeqCst));
+        // the cancel goes with the next call, the items left are discarded
+        let resp: i32 = stub
+            .call("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    fn forever(s: &mut Server<BinCodec>, dropped: &Arc<AtomicBool>) {
+        let flag = dropped.clone();
+        s.register_stream_fn("forever", move |_: ()| {
+            let flag = DropFlag(flag.clone());
+            futures::stream::iter(0..).map(move |v: i32| {
+                let _ = &flag;
+                Ok(v)
+            })
+        })
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stream_disconnect() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let mut s = server();
+        forever(&mut s, &dropped);
+        let (client, server_io) = tokio::io::duplex(1024);
+        let conn = tokio::spawn(async move { s.call(server_io).await });
+        let mut stream = Framed::new(client, FrameCodec::default());
+        let mut stub = v2_stub(&mut stream).await;
+        stub.window = 1;
+        let mut items: ResponseStream<i32> = stub
+            .call_stream("forever", (), &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(items.next().await.unwrap().unwrap(), 0);
+        // a paused stream and no cancel: the disconnect alone ends it
+        drop(items);
+        drop(stream);
+        tokio::time::timeout(Duration::from_secs(1), conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_client_stream_cancel() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let mut s = server();
+        forever(&mut s, &dropped);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        let c = Client::<BinCodec>::dial(&addr).await.unwrap().set_window(1);
+        let mut items: ResponseStream<i32> = c.call_stream("forever", ()).await.unwrap();
+        assert_eq!(items.next().await.unwrap().unwrap(), 0);
+        // a shared connection cancels at once, without a next call
+        drop(items);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_client_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let s = Arc::new(server());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        let c = Client::<BinCodec>::dial(&addr).await.unwrap().set_window(2);
+        let items: ResponseStream<i32> = c.call_stream("count", 5).await.unwrap();
+        let items: Vec<i32> = items.map(|v| v.unwrap()).collect().await;
+        assert_eq!(items, vec![0, 1, 2, 3, 4]);
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+    }
+}
@@ -0,0 +1,208 @@
+#[cfg(all(test, feature = "tower"))]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::{AnyCodec, BinCodec, Decode, JsonCodec};
+    use drpc::context::{Context, State};
+    use drpc::server::Server;
+    use drpc::tower::Request;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context as TaskContext, Poll};
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+    use tower::layer::layer_fn;
+    use tower::limit::ConcurrencyLimitLayer;
+    use tower::timeout::TimeoutLayer;
+    use tower::{ServiceBuilder, ServiceExt};
+
+    fn server() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s.register_fn("slow", |arg: u64| async move {
+            tokio::time::sleep(Duration::from_millis(arg)).await;
+            Ok(arg)
+        })
+        .unwrap();
+        s
+    }
+
+    async fn serve(s: Arc<Server<BinCodec>>) -> Client<BinCodec> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                stream.set_nodelay(true).unwrap();
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        Client::<BinCodec>::dial(&addr).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_service() {
+        let c = Arc::new(serve(Arc::new(server())).await);
+        let svc = ServiceBuilder::new()
+            .timeout(Duration::from_millis(100))
+            .service(c.clone());
+        let rsp = svc
+            .clone()
+            .oneshot(Request::encode("add", 1, &c.codec).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(i32::decode(&c.codec, &rsp).unwrap(), 2);
+        let r = svc
+            .oneshot(Request::encode("slow", 500u64, &c.codec).unwrap())
+            .await;
+        assert_eq!(r.unwrap_err().to_string(), "request timed out");
+        // the errors of the server come back as they are
+        let r = c
+            .clone()
+            .oneshot(Request::encode("missing", 1, &c.codec).unwrap())
+            .await;
+        assert!(r.unwrap_err().to_string().ends_with("not find!"));
+    }
+
+    #[tokio::test]
+    async fn test_server_layer() {
+        let s = Arc::new(server().set_layer(TimeoutLayer::new(Duration::from_millis(100))));
+        let c = serve(s.clone()).await;
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+        let resp: drpc::Result<u64> = c.call("slow", 500u64).await;
+        assert_eq!(resp.unwrap_err().to_string(), "request timed out");
+        assert_eq!(s.metrics().errors.load(Ordering::SeqCst), 1);
+        // the calls of a batch pass the layer too
+        let mut batch = c.batch();
+        batch.push("slow", 500u64).unwrap();
+        batch.push("add", 2).unwrap();
+        let rsps = batch.send().await.unwrap();
+        assert!(rsps.get::<u64>(0).is_err());
+        assert_eq!(rsps.get::<i32>(1).unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_server_layer_limit() {
+        let s = server().set_layer(ConcurrencyLimitLayer::new(1));
+        let c = serve(Arc::new(s)).await;
+        let start = Instant::now();
+        let (a, b) = tokio::join!(
+            c.call::<u64, u64>("slow", 50),
+            c.call::<u64, u64>("slow", 50)
+        );
+        assert_eq!(a.unwrap(), 50);
+        assert_eq!(b.unwrap(), 50);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_server_layer_context() {
+        let mut s = server().set_state(7i32);
+        s.register_fn(
+            "state",
+            |ctx: Context, State(v): State<i32>, req: i32| async move {
+                Ok(format!("{} {}", ctx.method, v + req))
+            },
+        )
+        .unwrap();
+        let s = s.set_layer(TimeoutLayer::new(Duration::from_millis(100)));
+        let c = serve(Arc::new(s)).await;
+        let resp: String = c.call("state", 1).await.unwrap();
+        assert_eq!(resp, "state 8");
+    }
+
+    #[tokio::test]
+    async fn test_server_layer_codec() {
+        let mut s = Server::<AnyCodec>::new();
+        s.register_fn("hello", |name: String| async move {
+            Ok(format!("hello {}", name))
+        })
+        .unwrap();
+        let s = s.set_layer(TimeoutLayer::new(Duration::from_millis(100)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        // json is agreed on in the handshake, the calls name no codec
+        let c = Client::<JsonCodec>::dial(&addr).await.unwrap();
+        assert_eq!(c.stub.codec, Some(drpc::codec::CODEC_JSON));
+        let resp: String = c.call("hello", "joe").await.unwrap();
+        assert_eq!(resp, "hello joe");
+    }
+
+    /// Not ready for a while on the first call, ready at once for the others.
+    struct SlowReady<S> {
+        inner: S,
+        calls: Arc<AtomicUsize>,
+        delay: Option<Pin<Box<tokio::time::Sleep>>>,
+        polled: bool,
+    }
+
+    impl<S: Clone> Clone for SlowReady<S> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+                calls: self.calls.clone(),
+                delay: None,
+                polled: false,
+            }
+        }
+    }
+
+    impl<S: tower::Service<Request>> tower::Service<Request> for SlowReady<S> {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = S::Future;
+
+        fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), S::Error>> {
+            if !self.polled {
+                self.polled = true;
+                if self.calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    let delay = tokio::time::sleep(Duration::from_millis(300));
+                    self.delay = Some(Box::pin(delay));
+                }
+            }
+            if let Some(delay) = &mut self.delay {
+                if delay.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, req: Request) -> S::Future {
+            self.inner.call(req)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_layer_not_ready() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let s = server().set_layer(layer_fn(move |inner| SlowReady {
+            inner,
+            calls: calls.clone(),
+            delay: None,
+            polled: false,
+        }));
+        let c = Arc::new(serve(Arc::new(s)).await);
+        let first = {
+            let c = c.clone();
+            tokio::spawn(async move { c.call::<i32, i32>("add", 1).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // the call not ready holds back no other call
+        let start = Instant::now();
+        let resp: i32 = c.call("add", 2).await.unwrap();
+        assert_eq!(resp, 3);
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(first.await.unwrap().unwrap(), 2);
+    }
+}
@@ -0,0 +1,247 @@
+#[cfg(test)]
+mod test {
+    use drpc::client::Client;
+    use drpc::codec::BinCodec;
+    use drpc::frame::{Frame, FrameCodec};
+    use drpc::handshake::Handshake;
+    use drpc::server::Server;
+    use drpc::stream::{Control, RequestStream};
+    use drpc::stub::{ClientStub, Transport};
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::DuplexStream;
+    use tokio::net::TcpListener;
+    use tokio::sync::Notify;
+    use tokio_util::codec::Framed;
+
+    async fn serve(s: Server<BinCodec>) -> Framed<DuplexStream, FrameCodec> {
+        let (client, server) = tokio::io::duplex(1024);
+        let s = Arc::new(s);
+        tokio::spawn(async move {
+            s.call(server).await;
+        });
+        Framed::new(client, FrameCodec::default())
+    }
+
+    /// A stub speaking v2 frames, the ones carrying streams
+    async fn v2_stub(stream: &mut Framed<DuplexStream, FrameCodec>) -> ClientStub {
+        let mut stub = ClientStub::new();
+        stub.handshake(&Handshake::none(), stream).await.unwrap();
+        stub
+    }
+
+    fn server() -> Server<BinCodec> {
+        let mut s = Server::default();
+        s.register_upload_fn("sum", |reqs: RequestStream<i64>| async move {
+            let mut sum = 0;
+            let mut reqs = reqs;
+            while let Some(v) = reqs.next().await {
+                sum += v?;
+            }
+            Ok(sum)
+        })
+        .unwrap();
+        s.register_upload_fn("first", |mut reqs: RequestStream<i64>| async move {
+            match reqs.next().await {
+                Some(v) => Err(drpc::Error::from(format!("stop at {}", v?))),
+                None => Ok(0),
+            }
+        })
+        .unwrap();
+        s.register_fn("add", |arg: i32| async move { Ok(arg + 1) })
+            .unwrap();
+        s
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let mut stream = serve(server()).await;
+        let stub = v2_stub(&mut stream).await;
+        let sum: i64 = stub
+            .call_upload(
+                "sum",
+                futures::stream::iter(1..=1000i64),
+                &BinCodec {},
+                &mut stream,
+            )
+            .await
+            .unwrap();
+        assert_eq!(sum, 500500);
+        // the connection goes on after the upload
+        let resp: i32 = stub
+            .call("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_upload_answered_early() {
+        let mut stream = serve(server()).await;
+        let stub = v2_stub(&mut stream).await;
+        let resp: drpc::Result<i64> = stub
+            .call_upload(
+                "first",
+                futures::stream::iter(1i64..),
+                &BinCodec {},
+                &mut stream,
+            )
+            .await;
+        assert_eq!(resp.unwrap_err().to_string(), "stop at 1");
+        let resp: i32 = stub
+            .call("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_upload_mismatch() {
+        let mut stream = serve(server()).await;
+        let stub = v2_stub(&mut stream).await;
+        let resp: drpc::Result<i64> = stub.call("sum", 1i64, &BinCodec {}, &mut stream).await;
+        assert_eq!(
+            resp.unwrap_err().to_string(),
+            "method reads a stream of requests, call it with call_upload!"
+        );
+        let resp: drpc::Result<i32> = stub
+            .call_upload(
+                "add",
+                futures::stream::iter(0..3),
+                &BinCodec {},
+                &mut stream,
+            )
+            .await;
+        assert_eq!(
+            resp.unwrap_err().to_string(),
+            "method does not read a stream of requests!"
+        );
+        let resp: i32 = stub
+            .call("add", 1, &BinCodec {}, &mut stream)
+            .await
+            .unwrap();
+        assert_eq!(resp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_upload_window() {
+        let start = Arc::new(Notify::new());
+        let mut s = Server::default().set_window(4);
+        let notified = start.clone();
+        s.register_upload_fn("sum", move |mut reqs: RequestStream<i64>| {
+            let notified = notified.clone();
+            async move {
+                notified.notified().await;
+                let mut sum = 0;
+                while let Some(v) = reqs.next().await {
+                    sum += v?;
+                }
+                Ok(sum)
+            }
+        })
+        .unwrap();
+        let mut stream = serve(s).await;
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counter = sent.clone();
+        let reqs = futures::stream::iter(1..=100i64).map(move |v| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            v
+        });
+        let stub = v2_stub(&mut stream).await;
+        let call = tokio::spawn(async move {
+            let sum: drpc::Result<i64> = stub
+                .call_upload("sum", reqs, &BinCodec {}, &mut stream)
+                .await;
+            sum
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // the handler reads nothing, the caller waits after the first window
+        assert_eq!(sent.load(Ordering::SeqCst), 4);
+        start.notify_one();
+        assert_eq!(call.await.unwrap().unwrap(), 5050);
+        assert_eq!(sent.load(Ordering::SeqCst), 100);
+    }
+
+    /// A caller taking ten times the window the server grants.
+    struct Greedy(Framed<DuplexStream, FrameCodec>);
+
+    impl Transport for Greedy {
+        async fn send(&mut self, frame: Frame) -> std::io::Result<()> {
+            self.0.send(frame).await
+        }
+
+        async fn recv(&mut self) -> Option<std::io::Result<Frame>> {
+            let frame = self.0.recv().await;
+            match &frame {
+                Some(Ok(f)) => match Control::decode(f) {
+                    Some(Control::Window(n)) => Some(Ok(Control::Window(n * 10).into_frame(f.id))),
+                    _ => frame,
+                },
+                _ => frame,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_over_window() {
+        let start = Arc::new(Notify::new());
+        let mut s = server().set_window(2);
+        let notified = start.clone();
+        s.register_upload_fn("wait", move |mut reqs: RequestStream<i64>| {
+            let notified = notified.clone();
+            async move {
+                notified.notified().await;
+                let mut sum = 0;
+                while let Some(v) = reqs.next().await {
+                    sum += v?;
+                }
+                Ok(sum)
+            }
+        })
+        .unwrap();
+        let mut framed = serve(s).await;
+        let stub = v2_stub(&mut framed).await;
+        let mut stream = Greedy(framed);
+        let call = tokio::spawn(async move {
+            let sum: drpc::Result<i64> = stub
+                .call_upload(
+                    "wait",
+                    futures::stream::iter(1..=20i64),
+                    &BinCodec {},
+                    &mut stream,
+                )
+                .await;
+            sum
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        start.notify_one();
+        // the requests read are not taken for the whole upload
+        assert_eq!(
+            call.await.unwrap().unwrap_err().to_string(),
+            "upload window exceeded!"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_upload() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let s = Arc::new(server().set_window(2));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let s = s.clone();
+                tokio::spawn(async move { s.call(stream).await });
+            }
+        });
+        let c = Client::<BinCodec>::dial(&addr).await.unwrap();
+        let sum: i64 = c
+            .call_upload("sum", futures::stream::iter(vec![1i64, 2, 3]))
+            .await
+            .unwrap();
+        assert_eq!(sum, 6);
+        let resp: i32 = c.call("add", 1).await.unwrap();
+        assert_eq!(resp, 2);
+    }
+}